@@ -0,0 +1,170 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Measure the list and extract throughput on synthetic cpio archives.
+//!
+//! The archives are generated on the fly: many small files, few huge files,
+//! and a deep directory tree.
+
+use std::fs::{create_dir_all, remove_dir_all, File};
+use std::io::sink;
+use std::path::{Path, PathBuf};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use threecpio::{
+    extract_cpio_archive, list_cpio_content, ExtractOptions, Header, ListOptions,
+    DEFAULT_IO_BUFFER_SIZE, LOG_LEVEL_INFO, LOG_LEVEL_WARNING,
+};
+
+const MTIME: u32 = 1713104326;
+
+fn align_to_4_bytes(length: usize) -> usize {
+    (4 - length % 4) % 4
+}
+
+/// Minimal cpio writer (in the newc format) for generating the benchmark archives.
+struct CpioWriter {
+    data: Vec<u8>,
+    ino: u32,
+}
+
+impl CpioWriter {
+    fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            ino: 0,
+        }
+    }
+
+    fn write_entry(&mut self, mode: u32, nlink: u32, name: &str, content: &[u8]) {
+        let filesize = content.len().try_into().unwrap();
+        Header::new(self.ino, mode, 0, 0, nlink, MTIME, filesize, name.into())
+            .write(&mut self.data, 0, None)
+            .unwrap();
+        self.data.extend_from_slice(content);
+        self.data
+            .resize(self.data.len() + align_to_4_bytes(content.len()), 0);
+        self.ino += 1;
+    }
+
+    fn directory(&mut self, name: &str) {
+        self.write_entry(0o040_755, 2, name, b"");
+    }
+
+    fn file(&mut self, name: &str, content: &[u8]) {
+        self.write_entry(0o100_644, 1, name, content);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.ino = 0;
+        self.write_entry(0, 1, "TRAILER!!!", b"");
+        self.data
+    }
+}
+
+fn many_small_files() -> Vec<u8> {
+    let mut cpio = CpioWriter::new();
+    cpio.directory(".");
+    for dir in 0..50 {
+        cpio.directory(&format!("dir{dir}"));
+        for file in 0..100 {
+            let content = format!("file {file} in directory {dir}\n").repeat(4);
+            cpio.file(&format!("dir{dir}/file{file}"), content.as_bytes());
+        }
+    }
+    cpio.finish()
+}
+
+fn few_huge_files() -> Vec<u8> {
+    let mut cpio = CpioWriter::new();
+    cpio.directory(".");
+    let content = vec![0xA5; 16 * 1024 * 1024];
+    for file in 0..4 {
+        cpio.file(&format!("huge{file}"), &content);
+    }
+    cpio.finish()
+}
+
+fn deep_tree() -> Vec<u8> {
+    let mut cpio = CpioWriter::new();
+    cpio.directory(".");
+    let mut path = String::from("d");
+    for depth in 0..64 {
+        cpio.directory(&path);
+        for file in 0..10 {
+            cpio.file(&format!("{path}/file{file}"), b"content\n");
+        }
+        path.push_str(&format!("/d{depth}"));
+    }
+    cpio.finish()
+}
+
+fn write_archive(dir: &Path, name: &str, data: &[u8]) -> PathBuf {
+    let path = dir.join(format!("{name}.cpio"));
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+fn benchmark(c: &mut Criterion) {
+    let base_dir = std::env::temp_dir().join(format!("3cpio-bench-{}", std::process::id()));
+    create_dir_all(&base_dir).unwrap();
+    let archives = [
+        ("many-small-files", many_small_files()),
+        ("few-huge-files", few_huge_files()),
+        ("deep-tree", deep_tree()),
+    ];
+
+    let mut group = c.benchmark_group("list");
+    group.sample_size(20);
+    for (name, data) in &archives {
+        let path = write_archive(&base_dir, name, data);
+        group.throughput(Throughput::Bytes(data.len().try_into().unwrap()));
+        for (mode, log_level) in [("names", LOG_LEVEL_WARNING), ("long", LOG_LEVEL_INFO)] {
+            group.bench_with_input(BenchmarkId::new(mode, name), &path, |b, path| {
+                b.iter(|| {
+                    let file = File::open(path).unwrap();
+                    list_cpio_content(
+                        file,
+                        &mut sink(),
+                        &ListOptions::default(),
+                        log_level,
+                        DEFAULT_IO_BUFFER_SIZE,
+                    )
+                    .unwrap();
+                })
+            });
+        }
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("extract");
+    group.sample_size(10);
+    let cwd = std::env::current_dir().unwrap();
+    for (name, data) in &archives {
+        let path = write_archive(&base_dir, name, data);
+        let target = base_dir.join(format!("{name}.extracted"));
+        create_dir_all(&target).unwrap();
+        std::env::set_current_dir(&target).unwrap();
+        group.throughput(Throughput::Bytes(data.len().try_into().unwrap()));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &path, |b, path| {
+            b.iter(|| {
+                let file = File::open(path).unwrap();
+                extract_cpio_archive(
+                    file,
+                    &ExtractOptions::default(),
+                    LOG_LEVEL_WARNING,
+                    DEFAULT_IO_BUFFER_SIZE,
+                )
+                .unwrap();
+            })
+        });
+        std::env::set_current_dir(&cwd).unwrap();
+    }
+    group.finish();
+
+    remove_dir_all(&base_dir).unwrap();
+}
+
+criterion_group!(benches, benchmark);
+criterion_main!(benches);
@@ -0,0 +1,15 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use threecpio::Header;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = data;
+    // Read all headers until the data is exhausted or invalid.
+    while let Ok(header) = Header::read(&mut reader) {
+        let _ = header.mode_string();
+    }
+});
@@ -0,0 +1,13 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use threecpio::Compression;
+
+fuzz_target!(|data: [u8; 4]| {
+    if let Ok(compression) = Compression::from_magic_number(data) {
+        let _ = compression.to_string();
+    }
+});
@@ -0,0 +1,187 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Asynchronous reader for cpio archives (requires the `async` feature)
+//!
+//! Servers that inspect uploaded initrd images can iterate over the entries
+//! without blocking their tokio runtime. The header parsing is shared with
+//! the synchronous code. Decompressing is left to the caller (e.g. with the
+//! async-compression crate), because spawning the decompression programs
+//! would need a runtime with process support.
+
+use std::io::{Error, ErrorKind, Result};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::header::Header;
+use crate::parser::{Event, Parser};
+
+/// Size of the read buffer
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Asynchronous reader for the entries of uncompressed cpio archives
+///
+/// Multiple concatenated cpio archives (with zero padding in between) are
+/// read as one stream of entries.
+pub struct AsyncCpioReader<R> {
+    reader: R,
+    parser: Parser,
+    buffer: Vec<u8>,
+    /// Start of the bytes in the buffer that are not parsed yet
+    start: usize,
+}
+
+impl<R: AsyncRead + Unpin> AsyncCpioReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            parser: Parser::new(),
+            buffer: Vec::new(),
+            start: 0,
+        }
+    }
+
+    /// Return the next entry (skipping the unread data of the current entry).
+    ///
+    /// Return `None` at the end of the stream. Compressed cpio archives are
+    /// reported as errors of kind `ErrorKind::Unsupported`.
+    pub async fn next_entry(&mut self) -> Result<Option<Header>> {
+        loop {
+            let (consumed, event) = self.parser.next(&self.buffer[self.start..])?;
+            self.start += consumed;
+            match event {
+                Event::NeedMore(size) => {
+                    if !self.fill(size).await? {
+                        if self.parser.is_at_boundary() {
+                            return Ok(None);
+                        }
+                        return Err(Error::new(
+                            ErrorKind::UnexpectedEof,
+                            "Unexpected end of the cpio archive.",
+                        ));
+                    }
+                }
+                Event::Archive(compression) if !compression.is_uncompressed() => {
+                    return Err(Error::new(
+                        ErrorKind::Unsupported,
+                        format!(
+                            "Found {} compressed cpio archive. Decompress it before reading it asynchronously.",
+                            compression
+                        ),
+                    ));
+                }
+                Event::Entry(header) => return Ok(Some(header)),
+                Event::Archive(_) | Event::Data(_) | Event::Trailer(_) => {}
+            }
+        }
+    }
+
+    /// Read the file data of the current entry into the buffer.
+    ///
+    /// Return the number of bytes read (zero at the end of the file data).
+    pub async fn read_data(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.parser.data_remaining() == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.start == self.buffer.len() && !self.fill(1).await? {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "failed to read whole file data",
+            ));
+        }
+        let end = self.buffer.len().min(self.start + buf.len());
+        let (consumed, Event::Data(data)) = self.parser.next(&self.buffer[self.start..end])? else {
+            unreachable!("the parser is in the middle of the file data");
+        };
+        buf[..data.len()].copy_from_slice(data);
+        self.start += consumed;
+        Ok(consumed)
+    }
+
+    /// Read until at least `size` unparsed bytes are buffered.
+    ///
+    /// Return false if the end of the stream is reached before.
+    async fn fill(&mut self, size: usize) -> Result<bool> {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+        while self.buffer.len() < size {
+            let length = self.buffer.len();
+            // Grow with the read data (a bogus size must not allocate gigabytes upfront).
+            self.buffer.resize(length + BUFFER_SIZE, 0);
+            let result = self.reader.read(&mut self.buffer[length..]).await;
+            self.buffer
+                .truncate(length + result.as_ref().map_or(0, |read| *read));
+            if result? == 0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn test_async_cpio_reader() {
+        let archive = std::fs::read("tests/single.cpio").unwrap();
+        let mut reader = AsyncCpioReader::new(archive.as_slice());
+        let names = block_on(async {
+            let mut names = Vec::new();
+            while let Some(header) = reader.next_entry().await.unwrap() {
+                names.push(header.filename);
+            }
+            names
+        });
+        assert_eq!(names, vec![".", "path", "path/file"]);
+    }
+
+    #[test]
+    fn test_async_cpio_reader_read_data() {
+        let archive = std::fs::read("tests/single.cpio").unwrap();
+        let mut reader = AsyncCpioReader::new(archive.as_slice());
+        let content = block_on(async {
+            for _ in 0..3 {
+                reader.next_entry().await.unwrap();
+            }
+            let mut content = Vec::new();
+            let mut buffer = [0; 3];
+            loop {
+                let read = reader.read_data(&mut buffer).await.unwrap();
+                if read == 0 {
+                    break;
+                }
+                content.extend_from_slice(&buffer[..read]);
+            }
+            assert!(reader.next_entry().await.unwrap().is_none());
+            content
+        });
+        assert_eq!(content, b"content\n");
+    }
+
+    #[test]
+    fn test_async_cpio_reader_compressed() {
+        let archive = std::fs::read("tests/gzip.cpio").unwrap();
+        let mut reader = AsyncCpioReader::new(archive.as_slice());
+        let got = block_on(async {
+            loop {
+                if let Err(e) = reader.next_entry().await {
+                    return e;
+                }
+            }
+        });
+        assert_eq!(got.kind(), ErrorKind::Unsupported);
+        assert_eq!(
+            got.to_string(),
+            "Found gzip compressed cpio archive. Decompress it before reading it asynchronously."
+        );
+    }
+}
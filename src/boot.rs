@@ -0,0 +1,151 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Check that the cpio archives contain what the kernel needs for booting.
+//!
+//! The kernel executes `/init` from the initramfs and opens `/dev/console`
+//! as stdin, stdout, and stderr for it. The init process needs the `/dev`
+//! and `/proc` directories as mount points. These checks are heuristics
+//! for the CI of initramfs generators, not a guarantee that the system boots.
+
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Result, Write};
+
+use crate::metadata::FileType;
+use crate::symlinks::{read_cpio_into_namespace, Kind, Namespace, Problem};
+use crate::{check_decompressor_available, decompress_buffered, read_magic_header};
+
+/// Check of the archive entries that returns the reason for failing
+type Check = fn(&Namespace) -> std::result::Result<(), String>;
+
+const CHECKS: [(&str, Check); 4] = [
+    ("/init", check_init),
+    ("/dev", |namespace| check_directory(namespace, "dev")),
+    ("/proc", |namespace| check_directory(namespace, "proc")),
+    ("/dev/console", check_console),
+];
+
+/// Resolve the path (relative to the archive root) and return its entry.
+fn lookup<'a>(namespace: &'a Namespace, path: &str) -> std::result::Result<&'a Kind, String> {
+    match namespace.resolve("", path) {
+        Ok(resolved) => namespace
+            .get(&resolved)
+            .ok_or_else(|| "missing".to_string()),
+        Err(Problem::Dangling) => Err("missing".into()),
+        Err(problem) => Err(problem.to_string()),
+    }
+}
+
+fn check_init(namespace: &Namespace) -> std::result::Result<(), String> {
+    match lookup(namespace, "init") {
+        Err(e) => Err(e),
+        Ok(Kind::Other(mode)) if FileType::from_mode(*mode) != Some(FileType::RegularFile) => {
+            Err("not a regular file".into())
+        }
+        Ok(Kind::Other(mode)) if mode & 0o111 == 0 => Err("not executable".into()),
+        Ok(Kind::Other(_)) => Ok(()),
+        Ok(_) => Err("not a regular file".into()),
+    }
+}
+
+fn check_directory(namespace: &Namespace, path: &str) -> std::result::Result<(), String> {
+    match lookup(namespace, path) {
+        Err(e) => Err(e),
+        Ok(Kind::Directory) => Ok(()),
+        Ok(_) => Err("not a directory".into()),
+    }
+}
+
+fn check_console(namespace: &Namespace) -> std::result::Result<(), String> {
+    match lookup(namespace, "dev/console") {
+        Err(e) => Err(e),
+        Ok(Kind::Other(mode)) if FileType::from_mode(*mode) == Some(FileType::CharacterDevice) => {
+            Ok(())
+        }
+        Ok(_) => Err("not a character device".into()),
+    }
+}
+
+/// Check that the cpio archives are bootable as initramfs.
+///
+/// Print the result of each check (`/init` is an executable file, `/dev`
+/// and `/proc` are directories, and `/dev/console` is a character device)
+/// to `out`. Fail if at least one check fails.
+pub fn check_boot<W: Write>(
+    archive: File,
+    out: &mut W,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let mut file = BufReader::with_capacity(io_buffer_size, archive);
+    check_decompressor_available(&mut file)?;
+    let mut namespace = Namespace::default();
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if compression.is_uncompressed() {
+            read_cpio_into_namespace(&mut file, &mut namespace)?;
+        } else {
+            let mut decompressed =
+                decompress_buffered(compression, file, io_buffer_size, log_level)?;
+            read_cpio_into_namespace(&mut decompressed, &mut namespace)?;
+            decompressed.finish()?;
+            break;
+        }
+    }
+    let mut failed = 0;
+    for (path, check) in CHECKS {
+        match check(&namespace) {
+            Ok(()) => writeln!(out, "{}: OK", path)?,
+            Err(e) => {
+                failed += 1;
+                writeln!(out, "{}: FAILED ({})", path, e)?;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} of {} boot checks failed.", failed, CHECKS.len()),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks_bootable() {
+        let mut namespace = Namespace::default();
+        namespace.insert("init", Kind::Symlink("usr/lib/systemd/systemd".into()));
+        namespace.insert("usr/lib/systemd/systemd", Kind::Other(0o100_755));
+        namespace.insert("dev", Kind::Directory);
+        namespace.insert("dev/console", Kind::Other(0o020_600));
+        namespace.insert("proc", Kind::Directory);
+        for (path, check) in CHECKS {
+            assert_eq!(check(&namespace), Ok(()), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_checks_failing() {
+        let mut namespace = Namespace::default();
+        namespace.insert("init", Kind::Other(0o100_644));
+        namespace.insert("dev/console", Kind::Other(0o100_644));
+        namespace.insert("proc", Kind::Symlink("../proc".into()));
+        let results: Vec<_> = CHECKS.iter().map(|(_, check)| check(&namespace)).collect();
+        assert_eq!(
+            results,
+            vec![
+                Err("not executable".into()),
+                Ok(()),
+                Err("points outside the archive".into()),
+                Err("not a character device".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Write the content of the regular files in the cpio archives to stdout.
+
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Write};
+
+use crate::header::*;
+use crate::metadata::FileType;
+use crate::seek_forward::SeekForward;
+use crate::{
+    align_to_4_bytes, check_decompressor_available, compile_patterns, decompress_buffered,
+    patterns_match, read_magic_header, MatchMode, Patterns, LOG_LEVEL_WARNING,
+};
+
+fn read_cpio_and_cat<R: Read + SeekForward, W: Write>(
+    file: &mut R,
+    patterns: &Patterns,
+    out: &mut W,
+    names_out: &mut Option<&mut dyn Write>,
+) -> Result<()> {
+    loop {
+        let header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            break;
+        }
+        // The data of hard-linked files is stored with only one of the links.
+        if header.file_type() != Some(FileType::RegularFile)
+            || (header.nlink > 1 && header.filesize == 0)
+            || !patterns_match(patterns, &header.filename)
+        {
+            header.skip_file_content(file)?;
+            continue;
+        }
+        let filesize = header.filesize.into();
+        let copied = std::io::copy(&mut file.take(filesize), out)?;
+        if copied != filesize {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Content of '{}' is truncated.", header.filename),
+            ));
+        }
+        file.seek_forward(align_to_4_bytes(header.filesize).into())?;
+        if let Some(names_out) = names_out {
+            names_out.write_all(header.filename.as_bytes())?;
+            names_out.write_all(b"\0")?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the content of the regular files in all cpio archives to `out`.
+///
+/// If `patterns` are given, only write the files whose names match one of
+/// these patterns (interpreted according to `match_mode`). The names of the written files are written
+/// NUL-separated to `names_out` (if given) in the same order.
+pub fn cat_cpio_content<W: Write>(
+    archive: File,
+    patterns: &[String],
+    match_mode: MatchMode,
+    out: &mut W,
+    mut names_out: Option<&mut dyn Write>,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let patterns = compile_patterns(patterns, match_mode)?;
+    let mut file = BufReader::with_capacity(io_buffer_size, archive);
+    check_decompressor_available(&mut file)?;
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if compression.is_uncompressed() {
+            read_cpio_and_cat(&mut file, &patterns, out, &mut names_out)?;
+        } else {
+            let mut decompressed =
+                decompress_buffered(compression, file, io_buffer_size, LOG_LEVEL_WARNING)?;
+            read_cpio_and_cat(&mut decompressed, &patterns, out, &mut names_out)?;
+            decompressed.finish()?;
+            break;
+        }
+    }
+    if let Some(names_out) = names_out {
+        names_out.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cat_cpio_content_with_names() {
+        let archive = File::open("tests/single.cpio").expect("test cpio should be present");
+        let mut output = Vec::new();
+        let mut names = Vec::new();
+        cat_cpio_content(
+            archive,
+            &[],
+            MatchMode::Glob,
+            &mut output,
+            Some(&mut names),
+            crate::DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        assert_eq!(output, b"content\n");
+        assert_eq!(names, b"path/file\0");
+    }
+}
@@ -0,0 +1,486 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::JoinHandle;
+
+use crate::seek_forward::{skip_by_reading, SeekForward};
+use crate::LOG_LEVEL_WARNING;
+
+/// Memory usage limit in bytes for the decompression programs (0 = no limit)
+static DECOMPRESS_MEMLIMIT: AtomicU64 = AtomicU64::new(0);
+
+/// Limit the memory usage of the decompression programs (in bytes).
+///
+/// The limit is passed to xz and lzma (`--memlimit-decompress`) and zstd
+/// (`--memory`, rounded down to KiB). The other decompression programs use
+/// a small, fixed amount of memory. Decompressing a cpio archive that needs
+/// more memory fails instead of exhausting the memory of the system, which
+/// protects against hostile archives with absurd dictionary or window sizes.
+pub fn set_decompress_memlimit(limit: Option<u64>) {
+    DECOMPRESS_MEMLIMIT.store(limit.unwrap_or(0), Ordering::SeqCst);
+}
+
+fn decompress_memlimit() -> Option<u64> {
+    match DECOMPRESS_MEMLIMIT.load(Ordering::SeqCst) {
+        0 => None,
+        limit => Some(limit),
+    }
+}
+
+/// Compression of a cpio archive in the initramfs cpio file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Uncompressed,
+    Bzip2,
+    Gzip,
+    Lz4,
+    Lzma,
+    Lzop,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Determine the compression from the first four bytes of the cpio archive.
+    pub fn from_magic_number(magic_number: [u8; 4]) -> Result<Self> {
+        let compression = match magic_number {
+            [0x42, 0x5A, 0x68, _] => Self::Bzip2,
+            [0x30, 0x37, 0x30, 0x37] => Self::Uncompressed,
+            [0x1F, 0x8B, _, _] => Self::Gzip,
+            // Different magic numbers (little endian) for lz4:
+            // v0.1-v0.9: 0x184C2102
+            // v1.0-v1.3: 0x184C2103
+            // v1.4+: 0x184D2204
+            [0x02, 0x21, 0x4C, 0x18] | [0x03, 0x21, 0x4C, 0x18] | [0x04, 0x22, 0x4D, 0x18] => {
+                Self::Lz4
+            }
+            [0x5D, _, _, _] => Self::Lzma,
+            // Full magic number for lzop: [0x89, 0x4C, 0x5A, 0x4F, 0x00, 0x0D, 0x0A, 0x1A, 0x0A]
+            [0x89, 0x4C, 0x5A, 0x4F] => Self::Lzop,
+            // Full magic number for xz: [0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00]
+            [0xFD, 0x37, 0x7A, 0x58] => Self::Xz,
+            [0x28, 0xB5, 0x2F, 0xFD] => Self::Zstd,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Failed to determine CPIO or compression magic number: 0x{:02x}{:02x}{:02x}{:02x} (big endian)",
+                        magic_number[0], magic_number[1], magic_number[2], magic_number[3]
+                    ),
+                ));
+            }
+        };
+        Ok(compression)
+    }
+
+    pub fn is_uncompressed(&self) -> bool {
+        *self == Self::Uncompressed
+    }
+
+    /// Return the name of the decompression program (or `None` for uncompressed).
+    pub fn program(&self) -> Option<&'static str> {
+        match self {
+            Self::Uncompressed => None,
+            Self::Bzip2 => Some("bzip2"),
+            Self::Gzip => Some("gzip"),
+            Self::Lz4 => Some("lz4"),
+            Self::Lzma => Some("lzma"),
+            Self::Lzop => Some("lzop"),
+            Self::Xz => Some("xz"),
+            Self::Zstd => Some("zstd"),
+        }
+    }
+
+    fn decompress_command(&self) -> Option<Command> {
+        let mut command = Command::new(self.program()?);
+        match self {
+            Self::Zstd => command.arg("-cdq"),
+            _ => command.arg("-cd"),
+        };
+        if let Some(limit) = decompress_memlimit() {
+            match self {
+                Self::Lzma | Self::Xz => {
+                    command.arg(format!("--memlimit-decompress={}", limit));
+                }
+                Self::Zstd => {
+                    let limit = (limit / 1024).clamp(1, u32::MAX.into());
+                    command.arg(format!("--memory={}KiB", limit));
+                }
+                _ => {}
+            }
+        }
+        Some(command)
+    }
+
+    /// Return the command for compressing with the settings the kernel
+    /// supports (legacy lz4 frame format and CRC32 checks for xz).
+    fn compress_command(&self) -> Option<Command> {
+        let mut command = Command::new(self.program()?);
+        match self {
+            Self::Lz4 => command.args(["-l", "-c"]),
+            Self::Xz => command.args(["--check=crc32", "-c"]),
+            Self::Zstd => command.arg("-cq"),
+            _ => command.arg("-c"),
+        };
+        Some(command)
+    }
+
+    /// Check that the decompression program is available.
+    ///
+    /// Return an error naming the program and the package that provides it
+    /// in case the program is not found in PATH.
+    pub fn check_decompressor_available(&self) -> Result<()> {
+        let program = match self.program() {
+            Some(program) => program,
+            None => return Ok(()),
+        };
+        if !is_program_in_path(program) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "Program '{}' not found in PATH. Please install the '{}' package.",
+                    program,
+                    program_package(program)
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Spawn the decompression program that reads the compressed cpio from `file`.
+    ///
+    /// The stderr output of the program is forwarded (prefixed with the
+    /// program name) if the log level is at least warning.
+    pub(crate) fn decompress(&self, file: File, log_level: u32) -> Result<Decompressor> {
+        let mut command = match self.decompress_command() {
+            Some(command) => command,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Uncompressed cpio archives cannot be decompressed.",
+                ))
+            }
+        };
+        decompress(&mut command, file, log_level)
+    }
+}
+
+impl Compression {
+    /// Spawn the compression program that writes the compressed cpio to `output`.
+    pub(crate) fn compress(&self, output: File) -> Result<Compressor> {
+        let mut command = match self.compress_command() {
+            Some(command) => command,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Uncompressed cpio archives cannot be compressed.",
+                ))
+            }
+        };
+        let program = command.get_program().to_string_lossy().into_owned();
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(output)
+            .spawn()
+            .map_err(|e| match e.kind() {
+                ErrorKind::NotFound => {
+                    Error::other(format!("Program '{}' not found in PATH.", program))
+                }
+                _ => e,
+            })?;
+        let stdin = child.stdin.take();
+        Ok(Compressor {
+            program,
+            child,
+            stdin,
+        })
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.program().unwrap_or("cpio"))
+    }
+}
+
+fn decompress(command: &mut Command, file: File, log_level: u32) -> Result<Decompressor> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(file)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => {
+                Error::other(format!("Program '{}' not found in PATH.", program))
+            }
+            _ => e,
+        })?;
+    let stdout = child.stdout.take().unwrap();
+    // Process stderr in the background. Otherwise a chatty decompressor
+    // could block on a full stderr pipe while we wait for its stdout.
+    let stderr = child.stderr.take().unwrap();
+    let name = program.clone();
+    let stderr_reader = std::thread::spawn(move || forward_stderr(stderr, &name, log_level));
+    Ok(Decompressor {
+        program,
+        child,
+        stdout,
+        stderr_reader: Some(stderr_reader),
+    })
+}
+
+/// Forward the non-empty stderr lines of the program with the program name
+/// as prefix.
+///
+/// Return the lines that were not forwarded (because of the log level).
+fn forward_stderr<R: Read>(stderr: R, program: &str, log_level: u32) -> String {
+    let mut collected = String::new();
+    for line in BufReader::new(stderr).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if log_level >= LOG_LEVEL_WARNING {
+            let _ = writeln!(std::io::stderr(), "Warning: {}: {}", program, line);
+        } else {
+            collected.push_str(&line);
+            collected.push('\n');
+        }
+    }
+    collected
+}
+
+/// Output of a spawned decompression program
+///
+/// Once the output is consumed, the exit status of the program is checked.
+/// A failing decompressor (e.g. on a corrupt archive) is reported as error
+/// including its (not forwarded) stderr output instead of looking like
+/// truncated data.
+#[derive(Debug)]
+pub(crate) struct Decompressor {
+    program: String,
+    child: Child,
+    stdout: ChildStdout,
+    stderr_reader: Option<JoinHandle<String>>,
+}
+
+impl Decompressor {
+    /// Wait for the program to exit and check its exit status.
+    fn check_exit_status(&mut self) -> Result<()> {
+        let Some(stderr_reader) = self.stderr_reader.take() else {
+            // Exit status was already checked.
+            return Ok(());
+        };
+        let status = self.child.wait()?;
+        let stderr = stderr_reader.join().unwrap_or_default();
+        if status.success() {
+            return Ok(());
+        }
+        let mut message = format!("Program '{}' failed ({})", self.program, status);
+        let stderr = stderr.trim();
+        if !stderr.is_empty() {
+            message.push_str(": ");
+            message.push_str(stderr);
+        }
+        Err(Error::other(message))
+    }
+}
+
+/// Input of a spawned compression program
+#[derive(Debug)]
+pub(crate) struct Compressor {
+    program: String,
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl Compressor {
+    /// Close the input, wait for the program to exit, and check its exit status.
+    pub(crate) fn finish(mut self) -> Result<()> {
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(Error::other(format!(
+                "Program '{}' failed ({})",
+                self.program, status
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Write for Compressor {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.stdin.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.stdin.as_mut().unwrap().flush()
+    }
+}
+
+impl Read for Decompressor {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            self.check_exit_status()?;
+        }
+        Ok(read)
+    }
+}
+
+impl SeekForward for Decompressor {
+    fn seek_forward(&mut self, offset: u64) -> Result<()> {
+        skip_by_reading(self, offset)
+    }
+}
+
+impl Drop for Decompressor {
+    /// Terminate the program if its output was not consumed completely
+    /// (e.g. on errors or after reading the cpio trailer).
+    fn drop(&mut self) {
+        if self.stderr_reader.is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Return the name of the package that provides the given program.
+///
+/// The package names are the ones used by Debian and Ubuntu.
+fn program_package(program: &str) -> &str {
+    match program {
+        "lzma" | "xz" => "xz-utils",
+        _ => program,
+    }
+}
+
+/// Check if the given program is an executable file in one of the PATH directories.
+fn is_program_in_path(program: &str) -> bool {
+    let paths = match std::env::var_os("PATH") {
+        Some(paths) => paths,
+        None => return false,
+    };
+    std::env::split_paths(&paths).any(|dir| match dir.join(program).metadata() {
+        Ok(stat) => stat.is_file() && stat.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_display() {
+        assert_eq!(Compression::Uncompressed.to_string(), "cpio");
+        assert_eq!(Compression::Zstd.to_string(), "zstd");
+    }
+
+    #[test]
+    fn test_compression_from_magic_number() {
+        assert_eq!(
+            Compression::from_magic_number(*b"0707").unwrap(),
+            Compression::Uncompressed
+        );
+        assert_eq!(
+            Compression::from_magic_number([0x04, 0x22, 0x4D, 0x18]).unwrap(),
+            Compression::Lz4
+        );
+    }
+
+    #[test]
+    fn test_compression_from_magic_number_invalid() {
+        let got = Compression::from_magic_number(*b"ABCD").unwrap_err();
+        assert_eq!(got.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            got.to_string(),
+            "Failed to determine CPIO or compression magic number: 0x41424344 (big endian)"
+        );
+    }
+
+    #[test]
+    fn test_decompress_program_not_found() {
+        let file = File::open("tests/single.cpio").expect("test cpio should be present");
+        let mut cmd = Command::new("non-existing-program");
+        let got = decompress(&mut cmd, file, LOG_LEVEL_WARNING).unwrap_err();
+        assert_eq!(got.kind(), ErrorKind::Other);
+        assert_eq!(
+            got.to_string(),
+            "Program 'non-existing-program' not found in PATH."
+        );
+    }
+
+    #[test]
+    fn test_decompress_program_fails() {
+        let file = File::open("tests/single.cpio").expect("test cpio should be present");
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "printf abc; echo 'corrupt data' >&2; exit 2"]);
+        let mut decompressor = decompress(&mut cmd, file, 0).unwrap();
+        let mut output = Vec::new();
+        let got = decompressor.read_to_end(&mut output).unwrap_err();
+        assert_eq!(output, b"abc");
+        assert_eq!(got.kind(), ErrorKind::Other);
+        assert_eq!(
+            got.to_string(),
+            "Program 'sh' failed (exit status: 2): corrupt data"
+        );
+    }
+
+    #[test]
+    fn test_decompress_skip_beyond_failed_output() {
+        let file = File::open("tests/single.cpio").expect("test cpio should be present");
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "printf abc; exit 1"]);
+        let mut decompressor = decompress(&mut cmd, file, 0).unwrap();
+        let got = decompressor.seek_forward(10).unwrap_err();
+        assert_eq!(got.to_string(), "Program 'sh' failed (exit status: 1)");
+    }
+
+    #[test]
+    fn test_drop_terminates_decompressor() {
+        let file = File::open("tests/single.cpio").expect("test cpio should be present");
+        let mut cmd = Command::new("sleep");
+        cmd.arg("60");
+        let decompressor = decompress(&mut cmd, file, 0).unwrap();
+        let start = std::time::Instant::now();
+        drop(decompressor);
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_forward_stderr_below_warning() {
+        let stderr = b"first line\nsecond line\n";
+        let got = forward_stderr(stderr.as_slice(), "xz", LOG_LEVEL_WARNING - 1);
+        assert_eq!(got, "first line\nsecond line\n");
+    }
+
+    #[test]
+    fn test_forward_stderr_skip_empty_lines() {
+        let stderr = b"\nfirst line\n  \nsecond line\n";
+        let got = forward_stderr(stderr.as_slice(), "gzip", LOG_LEVEL_WARNING - 1);
+        assert_eq!(got, "first line\nsecond line\n");
+    }
+
+    #[test]
+    fn test_is_program_in_path() {
+        assert!(is_program_in_path("sh"));
+        assert!(!is_program_in_path("non-existing-program"));
+    }
+
+    #[test]
+    fn test_program_package() {
+        assert_eq!(program_package("xz"), "xz-utils");
+        assert_eq!(program_package("zstd"), "zstd");
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Concatenate initramfs cpio files.
+//!
+//! The Linux kernel expects every uncompressed cpio archive to start at a
+//! 4-byte aligned offset. Concatenating the files with `cat` breaks this
+//! as soon as one file (e.g. a compressed one) has an odd size.
+
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+
+use crate::compression::Compression;
+use crate::examine::examine;
+
+/// Check that the file consists of well-formed cpio archives.
+///
+/// Return the compression of the last cpio archive.
+fn check_cpio_file(path: &Path, io_buffer_size: usize) -> Result<Compression> {
+    let segments = examine(File::open(path)?, io_buffer_size)?;
+    let Some(last) = segments.last() else {
+        return Err(Error::new(ErrorKind::InvalidData, "No cpio archive found."));
+    };
+    if last.trailing_data {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Found data after the last cpio archive.",
+        ));
+    }
+    Ok(last.compression)
+}
+
+/// Concatenate the initramfs cpio files and write them to `out`.
+///
+/// All files are checked before writing anything. Each file is copied
+/// unchanged (keeping its compression) and zero padded to a multiple of
+/// four bytes (or of `data_align` bytes to keep the alignment of the file
+/// data in the following files). Only the last file may end with a
+/// compressed cpio archive, because the decompression would consume the
+/// following files.
+pub fn concatenate_cpio_files<P: AsRef<Path>, W: Write>(
+    paths: &[P],
+    out: &mut W,
+    data_align: Option<u32>,
+    io_buffer_size: usize,
+) -> Result<()> {
+    for (i, path) in paths.iter().enumerate() {
+        let path = path.as_ref();
+        let compression = check_cpio_file(path, io_buffer_size)
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        if !compression.is_uncompressed() && i + 1 < paths.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{}: Only the last file can end with a compressed cpio archive, but this one ends with a {} compressed cpio archive.",
+                    path.display(),
+                    compression
+                ),
+            ));
+        }
+    }
+    let align = u64::from(data_align.unwrap_or(4));
+    let mut offset = 0;
+    for path in paths {
+        let padding = (align - offset % align) % align;
+        std::io::copy(&mut std::io::repeat(0).take(padding), out)?;
+        offset += padding + std::io::copy(&mut File::open(path)?, out)?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concatenate_cpio_files() {
+        let mut output = Vec::new();
+        concatenate_cpio_files(
+            &["tests/single.cpio", "tests/single.cpio"],
+            &mut output,
+            None,
+            4096,
+        )
+        .unwrap();
+        let single = std::fs::read("tests/single.cpio").unwrap();
+        assert_eq!(output, [single.clone(), single].concat());
+    }
+
+    #[test]
+    fn test_concatenate_cpio_files_data_align() {
+        let mut output = Vec::new();
+        concatenate_cpio_files(
+            &["tests/single.cpio", "tests/single.cpio"],
+            &mut output,
+            Some(4096),
+            4096,
+        )
+        .unwrap();
+        let single = std::fs::read("tests/single.cpio").unwrap();
+        assert_eq!(output.len(), 4096 + single.len());
+        assert_eq!(output[4096..], single);
+    }
+
+    #[test]
+    fn test_concatenate_cpio_files_compressed_not_last() {
+        let mut output = Vec::new();
+        let got = concatenate_cpio_files(
+            &["tests/gzip.cpio", "tests/single.cpio"],
+            &mut output,
+            None,
+            4096,
+        )
+        .unwrap_err();
+        assert_eq!(got.kind(), ErrorKind::InvalidInput);
+        assert!(output.is_empty());
+    }
+}
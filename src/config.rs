@@ -0,0 +1,250 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Default values for the command line options from configuration files
+//!
+//! The system-wide configuration is read from /etc/3cpio.conf and the user
+//! configuration from ~/.config/3cpio.conf (or $XDG_CONFIG_HOME/3cpio.conf).
+//! Later files override earlier ones and the command line overrides both.
+//! Each line has the form `option = value` with the name of the long
+//! command line option. The value can be enclosed in double quotes.
+//! Everything after a `#` (outside of quotes) is a comment. Empty lines are
+//! ignored. Unsupported options and malformed lines only cause a warning,
+//! so that a configuration file for a newer version does not break the
+//! older ones.
+//!
+//! The environment variables `THREECPIO_JOBS` and `THREECPIO_LOG_LEVEL`
+//! override the configuration files (but not the command line).
+
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use threecpio::{HardlinkPolicy, LogLevel, QuotingStyle};
+
+const SYSTEM_CONFIG: &str = "/etc/3cpio.conf";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub hardlink_policy: Option<HardlinkPolicy>,
+    pub io_buffer_size: Option<usize>,
+    pub jobs: Option<usize>,
+    pub log_level: Option<LogLevel>,
+    pub quoting_style: Option<QuotingStyle>,
+    /// Warnings about ignored lines of the configuration files
+    pub warnings: Vec<String>,
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    let mut path = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let mut home = PathBuf::from(std::env::var_os("HOME")?);
+            home.push(".config");
+            home
+        }
+    };
+    path.push("3cpio.conf");
+    Some(path)
+}
+
+/// Strip the comment (starting with `#` outside of quotes) from the line.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Strip the quotes from a quoted string value.
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn parse_positive(key: &str, value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(0) | Err(_) => Err(format!(
+            "Invalid value '{}' for '{}'. Expected a positive number.",
+            value, key
+        )),
+        Ok(number) => Ok(number),
+    }
+}
+
+/// Return the value of the environment variable (if set and not empty).
+fn env_var(name: &str) -> Result<Option<String>, String> {
+    match std::env::var(name) {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(format!("{}: {}", name, e)),
+    }
+}
+
+impl Config {
+    /// Read the system-wide and the user configuration (if present).
+    pub fn load() -> Result<Self, String> {
+        let mut config = Self::default();
+        let mut paths = vec![PathBuf::from(SYSTEM_CONFIG)];
+        paths.extend(user_config_path());
+        for path in paths {
+            match std::fs::read_to_string(&path) {
+                Ok(content) => config
+                    .parse(&content, &path.display().to_string())
+                    .map_err(|e| format!("{}: {}", path.display(), e))?,
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+            }
+        }
+        config.read_environment()?;
+        Ok(config)
+    }
+
+    fn read_environment(&mut self) -> Result<(), String> {
+        if let Some(jobs) = env_var("THREECPIO_JOBS")? {
+            self.jobs = Some(parse_positive("THREECPIO_JOBS", &jobs)?);
+        }
+        if let Some(log_level) = env_var("THREECPIO_LOG_LEVEL")? {
+            self.log_level = Some(
+                log_level
+                    .parse()
+                    .map_err(|e| format!("THREECPIO_LOG_LEVEL: {}", e))?,
+            );
+        }
+        Ok(())
+    }
+
+    /// Parse the content of the configuration file `name`.
+    ///
+    /// Fail on invalid values of supported options. Record a warning for
+    /// unsupported options and lines that are not in the form
+    /// `option = value`.
+    fn parse(&mut self, content: &str, name: &str) -> Result<(), String> {
+        for (number, line) in content.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                self.warnings.push(format!(
+                    "{}: line {}: Expected 'option = value'. Ignoring '{}'.",
+                    name,
+                    number + 1,
+                    line
+                ));
+                continue;
+            };
+            let key = key.trim();
+            if !self
+                .set(key, unquote(value.trim()))
+                .map_err(|e| format!("line {}: {}", number + 1, e))?
+            {
+                self.warnings.push(format!(
+                    "{}: line {}: Ignoring unsupported option '{}'.",
+                    name,
+                    number + 1,
+                    key
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the option. Return `false` if the option is not supported.
+    fn set(&mut self, key: &str, value: &str) -> Result<bool, String> {
+        match key {
+            "hardlink-policy" => self.hardlink_policy = Some(value.parse()?),
+            "io-buffer-size" => self.io_buffer_size = Some(parse_positive(key, value)?),
+            "jobs" => self.jobs = Some(parse_positive(key, value)?),
+            "log-level" => self.log_level = Some(value.parse()?),
+            "quoting-style" => self.quoting_style = Some(value.parse()?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut config = Config::default();
+        config
+            .parse(
+                "# site policy\njobs = 4\n\nquoting-style = \"c\"\n",
+                "3cpio.conf",
+            )
+            .unwrap();
+        config.parse("jobs=2", "3cpio.conf").unwrap();
+        assert_eq!(
+            config,
+            Config {
+                jobs: Some(2),
+                quoting_style: Some(QuotingStyle::C),
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_comments() {
+        let mut config = Config::default();
+        config
+            .parse(
+                "jobs = 4  # comment\nquoting-style = \"c\" # \"escape\"\n",
+                "3cpio.conf",
+            )
+            .unwrap();
+        assert_eq!(config.jobs, Some(4));
+        assert_eq!(config.quoting_style, Some(QuotingStyle::C));
+        assert_eq!(config.warnings, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_parse_log_level() {
+        let mut config = Config::default();
+        config.parse("log-level = error", "3cpio.conf").unwrap();
+        assert_eq!(config.log_level, Some(LogLevel::Error));
+        assert_eq!(
+            config.parse("log-level = verbose", "3cpio.conf"),
+            Err(
+                "line 1: Unknown log level 'verbose'. Expected 'error', 'warning', 'info', or 'debug'."
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_unsupported_option() {
+        let mut config = Config::default();
+        config
+            .parse("[extract]\njobs = 4\ncompression = \"zstd\"", "3cpio.conf")
+            .unwrap();
+        assert_eq!(config.jobs, Some(4));
+        assert_eq!(
+            config.warnings,
+            vec![
+                "3cpio.conf: line 1: Expected 'option = value'. Ignoring '[extract]'.",
+                "3cpio.conf: line 3: Ignoring unsupported option 'compression'.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_jobs() {
+        let mut config = Config::default();
+        assert_eq!(
+            config.parse("jobs = 0", "3cpio.conf"),
+            Err("line 1: Invalid value '0' for 'jobs'. Expected a positive number.".into())
+        );
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Minimal parser for the dynamic linking information of ELF files
+//!
+//! Only the program headers are parsed (like the dynamic loader does), so
+//! stripped binaries without section headers are supported. All offsets are
+//! checked, because the ELF files come from untrusted archives.
+
+use std::io::{Error, ErrorKind, Result};
+
+pub const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ELFDATA2MSB: u8 = 2;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+
+/// Dynamic linking information of an ELF file
+#[derive(Debug, Default, PartialEq)]
+pub struct DynamicInfo {
+    /// Path of the dynamic loader (PT_INTERP)
+    pub interpreter: Option<String>,
+    /// Names of the needed shared libraries (DT_NEEDED)
+    pub needed: Vec<String>,
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid ELF file: {}", message),
+    )
+}
+
+struct ProgramHeader {
+    kind: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+/// Reader for the fields of an ELF file with the given class and byte order
+struct Elf<'a> {
+    data: &'a [u8],
+    is_64bit: bool,
+    big_endian: bool,
+}
+
+impl Elf<'_> {
+    fn bytes<const N: usize>(&self, offset: u64) -> Result<[u8; N]> {
+        usize::try_from(offset)
+            .ok()
+            .and_then(|start| self.data.get(start..start.checked_add(N)?))
+            .map(|bytes| bytes.try_into().unwrap())
+            .ok_or_else(|| invalid("offset out of bounds"))
+    }
+
+    fn u16(&self, offset: u64) -> Result<u16> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: u64) -> Result<u32> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    /// Read an address or offset (32 or 64 bit depending on the class).
+    fn word(&self, offset: u64) -> Result<u64> {
+        if !self.is_64bit {
+            return self.u32(offset).map(u64::from);
+        }
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+
+    fn word_size(&self) -> u64 {
+        if self.is_64bit {
+            8
+        } else {
+            4
+        }
+    }
+
+    /// Read the NUL-terminated string at the offset.
+    fn string(&self, offset: u64) -> Result<String> {
+        let start = usize::try_from(offset)
+            .ok()
+            .filter(|start| *start < self.data.len())
+            .ok_or_else(|| invalid("string offset out of bounds"))?;
+        let end = self.data[start..]
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| invalid("unterminated string"))?;
+        Ok(String::from_utf8_lossy(&self.data[start..start + end]).into_owned())
+    }
+
+    fn program_headers(&self) -> Result<Vec<ProgramHeader>> {
+        let (phoff, phentsize, phnum) = if self.is_64bit {
+            (self.word(32)?, self.u16(54)?, self.u16(56)?)
+        } else {
+            (self.word(28)?, self.u16(42)?, self.u16(44)?)
+        };
+        (0..u64::from(phnum))
+            .map(|i| {
+                let base = phoff
+                    .checked_add(i * u64::from(phentsize))
+                    .ok_or_else(|| invalid("program header offset overflow"))?;
+                let kind = self.u32(base)?;
+                let field = |offset| self.word(base.saturating_add(offset));
+                Ok(if self.is_64bit {
+                    ProgramHeader {
+                        kind,
+                        offset: field(8)?,
+                        vaddr: field(16)?,
+                        filesz: field(32)?,
+                    }
+                } else {
+                    ProgramHeader {
+                        kind,
+                        offset: field(4)?,
+                        vaddr: field(8)?,
+                        filesz: field(16)?,
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+/// Translate a virtual address into a file offset using the PT_LOAD segments.
+fn address_to_offset(headers: &[ProgramHeader], address: u64) -> Result<u64> {
+    headers
+        .iter()
+        .filter(|header| header.kind == PT_LOAD)
+        .find(|header| address >= header.vaddr && address - header.vaddr < header.filesz)
+        .and_then(|header| (address - header.vaddr).checked_add(header.offset))
+        .ok_or_else(|| invalid("address not in any loadable segment"))
+}
+
+/// Parse the interpreter and the needed libraries of the ELF file.
+///
+/// Statically linked executables, kernel modules, and other ELF files
+/// without dynamic section return an empty `DynamicInfo`.
+pub fn parse_dynamic_info(data: &[u8]) -> Result<DynamicInfo> {
+    if data.get(..4) != Some(&ELF_MAGIC) {
+        return Err(invalid("missing magic number"));
+    }
+    let is_64bit = match data.get(4) {
+        Some(&ELFCLASS32) => false,
+        Some(&ELFCLASS64) => true,
+        _ => return Err(invalid("unknown class")),
+    };
+    let big_endian = match data.get(5) {
+        Some(&ELFDATA2LSB) => false,
+        Some(&ELFDATA2MSB) => true,
+        _ => return Err(invalid("unknown byte order")),
+    };
+    let elf = Elf {
+        data,
+        is_64bit,
+        big_endian,
+    };
+    let headers = elf.program_headers()?;
+    let mut info = DynamicInfo::default();
+    if let Some(interp) = headers.iter().find(|header| header.kind == PT_INTERP) {
+        info.interpreter = Some(elf.string(interp.offset)?);
+    }
+    let Some(dynamic) = headers.iter().find(|header| header.kind == PT_DYNAMIC) else {
+        return Ok(info);
+    };
+    let entry_size = 2 * elf.word_size();
+    let mut needed_offsets = Vec::new();
+    let mut strtab = None;
+    for i in 0..dynamic.filesz / entry_size {
+        let entry = dynamic.offset.saturating_add(i * entry_size);
+        let tag = elf.word(entry)?;
+        let value = elf.word(entry.saturating_add(elf.word_size()))?;
+        match tag {
+            DT_NULL => break,
+            DT_NEEDED => needed_offsets.push(value),
+            DT_STRTAB => strtab = Some(address_to_offset(&headers, value)?),
+            _ => {}
+        }
+    }
+    if needed_offsets.is_empty() {
+        return Ok(info);
+    }
+    let strtab = strtab.ok_or_else(|| invalid("missing string table"))?;
+    for offset in needed_offsets {
+        let offset = strtab
+            .checked_add(offset)
+            .ok_or_else(|| invalid("string offset overflow"))?;
+        info.needed.push(elf.string(offset)?);
+    }
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a little endian ELF64 file with interpreter and needed libraries.
+    fn build_elf64(interpreter: &str, needed: &[&str]) -> Vec<u8> {
+        let mut strtab = vec![0];
+        let mut dynamic = Vec::new();
+        for name in needed {
+            dynamic.extend_from_slice(&DT_NEEDED.to_le_bytes());
+            dynamic.extend_from_slice(&(strtab.len() as u64).to_le_bytes());
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+        // Layout: ELF header (64), 3 program headers (3 * 56), interpreter,
+        // string table, dynamic section. Virtual address = file offset.
+        let interp_offset = 64 + 3 * 56;
+        let strtab_offset = interp_offset + interpreter.len() as u64 + 1;
+        let dynamic_offset = strtab_offset + strtab.len() as u64;
+        dynamic.extend_from_slice(&DT_STRTAB.to_le_bytes());
+        dynamic.extend_from_slice(&strtab_offset.to_le_bytes());
+        dynamic.extend_from_slice(&[0; 16]);
+        let size = dynamic_offset + dynamic.len() as u64;
+
+        let mut elf = vec![0x7F, b'E', b'L', b'F', ELFCLASS64, ELFDATA2LSB, 1];
+        elf.resize(32, 0);
+        elf.extend_from_slice(&64u64.to_le_bytes());
+        elf.resize(54, 0);
+        elf.extend_from_slice(&56u16.to_le_bytes());
+        elf.extend_from_slice(&3u16.to_le_bytes());
+        elf.resize(64, 0);
+        for (kind, offset, filesz) in [
+            (PT_LOAD, 0, size),
+            (PT_INTERP, interp_offset, interpreter.len() as u64 + 1),
+            (PT_DYNAMIC, dynamic_offset, dynamic.len() as u64),
+        ] {
+            elf.extend_from_slice(&kind.to_le_bytes());
+            elf.extend_from_slice(&0u32.to_le_bytes());
+            for value in [offset, offset, offset, filesz, filesz, 0] {
+                elf.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        elf.extend_from_slice(interpreter.as_bytes());
+        elf.push(0);
+        elf.extend_from_slice(&strtab);
+        elf.extend_from_slice(&dynamic);
+        elf
+    }
+
+    #[test]
+    fn test_parse_dynamic_info() {
+        let elf = build_elf64(
+            "/lib64/ld-linux-x86-64.so.2",
+            &["libgcc_s.so.1", "libc.so.6"],
+        );
+        assert_eq!(
+            parse_dynamic_info(&elf).unwrap(),
+            DynamicInfo {
+                interpreter: Some("/lib64/ld-linux-x86-64.so.2".into()),
+                needed: vec!["libgcc_s.so.1".into(), "libc.so.6".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_dynamic_info_truncated() {
+        let elf = build_elf64("/lib/ld.so", &["libc.so.6"]);
+        for length in [3, 20, 100, 250] {
+            let got = parse_dynamic_info(&elf[..length]).unwrap_err();
+            assert_eq!(got.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_parse_dynamic_info_own_binary() {
+        let data = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let info = parse_dynamic_info(&data).unwrap();
+        if cfg!(target_os = "linux") && info.interpreter.is_some() {
+            assert!(info.needed.iter().any(|name| name.starts_with("libc.so")));
+        }
+    }
+}
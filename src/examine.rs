@@ -0,0 +1,543 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Examine the cpio archives in the initramfs cpio file.
+
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Result, Seek, Write};
+
+use crate::compression::Compression;
+use crate::header::{Header, CPIO_HEADER_LENGTH};
+use crate::seek_forward::SeekForward;
+use crate::{
+    align_to_4_bytes, decompress_buffered, read_magic_header, CountingReader, LOG_LEVEL_INFO,
+    LOG_LEVEL_WARNING,
+};
+
+/// Output format for examining the cpio archives
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExamineFormat {
+    /// Tab-separated offset and compression (plus the number of entries and
+    /// trailing bytes in verbose mode)
+    #[default]
+    Raw,
+    /// Table with column headers and sizes
+    Human,
+    /// JSON array with one object per cpio archive
+    Json,
+}
+
+impl std::str::FromStr for ExamineFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Self::Raw),
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "Unknown examine format '{}'. Expected 'raw', 'human', or 'json'.",
+                s
+            )),
+        }
+    }
+}
+
+/// Bytes of a cpio archive that are not file data
+#[derive(Debug, Default, PartialEq)]
+pub struct Overhead {
+    /// Headers including the names (and the trailer)
+    pub headers: u64,
+    /// Padding after the names to a multiple of four bytes
+    pub name_padding: u64,
+    /// Padding after the file data to a multiple of four bytes
+    pub data_padding: u64,
+    /// Additional NUL bytes after the names for aligning the file data
+    pub alignment: u64,
+}
+
+impl Overhead {
+    /// Add the overhead of the cpio object with the given header length.
+    fn add(&mut self, header: &Header, length: u64) {
+        let namesize = u32::try_from(header.filename.len() + 1).unwrap();
+        let name_padding = align_to_4_bytes(CPIO_HEADER_LENGTH.wrapping_add(namesize));
+        let headers = u64::from(CPIO_HEADER_LENGTH) + u64::from(namesize);
+        self.headers += headers;
+        self.name_padding += u64::from(name_padding);
+        self.data_padding += u64::from(align_to_4_bytes(header.filesize));
+        self.alignment += length - headers - u64::from(name_padding);
+    }
+
+    pub fn total(&self) -> u64 {
+        self.headers + self.name_padding + self.data_padding + self.alignment
+    }
+}
+
+/// Information about one cpio archive inside the initramfs cpio file.
+#[derive(Debug, PartialEq)]
+pub struct SegmentInfo {
+    /// Offset of the cpio archive in the file in bytes
+    pub offset: u64,
+    /// Size of the cpio archive in the file (including padding) in bytes
+    pub compressed_size: u64,
+    pub compression: Compression,
+    /// Size of the uncompressed cpio archive (up to the trailer) in bytes
+    pub uncompressed_size: u64,
+    /// Number of entries in the cpio archive (excluding the trailer)
+    pub entry_count: u64,
+    /// Number of bytes after the trailer up to the next cpio archive or the
+    /// end of the file (for compressed cpio archives: after decompression)
+    pub trailing_size: u64,
+    /// The trailing bytes are not only zero padding
+    pub trailing_data: bool,
+    /// Bytes spent on headers, padding, and alignment
+    pub overhead: Overhead,
+}
+
+/// Offset and compression of one cpio archive inside the initramfs cpio file.
+#[derive(Debug, PartialEq)]
+pub struct SegmentLocation {
+    /// Offset of the cpio archive in the file in bytes
+    pub offset: u64,
+    pub compression: Compression,
+    /// The data after this cpio archive is neither zero padding nor a cpio
+    /// archive (only detected for uncompressed cpio archives)
+    pub trailing_data: bool,
+}
+
+/// Read all entries up to the trailer and return the number of entries and
+/// the overhead.
+fn read_cpio_entries<R: Read + SeekForward>(file: &mut R) -> Result<(u64, Overhead)> {
+    let mut count = 0;
+    let mut overhead = Overhead::default();
+    loop {
+        let (header, length) = Header::read_with_length(file)?;
+        header.skip_file_content(file)?;
+        overhead.add(&header, length);
+        if header.filename == "TRAILER!!!" {
+            return Ok((count, overhead));
+        }
+        count += 1;
+    }
+}
+
+/// Read the remaining data and return its size and whether it contains
+/// non-zero bytes.
+fn read_trailing_data<R: Read>(file: &mut R) -> Result<(u64, bool)> {
+    let mut buffer = [0; 4096];
+    let mut size = 0;
+    let mut data = false;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        size += u64::try_from(read).unwrap();
+        data = data || buffer[..read].iter().any(|&b| b != 0);
+    }
+    Ok((size, data))
+}
+
+/// Examine the cpio archives in the initramfs cpio file.
+///
+/// Return the offset, size, compression, and number of entries of each cpio
+/// archive. The compressed cpio archive is decompressed to determine its
+/// uncompressed size and number of entries. Data after the last cpio
+/// archive that is neither zero padding nor a cpio archive is reported as
+/// trailing data of the last cpio archive.
+pub fn examine(archive: File, io_buffer_size: usize) -> Result<Vec<SegmentInfo>> {
+    let file_size = archive.metadata()?.len();
+    let mut file = BufReader::with_capacity(io_buffer_size, archive);
+    let mut segments: Vec<SegmentInfo> = Vec::new();
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(Err(e)) if e.kind() == ErrorKind::InvalidData && !segments.is_empty() => {
+                segments.last_mut().unwrap().trailing_data = true;
+                break;
+            }
+            Some(x) => x?,
+        };
+        let offset = file.stream_position()?;
+        if let Some(previous) = segments.last_mut() {
+            previous.compressed_size = offset - previous.offset;
+            previous.trailing_size = previous.compressed_size - previous.uncompressed_size;
+        }
+        if compression.is_uncompressed() {
+            let (entry_count, overhead) = read_cpio_entries(&mut file)?;
+            let uncompressed_size = file.stream_position()? - offset;
+            segments.push(SegmentInfo {
+                offset,
+                compressed_size: file_size - offset,
+                compression,
+                uncompressed_size,
+                entry_count,
+                trailing_size: file_size - offset - uncompressed_size,
+                trailing_data: false,
+                overhead,
+            });
+        } else {
+            let decompressed =
+                decompress_buffered(compression, file, io_buffer_size, LOG_LEVEL_WARNING)?;
+            let mut decompressed = CountingReader::new(decompressed);
+            let (entry_count, overhead) = read_cpio_entries(&mut decompressed)?;
+            let (trailing_size, trailing_data) = read_trailing_data(&mut decompressed.inner)?;
+            segments.push(SegmentInfo {
+                offset,
+                compressed_size: file_size - offset,
+                compression,
+                uncompressed_size: decompressed.count,
+                entry_count,
+                trailing_size,
+                trailing_data,
+                overhead,
+            });
+            break;
+        }
+    }
+    Ok(segments)
+}
+
+/// Locate the cpio archives in the initramfs cpio file without
+/// decompressing them.
+///
+/// Only the headers of the uncompressed cpio archives are read. A
+/// compressed cpio archive is the last one, because the decompressed data
+/// does not reveal where the compressed data ends.
+pub fn locate_segments(archive: File, io_buffer_size: usize) -> Result<Vec<SegmentLocation>> {
+    let mut file = BufReader::with_capacity(io_buffer_size, archive);
+    let mut segments: Vec<SegmentLocation> = Vec::new();
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(Err(e)) if e.kind() == ErrorKind::InvalidData && !segments.is_empty() => {
+                segments.last_mut().unwrap().trailing_data = true;
+                break;
+            }
+            Some(x) => x?,
+        };
+        segments.push(SegmentLocation {
+            offset: file.stream_position()?,
+            compression,
+            trailing_data: false,
+        });
+        if !compression.is_uncompressed() {
+            break;
+        }
+        read_cpio_entries(&mut file)?;
+    }
+    Ok(segments)
+}
+
+fn trailing_description(segment: &SegmentInfo) -> &'static str {
+    if segment.trailing_data {
+        "data"
+    } else {
+        "zero padding"
+    }
+}
+
+fn write_raw<W: Write>(out: &mut W, segments: &[SegmentInfo]) -> Result<()> {
+    for (i, segment) in segments.iter().enumerate() {
+        let overhead = &segment.overhead;
+        write!(
+            out,
+            "{}\t{}\t{} entries\t{} overhead bytes ({} headers, {} name padding, \
+             {} data padding, {} alignment)",
+            segment.offset,
+            segment.compression,
+            segment.entry_count,
+            overhead.total(),
+            overhead.headers,
+            overhead.name_padding,
+            overhead.data_padding,
+            overhead.alignment
+        )?;
+        if i + 1 == segments.len() && segment.trailing_size > 0 {
+            write!(
+                out,
+                "\t{} trailing bytes ({})",
+                segment.trailing_size,
+                trailing_description(segment)
+            )?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn write_human<W: Write>(out: &mut W, segments: &[SegmentInfo]) -> Result<()> {
+    writeln!(
+        out,
+        "{:>10}  {:<11}  {:>10}  {:>12}  {:>7}  {:>8}",
+        "Offset", "Compression", "Size", "Uncompressed", "Entries", "Overhead"
+    )?;
+    for segment in segments {
+        writeln!(
+            out,
+            "{:>10}  {:<11}  {:>10}  {:>12}  {:>7}  {:>8}",
+            segment.offset,
+            segment.compression.to_string(),
+            segment.compressed_size,
+            segment.uncompressed_size,
+            segment.entry_count,
+            segment.overhead.total()
+        )?;
+    }
+    if let Some(last) = segments.last() {
+        if last.trailing_size > 0 {
+            writeln!(
+                out,
+                "{} trailing bytes after the last cpio archive ({})",
+                last.trailing_size,
+                trailing_description(last)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn write_json<W: Write>(out: &mut W, segments: &[SegmentInfo]) -> Result<()> {
+    writeln!(out, "[")?;
+    for (i, segment) in segments.iter().enumerate() {
+        writeln!(
+            out,
+            "  {{\"offset\": {}, \"compression\": \"{}\", \"size\": {}, \
+             \"uncompressed_size\": {}, \"entries\": {}, \"trailing_size\": {}, \
+             \"trailing_data\": {}, \"overhead\": {{\"headers\": {}, \"name_padding\": {}, \
+             \"data_padding\": {}, \"alignment\": {}}}}}{}",
+            segment.offset,
+            segment.compression,
+            segment.compressed_size,
+            segment.uncompressed_size,
+            segment.entry_count,
+            segment.trailing_size,
+            segment.trailing_data,
+            segment.overhead.headers,
+            segment.overhead.name_padding,
+            segment.overhead.data_padding,
+            segment.overhead.alignment,
+            if i + 1 < segments.len() { "," } else { "" }
+        )?;
+    }
+    writeln!(out, "]")?;
+    Ok(())
+}
+
+/// Examine the cpio archives and print the result in the given format.
+///
+/// Warn on stderr if there is data after the trailer of the last cpio
+/// archive.
+pub fn examine_cpio_content<W: Write>(
+    archive: File,
+    out: &mut W,
+    format: ExamineFormat,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let last = if format == ExamineFormat::Raw && log_level < LOG_LEVEL_INFO {
+        // Printing only the offsets and compressions needs no decompression.
+        let segments = locate_segments(archive, io_buffer_size)?;
+        for segment in &segments {
+            writeln!(out, "{}\t{}", segment.offset, segment.compression)?;
+        }
+        segments.last().map(|s| (s.offset, s.trailing_data))
+    } else {
+        let segments = examine(archive, io_buffer_size)?;
+        match format {
+            ExamineFormat::Raw => write_raw(out, &segments)?,
+            ExamineFormat::Human => write_human(out, &segments)?,
+            ExamineFormat::Json => write_json(out, &segments)?,
+        }
+        segments.last().map(|s| (s.offset, s.trailing_data))
+    };
+    if let Some((offset, true)) = last {
+        if log_level >= LOG_LEVEL_WARNING {
+            writeln!(
+                std::io::stderr(),
+                "Warning: Found data after the trailer of the last cpio archive at offset {}.",
+                offset
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Print the total number of entries in all cpio archives.
+///
+/// In verbose mode, print the number, compression, and number of entries of
+/// each cpio archive before the total.
+pub fn count_cpio_content<W: Write>(
+    archive: File,
+    out: &mut W,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let segments = examine(archive, io_buffer_size)?;
+    let mut total = 0;
+    for (i, segment) in segments.iter().enumerate() {
+        if log_level >= LOG_LEVEL_INFO {
+            writeln!(
+                out,
+                "{} {} {} entries",
+                i + 1,
+                segment.compression,
+                segment.entry_count
+            )?;
+        }
+        total += segment.entry_count;
+    }
+    writeln!(out, "{}", total)?;
+    Ok(())
+}
+
+/// Print the number of cpio archives.
+///
+/// In verbose mode, print the number and compression of each cpio archive
+/// before the total. The cpio archives are not decompressed.
+pub fn count_cpio_archives<W: Write>(
+    archive: File,
+    out: &mut W,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let segments = locate_segments(archive, io_buffer_size)?;
+    if log_level >= LOG_LEVEL_INFO {
+        for (i, segment) in segments.iter().enumerate() {
+            writeln!(out, "{} {}", i + 1, segment.compression)?;
+        }
+    }
+    writeln!(out, "{}", segments.len())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DEFAULT_IO_BUFFER_SIZE;
+
+    #[test]
+    fn test_examine() {
+        let file = File::open("tests/gzip.cpio").expect("test cpio should be present");
+        let segments = examine(file, DEFAULT_IO_BUFFER_SIZE).unwrap();
+        assert_eq!(
+            segments,
+            vec![
+                SegmentInfo {
+                    offset: 0,
+                    compressed_size: 512,
+                    compression: Compression::Uncompressed,
+                    uncompressed_size: 480,
+                    entry_count: 3,
+                    trailing_size: 32,
+                    trailing_data: false,
+                    overhead: Overhead {
+                        headers: 468,
+                        name_padding: 4,
+                        data_padding: 0,
+                        alignment: 0,
+                    },
+                },
+                SegmentInfo {
+                    offset: 512,
+                    compressed_size: 192,
+                    compression: Compression::Gzip,
+                    uncompressed_size: 652,
+                    entry_count: 4,
+                    trailing_size: 372,
+                    trailing_data: false,
+                    overhead: Overhead {
+                        headers: 586,
+                        name_padding: 10,
+                        data_padding: 0,
+                        alignment: 0,
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_locate_segments() {
+        let file = File::open("tests/lzop.cpio").expect("test cpio should be present");
+        // Locating the cpio archives must not need the lzop program.
+        let segments = locate_segments(file, DEFAULT_IO_BUFFER_SIZE).unwrap();
+        assert_eq!(
+            segments,
+            vec![
+                SegmentLocation {
+                    offset: 0,
+                    compression: Compression::Uncompressed,
+                    trailing_data: false,
+                },
+                SegmentLocation {
+                    offset: 512,
+                    compression: Compression::Lzop,
+                    trailing_data: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_count_cpio_content_verbose() {
+        let file = File::open("tests/gzip.cpio").expect("test cpio should be present");
+        let mut output = Vec::new();
+        count_cpio_content(file, &mut output, LOG_LEVEL_INFO, DEFAULT_IO_BUFFER_SIZE).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "1 cpio 3 entries\n2 gzip 4 entries\n7\n"
+        );
+    }
+
+    #[test]
+    fn test_count_cpio_archives_verbose() {
+        let file = File::open("tests/lzop.cpio").expect("test cpio should be present");
+        let mut output = Vec::new();
+        count_cpio_archives(file, &mut output, LOG_LEVEL_INFO, DEFAULT_IO_BUFFER_SIZE).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "1 cpio\n2 lzop\n2\n");
+    }
+
+    #[test]
+    fn test_examine_cpio_content_human() {
+        let file = File::open("tests/single.cpio").expect("test cpio should be present");
+        let mut output = Vec::new();
+        examine_cpio_content(
+            file,
+            &mut output,
+            ExamineFormat::Human,
+            LOG_LEVEL_WARNING,
+            DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "    Offset  Compression        Size  Uncompressed  Entries  Overhead\n",
+                "         0  cpio                512           480        3       472\n",
+                "32 trailing bytes after the last cpio archive (zero padding)\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_examine_cpio_content_json() {
+        let file = File::open("tests/single.cpio").expect("test cpio should be present");
+        let mut output = Vec::new();
+        examine_cpio_content(
+            file,
+            &mut output,
+            ExamineFormat::Json,
+            LOG_LEVEL_WARNING,
+            DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[\n  {\"offset\": 0, \"compression\": \"cpio\", \"size\": 512, \
+             \"uncompressed_size\": 480, \"entries\": 3, \"trailing_size\": 32, \
+             \"trailing_data\": false, \"overhead\": {\"headers\": 468, \"name_padding\": 4, \
+             \"data_padding\": 0, \"alignment\": 0}}\n]\n"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Search the content of the regular files in the cpio archives.
+
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Write};
+
+use crate::header::*;
+use crate::metadata::FileType;
+use crate::seek_forward::SeekForward;
+use crate::{
+    align_to_4_bytes, check_decompressor_available, compile_patterns, decompress_buffered,
+    patterns_match, read_magic_header, MatchMode, Patterns, LOG_LEVEL_WARNING,
+};
+
+const CHUNK_SIZE: usize = 65536;
+
+/// Search the content for the pattern and return the offsets of all matches.
+///
+/// The content is read in chunks. The last bytes of each chunk are kept to
+/// find matches that span two chunks.
+fn find_matches<R: Read>(content: &mut R, pattern: &[u8]) -> Result<Vec<u64>> {
+    let mut offsets = Vec::new();
+    let mut window: Vec<u8> = Vec::with_capacity(CHUNK_SIZE + pattern.len());
+    // Offset of the first byte of the window in the content
+    let mut window_offset = 0;
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let read = content.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        window.extend_from_slice(&chunk[..read]);
+        for (i, candidate) in window.windows(pattern.len()).enumerate() {
+            if candidate == pattern {
+                offsets.push(window_offset + u64::try_from(i).unwrap());
+            }
+        }
+        let keep = window.len().min(pattern.len() - 1);
+        let drop = window.len() - keep;
+        window.drain(..drop);
+        window_offset += u64::try_from(drop).unwrap();
+    }
+    Ok(offsets)
+}
+
+fn read_cpio_and_grep<R: Read + SeekForward, W: Write>(
+    file: &mut R,
+    pattern: &[u8],
+    names: &Patterns,
+    out: &mut W,
+) -> Result<()> {
+    loop {
+        let header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            break;
+        }
+        if header.file_type() != Some(FileType::RegularFile)
+            || header.filesize == 0
+            || !patterns_match(names, &header.filename)
+        {
+            header.skip_file_content(file)?;
+            continue;
+        }
+        let mut content = file.take(header.filesize.into());
+        for offset in find_matches(&mut content, pattern)? {
+            writeln!(out, "{}:{}", header.filename, offset)?;
+        }
+        if content.limit() > 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Content of '{}' is truncated.", header.filename),
+            ));
+        }
+        file.seek_forward(align_to_4_bytes(header.filesize).into())?;
+    }
+    Ok(())
+}
+
+/// Search the content of the regular files in all cpio archives.
+///
+/// Print the name of the entry and the offset in its content for each
+/// occurrence of `pattern`. If `names` are given, only search the entries
+/// whose names (without leading `./` or `/`) match one of these patterns
+/// (interpreted according to `match_mode`).
+pub fn grep_cpio_content<W: Write>(
+    archive: File,
+    pattern: &[u8],
+    names: &[String],
+    match_mode: MatchMode,
+    out: &mut W,
+    io_buffer_size: usize,
+) -> Result<()> {
+    if pattern.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "The search pattern must not be empty.",
+        ));
+    }
+    let names = compile_patterns(names, match_mode)?;
+    let mut file = BufReader::with_capacity(io_buffer_size, archive);
+    check_decompressor_available(&mut file)?;
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if compression.is_uncompressed() {
+            read_cpio_and_grep(&mut file, pattern, &names, out)?;
+        } else {
+            let mut decompressed =
+                decompress_buffered(compression, file, io_buffer_size, LOG_LEVEL_WARNING)?;
+            read_cpio_and_grep(&mut decompressed, pattern, &names, out)?;
+            decompressed.finish()?;
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_matches_across_chunks() {
+        let mut content = vec![b'a'; CHUNK_SIZE - 2];
+        content.extend_from_slice(b"needle needle");
+        let got = find_matches(&mut content.as_slice(), b"needle").unwrap();
+        let offset = u64::try_from(CHUNK_SIZE).unwrap() - 2;
+        assert_eq!(got, vec![offset, offset + 7]);
+    }
+
+    #[test]
+    fn test_find_matches_overlapping() {
+        let got = find_matches(&mut b"aaaa".as_ref(), b"aa").unwrap();
+        assert_eq!(got, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_grep_cpio_content() {
+        let archive = File::open("tests/single.cpio").expect("test cpio should be present");
+        let mut output = Vec::new();
+        grep_cpio_content(
+            archive,
+            b"tent",
+            &["path/*".into()],
+            MatchMode::Glob,
+            &mut output,
+            crate::DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "path/file:3\n");
+    }
+
+    #[test]
+    fn test_grep_cpio_content_not_matching_name() {
+        let archive = File::open("tests/single.cpio").expect("test cpio should be present");
+        let mut output = Vec::new();
+        grep_cpio_content(
+            archive,
+            b"tent",
+            &["usr/*".into()],
+            MatchMode::Glob,
+            &mut output,
+            crate::DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        assert!(output.is_empty());
+    }
+}
@@ -2,16 +2,24 @@
 // SPDX-License-Identifier: ISC
 
 use std::fs::Permissions;
-use std::io::{Error, ErrorKind, Read, Result};
+use std::io::{Error, ErrorKind, Read, Result, Write};
 use std::os::unix::fs::PermissionsExt;
 
+use crate::metadata::{EntryMetadata, FileType};
+use crate::parser::{Event, Parser};
 use crate::seek_forward::SeekForward;
 use crate::{align_to_4_bytes, SeenFiles};
 
-const CPIO_HEADER_LENGTH: u32 = 110;
+pub(crate) const CPIO_HEADER_LENGTH: u32 = 110;
 const CPIO_MAGIC_NUMBER: [u8; 6] = *b"070701";
+/// Magic number of the new ASCII format with checksums (crc)
+const CPIO_CRC_MAGIC_NUMBER: [u8; 6] = *b"070702";
 
 const MODE_PERMISSION_MASK: u32 = 0o007_777;
+pub const MODE_SETUID: u32 = 0o004_000;
+pub const MODE_SETGID: u32 = 0o002_000;
+pub const MODE_STICKY: u32 = 0o001_000;
+pub const MODE_WRITE_OTHERS: u32 = 0o000_002;
 pub const MODE_FILETYPE_MASK: u32 = 0o770_000;
 pub const FILETYPE_FIFO: u32 = 0o010_000;
 pub const FILETYPE_CHARACTER_DEVICE: u32 = 0o020_000;
@@ -21,6 +29,10 @@ pub const FILETYPE_REGULAR_FILE: u32 = 0o100_000;
 pub const FILETYPE_SYMLINK: u32 = 0o120_000;
 pub const FILETYPE_SOCKET: u32 = 0o140_000;
 
+/// Header of a cpio entry
+///
+/// The fields mirror the cpio format and are not covered by the semver
+/// guarantees. Use `Header::metadata` for a stable interface.
 #[derive(Debug, PartialEq)]
 pub struct Header {
     pub ino: u32,
@@ -30,16 +42,20 @@ pub struct Header {
     pub nlink: u32,
     pub mtime: u32,
     pub filesize: u32,
-    major: u32,
-    minor: u32,
+    /// Major number of the device containing the file
+    pub major: u32,
+    /// Minor number of the device containing the file
+    pub minor: u32,
     pub rmajor: u32,
     pub rminor: u32,
+    /// Checksum of the file data (only set for the crc format 070702)
+    pub check: Option<u32>,
     pub filename: String,
 }
 
 impl Header {
     #![allow(clippy::too_many_arguments)]
-    #[cfg(test)]
+    /// Create a header (without device numbers and checksum).
     pub fn new(
         ino: u32,
         mode: u32,
@@ -62,6 +78,7 @@ impl Header {
             minor: 0,
             rmajor: 0,
             rminor: 0,
+            check: None,
             filename,
         }
     }
@@ -71,6 +88,16 @@ impl Header {
         u64::from(self.major) << 32 | u64::from(self.minor)
     }
 
+    /// Return the stable view of the metadata of the entry.
+    pub fn metadata(&self) -> EntryMetadata<'_> {
+        EntryMetadata::new(self)
+    }
+
+    /// Return the file type or `None` for an unknown file type.
+    pub fn file_type(&self) -> Option<FileType> {
+        FileType::from_mode(self.mode)
+    }
+
     pub fn mode_perm(&self) -> u32 {
         self.mode & MODE_PERMISSION_MASK
     }
@@ -78,14 +105,14 @@ impl Header {
     // ls-style ASCII representation of the mode
     pub fn mode_string(&self) -> [u8; 10] {
         [
-            match self.mode & MODE_FILETYPE_MASK {
-                FILETYPE_FIFO => b'p',
-                FILETYPE_CHARACTER_DEVICE => b'c',
-                FILETYPE_DIRECTORY => b'd',
-                FILETYPE_BLOCK_DEVICE => b'b',
-                FILETYPE_REGULAR_FILE => b'-',
-                FILETYPE_SYMLINK => b'l',
-                FILETYPE_SOCKET => b's',
+            match self.file_type() {
+                Some(FileType::Fifo) => b'p',
+                Some(FileType::CharacterDevice) => b'c',
+                Some(FileType::Directory) => b'd',
+                Some(FileType::BlockDevice) => b'b',
+                Some(FileType::RegularFile) => b'-',
+                Some(FileType::Symlink) => b'l',
+                Some(FileType::Socket) => b's',
                 _ => b'?',
             },
             if self.mode & 0o400 != 0 { b'r' } else { b'-' },
@@ -119,21 +146,52 @@ impl Header {
         PermissionsExt::from_mode(self.mode & MODE_PERMISSION_MASK)
     }
 
-    fn ino_and_dev(&self) -> u128 {
+    pub(crate) fn ino_and_dev(&self) -> u128 {
         u128::from(self.ino) << 64 | u128::from(self.dev())
     }
 
-    pub fn mark_seen(&self, seen_files: &mut SeenFiles) {
+    pub(crate) fn mark_seen(&self, seen_files: &mut SeenFiles) {
         seen_files.insert(self.ino_and_dev(), self.filename.clone());
     }
 
+    /// Read the header of the next cpio object (including its name).
+    ///
+    /// The file data is not read. Malformed headers are reported as errors
+    /// of kind `ErrorKind::InvalidData`.
     pub fn read<R: Read>(file: &mut R) -> Result<Self> {
+        Self::read_with_length(file).map(|(header, _)| header)
+    }
+
+    /// Read the header like `read` and return it with its length in bytes
+    /// (including the name and its padding).
+    pub(crate) fn read_with_length<R: Read>(file: &mut R) -> Result<(Self, u64)> {
+        let mut parser = Parser::header();
         let mut buffer = [0; CPIO_HEADER_LENGTH as usize];
         file.read_exact(&mut buffer)?;
-        check_begins_with_cpio_magic_header(&buffer)?;
+        let (_, Event::NeedMore(size)) = parser.next(&buffer)? else {
+            unreachable!("the name follows the fixed-size header");
+        };
+        let name = read_bytes(file, size.try_into().unwrap())?;
+        let length = u64::from(CPIO_HEADER_LENGTH) + u64::try_from(name.len()).unwrap();
+        match parser.next(&name)? {
+            (_, Event::Entry(header) | Event::Trailer(header)) => Ok((header, length)),
+            (_, event) => unreachable!("unexpected event {:?} after the name", event),
+        }
+    }
+
+    /// Parse the fixed-size part of the header (without the name).
+    ///
+    /// Return the header with an empty name and the size of the name
+    /// (including the NULL terminator) that follows the fixed-size part.
+    pub(crate) fn parse(buffer: &[u8; CPIO_HEADER_LENGTH as usize]) -> Result<(Self, u32)> {
+        let has_checksum = check_begins_with_cpio_magic_header(buffer)?;
         let namesize = hex_str_to_u32(&buffer[94..102])?;
-        let filename = read_filename(file, namesize)?;
-        Ok(Self {
+        let check = if has_checksum {
+            Some(hex_str_to_u32(&buffer[102..110])?)
+        } else {
+            None
+        };
+        let header = Self {
             ino: hex_str_to_u32(&buffer[6..14])?,
             mode: hex_str_to_u32(&buffer[14..22])?,
             uid: hex_str_to_u32(&buffer[22..30])?,
@@ -145,11 +203,69 @@ impl Header {
             minor: hex_str_to_u32(&buffer[70..78])?,
             rmajor: hex_str_to_u32(&buffer[78..86])?,
             rminor: hex_str_to_u32(&buffer[86..94])?,
-            filename,
-        })
+            check,
+            filename: String::new(),
+        };
+        Ok((header, namesize))
     }
 
-    pub fn read_only_filesize_and_filename<R: Read>(file: &mut R) -> Result<(u32, String)> {
+    /// Write the header (including its name and padding) in the newc format
+    /// (or the crc format if the header has a checksum) and return the number
+    /// of written bytes.
+    ///
+    /// If `data_align` is set, the name of regular files with content is
+    /// padded with NUL bytes so that the file data starts at a multiple of
+    /// `data_align` bytes (which needs to be a multiple of four). `offset`
+    /// is the position of the header in the output. The file data and its
+    /// padding to four bytes is left to the caller.
+    pub fn write<W: Write>(
+        &self,
+        out: &mut W,
+        offset: u64,
+        data_align: Option<u32>,
+    ) -> Result<u64> {
+        let magic_number = match self.check {
+            Some(_) => CPIO_CRC_MAGIC_NUMBER,
+            None => CPIO_MAGIC_NUMBER,
+        };
+        let mut name_length = u64::try_from(self.filename.len()).unwrap() + 1;
+        if let Some(align) = data_align
+            .filter(|_| self.filesize > 0 && self.file_type() == Some(FileType::RegularFile))
+        {
+            let data_offset = offset + u64::from(CPIO_HEADER_LENGTH) + name_length;
+            name_length += (u64::from(align) - data_offset % u64::from(align)) % u64::from(align);
+        }
+        let namesize = u32::try_from(name_length).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Entry name '{}' is too long.", self.filename),
+            )
+        })?;
+        out.write_all(&magic_number)?;
+        write!(
+            out,
+            "{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}",
+            self.ino,
+            self.mode,
+            self.uid,
+            self.gid,
+            self.nlink,
+            self.mtime,
+            self.filesize,
+            self.major,
+            self.minor,
+            self.rmajor,
+            self.rminor,
+            namesize,
+            self.check.unwrap_or(0),
+        )?;
+        out.write_all(self.filename.as_bytes())?;
+        let padding = padded_name_size(namesize)? - u64::try_from(self.filename.len()).unwrap();
+        std::io::copy(&mut std::io::repeat(0).take(padding), out)?;
+        Ok(u64::from(CPIO_HEADER_LENGTH) + u64::try_from(self.filename.len()).unwrap() + padding)
+    }
+
+    pub(crate) fn read_only_filesize_and_filename<R: Read>(file: &mut R) -> Result<(u32, String)> {
         let mut header = [0; CPIO_HEADER_LENGTH as usize];
         file.read_exact(&mut header)?;
         check_begins_with_cpio_magic_header(&header)?;
@@ -159,26 +275,35 @@ impl Header {
         Ok((filesize, filename))
     }
 
-    pub fn read_symlink_target<R: Read>(&self, file: &mut R) -> Result<String> {
+    pub(crate) fn read_symlink_target<R: Read>(&self, file: &mut R) -> Result<String> {
         let align = align_to_4_bytes(self.filesize);
-        let mut target_bytes = vec![0u8; (self.filesize + align).try_into().unwrap()];
-        file.read_exact(&mut target_bytes)?;
+        let mut target_bytes = read_bytes(file, u64::from(self.filesize) + u64::from(align))?;
         target_bytes.truncate(self.filesize.try_into().unwrap());
-        // TODO: propper name reading handling
-        let target = std::str::from_utf8(&target_bytes).unwrap();
-        Ok(target.into())
+        String::from_utf8(target_bytes).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Symlink target '{}' of '{}' is not valid UTF-8.",
+                    e.as_bytes().escape_ascii(),
+                    self.filename
+                ),
+            )
+        })
     }
 
-    pub fn skip_file_content<R: SeekForward>(&self, file: &mut R) -> Result<()> {
+    pub(crate) fn skip_file_content<R: SeekForward>(&self, file: &mut R) -> Result<()> {
         if self.filesize == 0 {
             return Ok(());
         };
-        let skip = self.filesize + align_to_4_bytes(self.filesize);
-        file.seek_forward(skip.into())?;
+        let skip = u64::from(self.filesize) + u64::from(align_to_4_bytes(self.filesize));
+        file.seek_forward(skip)?;
         Ok(())
     }
 
-    pub fn try_get_hard_link_target<'a>(&self, seen_files: &'a SeenFiles) -> Option<&'a String> {
+    pub(crate) fn try_get_hard_link_target<'a>(
+        &self,
+        seen_files: &'a SeenFiles,
+    ) -> Option<&'a String> {
         if self.nlink <= 1 {
             return None;
         }
@@ -186,18 +311,23 @@ impl Header {
     }
 }
 
-fn check_begins_with_cpio_magic_header(header: &[u8]) -> std::io::Result<()> {
-    if header[0..6] != CPIO_MAGIC_NUMBER {
-        return Err(Error::new(
-            ErrorKind::InvalidData,
-            format!(
-                "Invalid CPIO magic number '{}'. Expected {}",
-                &header[0..6].escape_ascii(),
-                std::str::from_utf8(&CPIO_MAGIC_NUMBER).unwrap(),
-            ),
-        ));
+/// Check the magic number and return whether the header carries a checksum.
+fn check_begins_with_cpio_magic_header(header: &[u8]) -> std::io::Result<bool> {
+    if header[0..6] == CPIO_MAGIC_NUMBER {
+        return Ok(false);
+    }
+    if header[0..6] == CPIO_CRC_MAGIC_NUMBER {
+        return Ok(true);
     }
-    Ok(())
+    Err(Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "Invalid CPIO magic number '{}'. Expected {} or {}",
+            &header[0..6].escape_ascii(),
+            std::str::from_utf8(&CPIO_MAGIC_NUMBER).unwrap(),
+            std::str::from_utf8(&CPIO_CRC_MAGIC_NUMBER).unwrap(),
+        ),
+    ))
 }
 
 fn hex_str_to_u32(bytes: &[u8]) -> Result<u32> {
@@ -219,24 +349,66 @@ fn hex_str_to_u32(bytes: &[u8]) -> Result<u32> {
     }
 }
 
+/// Read the given number of bytes.
+///
+/// In contrast to `read_exact`, the buffer grows with the read data.
+/// So a bogus size in a corrupt header does not allocate gigabytes upfront.
+fn read_bytes<R: Read>(file: &mut R, size: u64) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    file.take(size).read_to_end(&mut bytes)?;
+    if u64::try_from(bytes.len()).unwrap() != size {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "failed to fill whole buffer",
+        ));
+    }
+    Ok(bytes)
+}
+
 fn read_filename<R: Read>(file: &mut R, namesize: u32) -> Result<String> {
-    let header_align = align_to_4_bytes(CPIO_HEADER_LENGTH + namesize);
-    let mut filename_bytes = vec![0u8; (namesize + header_align).try_into().unwrap()];
-    let filename_length: usize = (namesize - 1).try_into().unwrap();
-    file.read_exact(&mut filename_bytes)?;
-    if filename_bytes[filename_length] != 0 {
+    let filename_bytes = read_bytes(file, padded_name_size(namesize)?)?;
+    decode_filename(filename_bytes, namesize)
+}
+
+/// Return the size of the name including the padding after it.
+pub(crate) fn padded_name_size(namesize: u32) -> Result<u64> {
+    if namesize == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Entry name size must not be zero (missing NULL terminator).",
+        ));
+    }
+    // The sum modulo 4 is correct even if the addition wraps around.
+    let header_align = align_to_4_bytes(CPIO_HEADER_LENGTH.wrapping_add(namesize));
+    Ok(u64::from(namesize) + u64::from(header_align))
+}
+
+/// Decode the name (with NULL terminator and padding) of an entry.
+///
+/// Additional NUL bytes before the NULL terminator (used for aligning the
+/// file data) are ignored like the kernel does.
+pub(crate) fn decode_filename(mut filename_bytes: Vec<u8>, namesize: u32) -> Result<String> {
+    let namesize: usize = namesize.try_into().unwrap();
+    if filename_bytes[namesize - 1] != 0 {
         return Err(Error::new(
             ErrorKind::InvalidData,
             format!(
                 "Entry name '{:?}' is not NULL-terminated",
-                &filename_bytes[0..filename_length]
+                &filename_bytes[0..namesize - 1]
             ),
         ));
     }
+    let filename_length = filename_bytes.iter().position(|&b| b == 0).unwrap();
     filename_bytes.truncate(filename_length);
-    // TODO: propper name reading handling
-    let filename = std::str::from_utf8(&filename_bytes).unwrap();
-    Ok(filename.to_string())
+    String::from_utf8(filename_bytes).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Entry name '{}' is not valid UTF-8.",
+                e.as_bytes().escape_ascii()
+            ),
+        )
+    })
 }
 
 #[cfg(test)]
@@ -264,11 +436,38 @@ mod tests {
                 minor: 0,
                 rmajor: 0,
                 rminor: 0,
+                check: None,
                 filename: "path/file".into()
             }
         )
     }
 
+    #[test]
+    fn test_header_write() {
+        let cpio_data = b"07070100000002000081B4000003E8000007D000000001\
+            661BE5C600000008000000000000000000000000000000000000000A00000000\
+            path/file\0";
+        let header = Header::read(&mut cpio_data.as_ref()).unwrap();
+        let mut written = Vec::new();
+        header.write(&mut written, 0, None).unwrap();
+        assert_eq!(written, cpio_data);
+    }
+
+    #[test]
+    fn test_header_write_data_align() {
+        let cpio_data = b"07070100000002000081B4000003E8000007D000000001\
+            661BE5C600000008000000000000000000000000000000000000000A00000000\
+            path/file\0";
+        let header = Header::read(&mut cpio_data.as_ref()).unwrap();
+        let mut written = Vec::new();
+        let size = header.write(&mut written, 4, Some(512)).unwrap();
+        assert_eq!(size, 508);
+        assert_eq!(written.len(), 508);
+        assert_eq!(&written[94..102], b"0000018E");
+        let read = Header::read(&mut written.as_slice()).unwrap();
+        assert_eq!(read.filename, "path/file");
+    }
+
     #[test]
     fn test_header_read_invalid_magic_number() {
         let invalid_data = b"abc\tefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ\
@@ -277,7 +476,50 @@ mod tests {
         assert_eq!(got.kind(), ErrorKind::InvalidData);
         assert_eq!(
             got.to_string(),
-            "Invalid CPIO magic number 'abc\\tef'. Expected 070701"
+            "Invalid CPIO magic number 'abc\\tef'. Expected 070701 or 070702"
+        );
+    }
+
+    #[test]
+    fn test_header_read_crc_format() {
+        let cpio_data = b"07070200000002000081B4000003E8000007D000000001\
+            661BE5C600000008000000000000000000000000000000000000000A00000305\
+            path/file\0content\0";
+        let header = Header::read(&mut cpio_data.as_ref()).unwrap();
+        assert_eq!(header.check, Some(0x305));
+        assert_eq!(header.filename, "path/file");
+    }
+
+    /// Return a cpio header with all fields zero except the name size.
+    fn header_with_namesize(namesize: u32) -> Vec<u8> {
+        format!("070701{}{:08X}00000000", "0".repeat(88), namesize).into_bytes()
+    }
+
+    #[test]
+    fn test_header_read_huge_namesize() {
+        let mut cpio_data = header_with_namesize(0xFFFF_FFFF);
+        cpio_data.extend(b"path\0");
+        let got = Header::read(&mut cpio_data.as_slice()).unwrap_err();
+        assert_eq!(got.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_header_read_name_not_utf8() {
+        let mut cpio_data = header_with_namesize(4);
+        cpio_data.extend(b"a\xffb\0\0\0");
+        let got = Header::read(&mut cpio_data.as_slice()).unwrap_err();
+        assert_eq!(got.kind(), ErrorKind::InvalidData);
+        assert_eq!(got.to_string(), "Entry name 'a\\xffb' is not valid UTF-8.");
+    }
+
+    #[test]
+    fn test_header_read_zero_namesize() {
+        let cpio_data = header_with_namesize(0);
+        let got = Header::read(&mut cpio_data.as_slice()).unwrap_err();
+        assert_eq!(got.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            got.to_string(),
+            "Entry name size must not be zero (missing NULL terminator)."
         );
     }
 
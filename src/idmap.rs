@@ -0,0 +1,191 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Translation of the owners of the extracted entries
+//!
+//! Rootless container tooling extracts initrds inside a user namespace,
+//! where only a range of user and group IDs is mapped. Changing the owner
+//! to an unmapped ID fails. Alternatively the IDs can be shifted by a fixed
+//! offset (e.g. to the subordinate IDs of a container).
+
+use std::io::{Error, ErrorKind, Result};
+
+/// Range of IDs mapped into the user namespace (see user_namespaces(7))
+#[derive(Debug, PartialEq)]
+pub struct IdRange {
+    inside: u32,
+    count: u32,
+}
+
+impl IdRange {
+    fn contains(&self, id: u32) -> bool {
+        id >= self.inside && id - self.inside < self.count
+    }
+}
+
+/// Parse the content of /proc/self/uid_map or /proc/self/gid_map.
+fn parse_id_map(content: &str) -> std::result::Result<Vec<IdRange>, String> {
+    content
+        .lines()
+        .map(|line| {
+            let fields: Vec<u32> = line
+                .split_whitespace()
+                .map(|field| field.parse().map_err(|e| format!("{}: '{}'", e, line)))
+                .collect::<std::result::Result<_, _>>()?;
+            match fields[..] {
+                [inside, _outside, count] => Ok(IdRange { inside, count }),
+                _ => Err(format!("Expected three fields: '{}'", line)),
+            }
+        })
+        .collect()
+}
+
+fn read_id_map(path: &str) -> std::result::Result<Vec<IdRange>, String> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    parse_id_map(&content).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+/// How to translate the user and group IDs recorded in the archive
+#[derive(Debug, PartialEq)]
+pub enum IdMap {
+    /// Add the offset to all IDs.
+    Offset(u32),
+    /// Keep the IDs that are mapped in the user namespace of the process
+    /// and replace the unmapped ones by the IDs of the process.
+    UserNamespace {
+        uids: Vec<IdRange>,
+        gids: Vec<IdRange>,
+        uid: u32,
+        gid: u32,
+    },
+}
+
+impl IdMap {
+    /// Read the ID mappings of the user namespace of the process.
+    fn from_user_namespace() -> std::result::Result<Self, String> {
+        Ok(Self::UserNamespace {
+            uids: read_id_map("/proc/self/uid_map")?,
+            gids: read_id_map("/proc/self/gid_map")?,
+            uid: unsafe { libc::geteuid() },
+            gid: unsafe { libc::getegid() },
+        })
+    }
+
+    /// Translate the user ID recorded in the archive.
+    pub(crate) fn map_uid(&self, uid: u32) -> Result<u32> {
+        match self {
+            Self::Offset(offset) => add_offset(uid, *offset),
+            Self::UserNamespace { uids, uid: own, .. } => Ok(map_to_namespace(uids, uid, *own)),
+        }
+    }
+
+    /// Translate the group ID recorded in the archive.
+    pub(crate) fn map_gid(&self, gid: u32) -> Result<u32> {
+        match self {
+            Self::Offset(offset) => add_offset(gid, *offset),
+            Self::UserNamespace { gids, gid: own, .. } => Ok(map_to_namespace(gids, gid, *own)),
+        }
+    }
+}
+
+fn add_offset(id: u32, offset: u32) -> Result<u32> {
+    id.checked_add(offset).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("ID {} plus offset {} exceeds the maximum ID.", id, offset),
+        )
+    })
+}
+
+fn map_to_namespace(ranges: &[IdRange], id: u32, own: u32) -> u32 {
+    if ranges.iter().any(|range| range.contains(id)) {
+        id
+    } else {
+        own
+    }
+}
+
+impl std::str::FromStr for IdMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s == "auto" {
+            return Self::from_user_namespace();
+        }
+        match s.parse() {
+            Ok(offset) => Ok(Self::Offset(offset)),
+            Err(_) => Err(format!(
+                "Unknown ID map '{}'. Expected 'auto' or an offset.",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_id_map() {
+        let content = "         0       1000          1\n         1     100000      65536\n";
+        assert_eq!(
+            parse_id_map(content),
+            Ok(vec![
+                IdRange {
+                    inside: 0,
+                    count: 1
+                },
+                IdRange {
+                    inside: 1,
+                    count: 65536
+                },
+            ])
+        );
+        assert!(parse_id_map("0 1000\n").is_err());
+    }
+
+    #[test]
+    fn test_idmap_from_str() {
+        assert_eq!("100000".parse(), Ok(IdMap::Offset(100000)));
+        assert_eq!(
+            "container".parse::<IdMap>(),
+            Err("Unknown ID map 'container'. Expected 'auto' or an offset.".into())
+        );
+        if std::path::Path::new("/proc/self/uid_map").exists() {
+            assert!(matches!("auto".parse(), Ok(IdMap::UserNamespace { .. })));
+        }
+    }
+
+    #[test]
+    fn test_map_offset() {
+        let idmap = IdMap::Offset(100000);
+        assert_eq!(idmap.map_uid(0).unwrap(), 100000);
+        assert_eq!(idmap.map_gid(1000).unwrap(), 101000);
+        assert_eq!(
+            idmap.map_uid(u32::MAX).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_map_user_namespace() {
+        let idmap = IdMap::UserNamespace {
+            uids: vec![IdRange {
+                inside: 0,
+                count: 1,
+            }],
+            gids: vec![IdRange {
+                inside: 0,
+                count: 10,
+            }],
+            uid: 0,
+            gid: 0,
+        };
+        assert_eq!(idmap.map_uid(0).unwrap(), 0);
+        assert_eq!(idmap.map_uid(1000).unwrap(), 0);
+        assert_eq!(idmap.map_gid(5).unwrap(), 5);
+        assert_eq!(idmap.map_gid(10).unwrap(), 0);
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Export a machine-readable catalog of the content of the cpio archives.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{sink, BufReader, Read, Result, Write};
+
+use crate::header::*;
+use crate::metadata::FileType;
+use crate::objects::HashingWriter;
+use crate::seek_forward::SeekForward;
+use crate::{
+    align_to_4_bytes, check_decompressor_available, decompress_buffered, read_magic_header,
+    CountingReader, LOG_LEVEL_WARNING,
+};
+
+struct IndexEntry {
+    header: Header,
+    segment: usize,
+    offset: u64,
+    hash: Option<String>,
+    target: Option<String>,
+}
+
+pub(crate) fn file_type(mode: u32) -> &'static str {
+    match FileType::from_mode(mode) {
+        Some(FileType::Fifo) => "fifo",
+        Some(FileType::CharacterDevice) => "character-device",
+        Some(FileType::Directory) => "directory",
+        Some(FileType::BlockDevice) => "block-device",
+        Some(FileType::RegularFile) => "file",
+        Some(FileType::Symlink) => "symlink",
+        Some(FileType::Socket) => "socket",
+        _ => "unknown",
+    }
+}
+
+/// Quote the string for JSON.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn json_optional_string(text: &Option<String>) -> String {
+    match text {
+        Some(text) => json_string(text),
+        None => "null".into(),
+    }
+}
+
+fn read_cpio<R: Read + SeekForward>(
+    file: &mut CountingReader<R>,
+    segment: usize,
+    entries: &mut Vec<IndexEntry>,
+) -> Result<()> {
+    let first = entries.len();
+    let mut hard_links = HashMap::new();
+    loop {
+        let offset = file.count;
+        let header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            break;
+        }
+        let mut hash = None;
+        let mut target = None;
+        match header.file_type() {
+            Some(FileType::RegularFile) if header.nlink <= 1 || header.filesize > 0 => {
+                let mut writer = HashingWriter::new(sink());
+                std::io::copy(&mut file.take(header.filesize.into()), &mut writer)?;
+                file.seek_forward(align_to_4_bytes(header.filesize).into())?;
+                let sha256 = writer.finish()?;
+                if header.nlink > 1 {
+                    hard_links.insert(header.ino_and_dev(), sha256.clone());
+                }
+                hash = Some(sha256);
+            }
+            Some(FileType::Symlink) => target = Some(header.read_symlink_target(file)?),
+            _ => header.skip_file_content(file)?,
+        }
+        entries.push(IndexEntry {
+            header,
+            segment,
+            offset,
+            hash,
+            target,
+        });
+    }
+    // Each cpio archive numbers its inodes independently. So resolve the
+    // hard-links without data within this cpio archive only.
+    for entry in &mut entries[first..] {
+        if entry.hash.is_none() && entry.header.file_type() == Some(FileType::RegularFile) {
+            entry.hash = hard_links.get(&entry.header.ino_and_dev()).cloned();
+        }
+    }
+    Ok(())
+}
+
+fn write_entry<W: Write>(out: &mut W, entry: &IndexEntry) -> Result<()> {
+    let header = &entry.header;
+    write!(
+        out,
+        "{{\"name\": {}, \"type\": \"{}\", \"size\": {}, \"mode\": \"{:04o}\", \
+         \"uid\": {}, \"gid\": {}, \"mtime\": {}, \"hash\": {}, ",
+        json_string(&header.filename),
+        file_type(header.mode),
+        header.filesize,
+        header.mode_perm(),
+        header.uid,
+        header.gid,
+        header.mtime,
+        json_optional_string(&entry.hash),
+    )?;
+    if entry.target.is_some() {
+        write!(out, "\"target\": {}, ", json_optional_string(&entry.target))?;
+    }
+    write!(
+        out,
+        "\"segment\": {}, \"offset\": {}}}",
+        entry.segment, entry.offset
+    )
+}
+
+/// Write a JSON catalog of all entries in the cpio archives.
+///
+/// Each entry lists its name, type, size, permission, owner, modification
+/// time, the SHA-256 hash of regular files, the target of symlinks, the
+/// number of the cpio archive (starting at 1), and the offset of the entry
+/// header in the uncompressed cpio archive. Hard-links without data get the
+/// hash of the link that carries the data.
+pub fn export_index<W: Write>(archive: File, out: &mut W, io_buffer_size: usize) -> Result<()> {
+    let mut file = BufReader::with_capacity(io_buffer_size, archive);
+    check_decompressor_available(&mut file)?;
+    let mut entries = Vec::new();
+    let mut segment = 1;
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if compression.is_uncompressed() {
+            let mut counting = CountingReader::new(file);
+            read_cpio(&mut counting, segment, &mut entries)?;
+            file = counting.inner;
+        } else {
+            let decompressed =
+                decompress_buffered(compression, file, io_buffer_size, LOG_LEVEL_WARNING)?;
+            let mut counting = CountingReader::new(decompressed);
+            read_cpio(&mut counting, segment, &mut entries)?;
+            counting.inner.finish()?;
+            break;
+        }
+        segment += 1;
+    }
+
+    writeln!(out, "[")?;
+    for (i, entry) in entries.iter().enumerate() {
+        write!(out, "  ")?;
+        write_entry(out, entry)?;
+        writeln!(out, "{}", if i + 1 < entries.len() { "," } else { "" })?;
+    }
+    writeln!(out, "]")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::cpio_object;
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a\"b\\c\n\u{1b}"), "\"a\\\"b\\\\c\\n\\u001b\"");
+    }
+
+    #[test]
+    fn test_export_index() {
+        let archive = File::open("tests/single.cpio").expect("test cpio should be present");
+        let mut output = Vec::new();
+        export_index(archive, &mut output, crate::DEFAULT_IO_BUFFER_SIZE).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[\n\
+            \x20 {\"name\": \".\", \"type\": \"directory\", \"size\": 0, \"mode\": \"0775\", \
+            \"uid\": 1000, \"gid\": 1000, \"mtime\": 1713104326, \"hash\": null, \
+            \"segment\": 1, \"offset\": 0},\n\
+            \x20 {\"name\": \"path\", \"type\": \"directory\", \"size\": 0, \"mode\": \"0775\", \
+            \"uid\": 1000, \"gid\": 1000, \"mtime\": 1713104326, \"hash\": null, \
+            \"segment\": 1, \"offset\": 112},\n\
+            \x20 {\"name\": \"path/file\", \"type\": \"file\", \"size\": 8, \"mode\": \"0664\", \
+            \"uid\": 1000, \"gid\": 1000, \"mtime\": 1713104326, \
+            \"hash\": \"434728a410a78f56fc1b5899c3593436e61ab0c731e9072d95e96db290205e53\", \
+            \"segment\": 1, \"offset\": 228}\n\
+            ]\n"
+        );
+    }
+
+    #[test]
+    fn test_export_index_colliding_inodes() {
+        // Each cpio archive numbers its inodes independently.
+        let mut cpio = Vec::new();
+        for (name, data) in [("a", b"AAAA\n".as_slice()), ("b", b"BB\n".as_slice())] {
+            cpio.extend(cpio_object(1, 0o100_644, 2, &format!("{}1", name), b""));
+            cpio.extend(cpio_object(1, 0o100_644, 2, &format!("{}2", name), data));
+            cpio.extend(cpio_object(0, 0, 1, "TRAILER!!!", b""));
+        }
+        let directory = crate::libc::make_temp_dir().unwrap();
+        let archive = directory.join("archive.cpio");
+        std::fs::write(&archive, cpio).unwrap();
+        let mut output = Vec::new();
+        export_index(
+            File::open(archive).unwrap(),
+            &mut output,
+            crate::DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        std::fs::remove_dir_all(directory).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let hashes: Vec<&str> = output
+            .split("\"hash\": \"")
+            .skip(1)
+            .map(|rest| &rest[..64])
+            .collect();
+        let hash_a = "4677942dfa3e74b5dea7484661a2485bb73ba422eb72d311fdb39372c019c615";
+        let hash_b = "68cd080c537d3f1355f357189f74f3fe1c68dd13cf406a84aedc934c90a0df31";
+        assert_eq!(hashes, vec![hash_a, hash_a, hash_b, hash_b]);
+    }
+}
@@ -3,31 +3,135 @@
 
 use std::collections::{BTreeMap, HashMap};
 use std::fs::{
-    create_dir, hard_link, remove_file, set_permissions, symlink_metadata, File, OpenOptions,
+    create_dir, create_dir_all, hard_link, remove_dir_all, remove_file, set_permissions,
+    symlink_metadata, File, OpenOptions, Permissions,
 };
 use std::io::prelude::*;
+use std::io::BufReader;
 use std::io::Error;
 use std::io::ErrorKind;
 use std::io::Result;
 use std::io::SeekFrom;
-use std::os::unix::fs::{chown, fchown, lchown, symlink};
-use std::process::ChildStdout;
-use std::process::Command;
-use std::process::Stdio;
+use std::os::unix::fs::{chown, fchown, lchown, symlink, FileExt, PermissionsExt};
 use std::time::SystemTime;
 
+#[cfg(feature = "async")]
+pub use crate::async_reader::AsyncCpioReader;
+pub use crate::boot::check_boot;
+pub use crate::cat::cat_cpio_content;
+pub use crate::compression::{set_decompress_memlimit, Compression};
+pub use crate::concat::concatenate_cpio_files;
+pub use crate::examine::{
+    count_cpio_archives, count_cpio_content, examine, examine_cpio_content, locate_segments,
+    ExamineFormat, Overhead, SegmentInfo, SegmentLocation,
+};
+pub use crate::grep::grep_cpio_content;
+pub use crate::header::Header;
+pub use crate::idmap::IdMap;
+pub use crate::index::export_index;
+pub use crate::libraries::check_libraries;
+pub use crate::list_format::{list_cpio_content_formatted, ListFormat};
+pub use crate::manifest::diff_manifest;
+pub use crate::metadata::{EntryMetadata, FileType};
+pub use crate::objects::extract_objects;
+pub use crate::parser::{Event, Parser};
+pub use crate::privilege::drop_capabilities;
+pub use crate::quoting::QuotingStyle;
+pub use crate::rewrite::{
+    count_matching_entries, delete_entries, rename_entries, replace_entry, replace_entry_in_place,
+    set_mtime, set_mtime_in_place, DataAlign,
+};
+pub use crate::signals::{install_interrupt_handlers, received_signal};
+pub use crate::symlinks::check_symlinks;
+pub use crate::verify::verify_cpio_content;
+
+use crate::compression::Decompressor;
 use crate::header::*;
-use crate::libc::{set_modified, strftime_local};
+use crate::libc::{fadvise, mknod, set_modified, set_symlink_permissions, Advice};
+use crate::localtime::LocalTime;
 use crate::seek_forward::SeekForward;
+use crate::signals::check_interrupted;
+use crate::verify::{check_checksum, ChecksumReader};
+use crate::zstd_seekable::{read_seek_table, SeekableZstdReader};
 
+#[cfg(feature = "async")]
+mod async_reader;
+mod boot;
+mod cat;
+mod compression;
+mod concat;
+mod elf;
+mod examine;
+mod grep;
 mod header;
+mod idmap;
+mod index;
 mod libc;
+mod libraries;
+mod list_format;
+mod localtime;
+mod manifest;
+mod metadata;
+mod objects;
+mod parser;
+mod passwd;
+mod privilege;
+mod quoting;
+mod rewrite;
 mod seek_forward;
+mod signals;
+mod symlinks;
+mod verify;
+mod zstd_seekable;
 
+pub const LOG_LEVEL_ERROR: u32 = 3;
 pub const LOG_LEVEL_WARNING: u32 = 5;
 pub const LOG_LEVEL_INFO: u32 = 7;
 pub const LOG_LEVEL_DEBUG: u32 = 8;
 
+/// Log level selected by name (e.g. in the configuration)
+///
+/// The levels are ordered by verbosity. Use `u32::from` to get the
+/// corresponding `LOG_LEVEL_*` constant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "warning" => Ok(Self::Warning),
+            "info" => Ok(Self::Info),
+            "debug" => Ok(Self::Debug),
+            _ => Err(format!(
+                "Unknown log level '{}'. Expected 'error', 'warning', 'info', or 'debug'.",
+                s
+            )),
+        }
+    }
+}
+
+impl From<LogLevel> for u32 {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LOG_LEVEL_ERROR,
+            LogLevel::Warning => LOG_LEVEL_WARNING,
+            LogLevel::Info => LOG_LEVEL_INFO,
+            LogLevel::Debug => LOG_LEVEL_DEBUG,
+        }
+    }
+}
+
+/// Default size of the read buffer for the cpio archive and decompressor output.
+pub const DEFAULT_IO_BUFFER_SIZE: usize = 65536;
+
 struct CpioFilenameReader<'a, R: Read + SeekForward> {
     file: &'a mut R,
 }
@@ -49,6 +153,86 @@ impl<'a, R: Read + SeekForward> Iterator for CpioFilenameReader<'a, R> {
     }
 }
 
+/// Reader for a shared file that tracks its own read position.
+///
+/// The file is read with positioned reads (pread) without modifying the file
+/// offset. This allows multiple threads to read different parts of the same
+/// file concurrently.
+struct FileAtReader<'a> {
+    file: &'a File,
+    position: u64,
+}
+
+impl<'a> FileAtReader<'a> {
+    fn new(file: &'a File, position: u64) -> Self {
+        Self { file, position }
+    }
+}
+
+impl Read for FileAtReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.file.read_at(buf, self.position)?;
+        self.position += u64::try_from(read).unwrap();
+        Ok(read)
+    }
+}
+
+impl SeekForward for FileAtReader<'_> {
+    fn seek_forward(&mut self, offset: u64) -> Result<()> {
+        self.position += offset;
+        Ok(())
+    }
+}
+
+impl Seek for FileAtReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.file.metadata()?.len().checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// Translate user ID (UID) to user name.
+///
+/// Use NSS and fall back to parsing /etc/passwd in case NSS does not know
+/// the user or fails (e.g. in static binaries). The `passwd-files` feature
+/// skips NSS.
+fn lookup_user_name(uid: u32) -> Result<Option<String>> {
+    if cfg!(feature = "passwd-files") {
+        return passwd::passwd_name(uid);
+    }
+    match libc::getpwuid_name(uid) {
+        Ok(Some(name)) => Ok(Some(name)),
+        Ok(None) => passwd::passwd_name(uid),
+        Err(e) => passwd::passwd_name(uid).or(Err(e)),
+    }
+}
+
+/// Translate group ID (GID) to group name.
+///
+/// Use NSS and fall back to parsing /etc/group in case NSS does not know
+/// the group or fails (e.g. in static binaries). The `passwd-files` feature
+/// skips NSS.
+fn lookup_group_name(gid: u32) -> Result<Option<String>> {
+    if cfg!(feature = "passwd-files") {
+        return passwd::group_name(gid);
+    }
+    match libc::getgrgid_name(gid) {
+        Ok(Some(name)) => Ok(Some(name)),
+        Ok(None) => passwd::group_name(gid),
+        Err(e) => passwd::group_name(gid).or(Err(e)),
+    }
+}
+
 struct UserGroupCache {
     user_cache: HashMap<u32, Option<String>>,
     group_cache: HashMap<u32, Option<String>>,
@@ -67,7 +251,7 @@ impl UserGroupCache {
         match self.user_cache.get(&uid) {
             Some(name) => Ok(name.clone()),
             None => {
-                let name = libc::getpwuid_name(uid)?;
+                let name = lookup_user_name(uid)?;
                 self.user_cache.insert(uid, name.clone());
                 Ok(name)
             }
@@ -79,7 +263,7 @@ impl UserGroupCache {
         match self.group_cache.get(&gid) {
             Some(name) => Ok(name.clone()),
             None => {
-                let name = libc::getgrgid_name(gid)?;
+                let name = lookup_group_name(gid)?;
                 self.group_cache.insert(gid, name.clone());
                 Ok(name)
             }
@@ -88,37 +272,419 @@ impl UserGroupCache {
 }
 
 /// Format the time in a similar way to coreutils' ls command.
-fn format_time(timestamp: u32, now: i64) -> Result<String> {
+fn format_time(timestamp: u32, local_time: &LocalTime) -> Result<String> {
     // Logic from coreutils ls command:
     // Consider a time to be recent if it is within the past six months.
     // A Gregorian year has 365.2425 * 24 * 60 * 60 == 31556952 seconds
     // on the average.
-    let recent = now - i64::from(timestamp) <= 15778476;
-    if recent {
-        strftime_local(b"%b %e %H:%M\0", timestamp)
-    } else {
-        strftime_local(b"%b %e  %Y\0", timestamp)
+    let recent = local_time.now - i64::from(timestamp) <= 15778476;
+    local_time.format(timestamp.into(), recent)
+}
+
+/// Options for listing the cpio archives
+#[derive(Debug, Default)]
+pub struct ListOptions {
+    /// Number of cpio archives to list in parallel.
+    pub jobs: usize,
+    /// How to quote the names of the entries.
+    pub quoting_style: QuotingStyle,
+    /// Terminate the names with a NUL byte instead of a newline.
+    pub null_terminated: bool,
+    /// Only list the entries whose names match one of these patterns (or
+    /// all entries if there are no patterns).
+    pub names: Vec<String>,
+    /// How the name patterns select the entries.
+    pub match_mode: MatchMode,
+}
+
+impl ListOptions {
+    /// Return the terminator of the listed entries.
+    pub(crate) fn terminator(&self) -> &'static str {
+        if self.null_terminated {
+            "\0"
+        } else {
+            "\n"
+        }
+    }
+}
+
+/// Options for extracting the cpio archives
+#[derive(Debug, Default)]
+pub struct ExtractOptions {
+    /// Extract into this directory instead of the working directory.
+    pub directory: Option<String>,
+    /// Set the owner of the extracted files to the one recorded in the archive.
+    pub preserve_permissions: bool,
+    /// Extract the cpio archives into separate directories (using the given
+    /// name plus an incrementing number) below the target directory.
+    pub subdir: Option<String>,
+    /// Number of cpio archives to extract in parallel (only used together
+    /// with `subdir`, because the cpio archives are independent trees then).
+    pub jobs: usize,
+    /// Do not restore the modification times recorded in the archive.
+    pub touch: bool,
+    /// Apply this umask to the permissions recorded in the archive.
+    pub umask: Option<u32>,
+    /// Clear the setuid and setgid bits of the extracted files.
+    pub no_setuid: bool,
+    /// Clear the setuid, setgid, and sticky bits as well as the write
+    /// permission for others. Log each changed mode.
+    pub sanitize_modes: bool,
+    /// How to handle hard-links with inconsistent link counts.
+    pub hardlink_policy: HardlinkPolicy,
+    /// Verify the checksums of the extracted files (for cpio archives in
+    /// the crc format) and fail on the first mismatch.
+    pub verify_checksums: bool,
+    /// Write the extracted files to disk and drop them from the page cache.
+    pub no_cache: bool,
+    /// Create empty placeholders for the regular files instead of writing
+    /// their content (keeping names, permissions, owners, and mtimes).
+    pub metadata_only: bool,
+    /// Extract the entries of each cpio archive sorted by name (parents
+    /// first) instead of in archive order. The headers are buffered in
+    /// memory. The content is read again from uncompressed cpio archives.
+    /// Compressed cpio archives are spooled into an anonymous file in the
+    /// target directory first.
+    pub sorted: bool,
+    /// Translate the owners recorded in the archive (if preserving them).
+    pub idmap: Option<IdMap>,
+    /// Only extract the entries whose names match one of these patterns (or
+    /// all entries if there are no patterns). Missing parent directories of
+    /// the selected entries are created with the default permissions.
+    pub names: Vec<String>,
+    /// How the name patterns select the entries.
+    pub match_mode: MatchMode,
+}
+
+impl ExtractOptions {
+    /// Return the path of `name` below the target directory.
+    fn target_path(&self, name: &str) -> String {
+        match self.directory {
+            Some(ref directory) => format!("{}/{}", directory, name),
+            None => name.into(),
+        }
+    }
+
+    /// Return the permission bits to set for the extracted entry.
+    fn mode_perm(&self, header: &Header) -> u32 {
+        let mode = self.unsanitized_mode_perm(header);
+        if self.sanitize_modes {
+            mode & !(MODE_SETUID | MODE_SETGID | MODE_STICKY | MODE_WRITE_OTHERS)
+        } else {
+            mode
+        }
+    }
+
+    fn unsanitized_mode_perm(&self, header: &Header) -> u32 {
+        let mut mode = header.mode_perm();
+        if let Some(umask) = self.umask {
+            mode &= !umask;
+        }
+        if self.no_setuid {
+            mode &= !(MODE_SETUID | MODE_SETGID);
+        }
+        mode
+    }
+
+    fn permission(&self, header: &Header, log_level: u32) -> Result<Permissions> {
+        let mode = self.mode_perm(header);
+        if self.sanitize_modes && log_level >= LOG_LEVEL_WARNING {
+            let unsanitized_mode = self.unsanitized_mode_perm(header);
+            if mode != unsanitized_mode {
+                writeln!(
+                    std::io::stderr(),
+                    "Sanitized mode of '{}' from {:o} to {:o}",
+                    header.filename,
+                    unsanitized_mode,
+                    mode
+                )?;
+            }
+        }
+        Ok(PermissionsExt::from_mode(mode))
+    }
+}
+
+/// Accounting of the extracted entries
+#[derive(Debug, Default, PartialEq)]
+pub struct ExtractSummary {
+    pub directories: u64,
+    /// Regular files (excluding additional hard-links)
+    pub files: u64,
+    /// Additional links to already extracted regular files
+    pub hard_links: u64,
+    pub symlinks: u64,
+    /// Device nodes and named pipes (FIFOs)
+    pub special_files: u64,
+    /// Number of bytes written into regular files
+    pub bytes_written: u64,
+    /// Number of warnings (including the suppressed repeated ones)
+    pub warnings: u64,
+}
+
+impl ExtractSummary {
+    fn add(&mut self, other: &ExtractSummary) {
+        self.directories += other.directories;
+        self.files += other.files;
+        self.hard_links += other.hard_links;
+        self.symlinks += other.symlinks;
+        self.special_files += other.special_files;
+        self.bytes_written += other.bytes_written;
+        self.warnings += other.warnings;
+    }
+}
+
+/// Number of warnings of the same kind to print before suppressing them
+const MAX_REPEATED_WARNINGS: u64 = 10;
+
+/// Printed warnings counted per kind to limit repeated warnings
+///
+/// An archive can trigger the same warning thousands of times (e.g. for
+/// every symlink). Only the first warnings of each kind are printed and
+/// the number of suppressed ones is reported at the end.
+#[derive(Debug, Default)]
+struct Warnings {
+    total: u64,
+    per_kind: BTreeMap<&'static str, u64>,
+}
+
+impl Warnings {
+    fn warn<D: std::fmt::Display>(
+        &mut self,
+        kind: &'static str,
+        message: D,
+        log_level: u32,
+    ) -> Result<()> {
+        self.total += 1;
+        let count = self.per_kind.entry(kind).or_default();
+        *count += 1;
+        if log_level >= LOG_LEVEL_WARNING && *count <= MAX_REPEATED_WARNINGS {
+            writeln!(std::io::stderr(), "Warning: {}", message)?;
+        }
+        Ok(())
+    }
+
+    /// Print the number of suppressed warnings per kind.
+    fn report_suppressed(&self, log_level: u32) -> Result<()> {
+        if log_level < LOG_LEVEL_WARNING {
+            return Ok(());
+        }
+        for (kind, count) in &self.per_kind {
+            if *count > MAX_REPEATED_WARNINGS {
+                writeln!(
+                    std::io::stderr(),
+                    "Warning: Suppressed {} more {} warnings.",
+                    count - MAX_REPEATED_WARNINGS,
+                    kind
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How to handle hard-links whose link count does not match the archive
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HardlinkPolicy {
+    /// Fail if the number of entries of a hard-link differs from its nlink.
+    Strict,
+    /// Warn about inconsistent hard-links, but extract them anyway.
+    #[default]
+    Lenient,
+}
+
+impl std::str::FromStr for HardlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "lenient" => Ok(Self::Lenient),
+            _ => Err(format!(
+                "Unknown hard-link policy '{}'. Expected 'strict' or 'lenient'.",
+                s
+            )),
+        }
+    }
+}
+
+/// How the patterns select the entries by name
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MatchMode {
+    /// Shell-style glob pattern where `*` and `?` also match `/`.
+    #[default]
+    Glob,
+    /// Glob pattern where `*` and `?` do not match `/`, but `**` matches
+    /// any number of directories.
+    Globstar,
+    /// Select the entry with the given name and everything below it.
+    Prefix,
+    /// Select only the entry with the given name.
+    Exact,
+}
+
+impl std::str::FromStr for MatchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "glob" => Ok(Self::Glob),
+            "globstar" => Ok(Self::Globstar),
+            "prefix" => Ok(Self::Prefix),
+            "exact" => Ok(Self::Exact),
+            _ => Err(format!(
+                "Unknown match mode '{}'. Expected 'glob', 'globstar', 'prefix', or 'exact'.",
+                s
+            )),
+        }
     }
 }
 
 // TODO: Document hardlink structure
 type SeenFiles = HashMap<u128, String>;
 
+/// Entries of one hard-linked file seen in the cpio archive
+///
+/// The newc format stores the data of hard-linked files with the last link
+/// (other tools store it with the first link). Both conventions are handled
+/// by linking to the first seen entry and writing the data through the link
+/// that carries it.
+struct HardLinkGroup {
+    /// Extracted names of the entries (the first one is the link target)
+    names: Vec<String>,
+    nlink: u32,
+    has_data: bool,
+}
+
 struct Extractor {
+    /// Directory to extract the entries into (instead of the working directory)
+    directory: Option<String>,
+    /// Hard-linked files of the current cpio archive
     seen_files: SeenFiles,
+    hard_links: HashMap<u128, HardLinkGroup>,
+    /// Hard-linked files of the earlier cpio archives
+    earlier_hard_links: HashMap<u128, HardLinkGroup>,
     mtimes: BTreeMap<String, i64>,
+    /// Only extract the entries matching these patterns
+    patterns: Patterns,
+    warnings: Warnings,
+    summary: ExtractSummary,
 }
 
 impl Extractor {
     fn new() -> Extractor {
         Extractor {
+            directory: None,
             seen_files: SeenFiles::new(),
+            hard_links: HashMap::new(),
+            earlier_hard_links: HashMap::new(),
             mtimes: BTreeMap::new(),
+            patterns: Patterns::default(),
+            warnings: Warnings::default(),
+            summary: ExtractSummary::default(),
+        }
+    }
+
+    fn record_hard_link(&mut self, header: &Header) {
+        let group = self
+            .hard_links
+            .entry(header.ino_and_dev())
+            .or_insert_with(|| HardLinkGroup {
+                names: Vec::new(),
+                nlink: header.nlink,
+                has_data: false,
+            });
+        group.names.push(header.filename.clone());
+        group.has_data |= header.filesize > 0;
+    }
+
+    /// Finish the hard-links of the current cpio archive.
+    ///
+    /// Each cpio archive numbers its inodes independently (the kernel resets
+    /// its hard-link table at each trailer). Only a hard-link without any
+    /// entry carrying data in this cpio archive is linked to the file with
+    /// the same inode number of an earlier cpio archive, because its data
+    /// might be stored there.
+    fn finish_archive(&mut self, policy: HardlinkPolicy, log_level: u32) -> Result<()> {
+        self.seen_files.clear();
+        let mut groups: Vec<(u128, HardLinkGroup)> = self.hard_links.drain().collect();
+        groups.sort_by(|(_, a), (_, b)| a.names[0].cmp(&b.names[0]));
+        for (ino_and_dev, group) in groups {
+            if !group.has_data {
+                if let Some(earlier) = self.earlier_hard_links.get_mut(&ino_and_dev) {
+                    for name in group.names {
+                        if log_level >= LOG_LEVEL_DEBUG {
+                            writeln!(
+                                std::io::stderr(),
+                                "Replacing '{}' by hard-link to '{}' of an earlier cpio archive",
+                                name,
+                                earlier.names[0]
+                            )?;
+                        }
+                        remove_file(&name)?;
+                        hard_link(&earlier.names[0], &name)?;
+                        earlier.names.push(name);
+                    }
+                    // The first entry was counted as file instead of hard-link.
+                    self.summary.files -= 1;
+                    self.summary.hard_links += 1;
+                    continue;
+                }
+            }
+            if let Some(replaced) = self.earlier_hard_links.insert(ino_and_dev, group) {
+                self.check_hard_link(&replaced, policy, log_level)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the number of entries of each hard-link matches its nlink.
+    ///
+    /// Forget all seen hard-links afterwards. A hard-link target that was
+    /// never extracted (and no entry carrying data) will be reported here
+    /// instead of silently leaving an empty file behind.
+    fn finish_hard_links(&mut self, policy: HardlinkPolicy, log_level: u32) -> Result<()> {
+        self.finish_archive(policy, log_level)?;
+        let mut groups: Vec<HardLinkGroup> = self
+            .earlier_hard_links
+            .drain()
+            .map(|(_, group)| group)
+            .collect();
+        groups.sort_by(|a, b| a.names[0].cmp(&b.names[0]));
+        for group in groups {
+            self.check_hard_link(&group, policy, log_level)?;
+        }
+        Ok(())
+    }
+
+    /// Check that the number of entries of the hard-link matches its nlink.
+    fn check_hard_link(
+        &mut self,
+        group: &HardLinkGroup,
+        policy: HardlinkPolicy,
+        log_level: u32,
+    ) -> Result<()> {
+        if group.names.len() == usize::try_from(group.nlink).unwrap() {
+            return Ok(());
+        }
+        let message = format!(
+            "Hard-link '{}' has {} entries in the archive, but a link count of {}{}.",
+            group.names[0],
+            group.names.len(),
+            group.nlink,
+            if group.has_data {
+                ""
+            } else {
+                " and no entry carries data"
+            },
+        );
+        match policy {
+            HardlinkPolicy::Strict => Err(Error::new(ErrorKind::InvalidData, message)),
+            HardlinkPolicy::Lenient => self.warnings.warn("hard-link", message, log_level),
         }
     }
 
-    fn set_modified_times(&self, log_level: u32) -> Result<()> {
-        for (path, mtime) in self.mtimes.iter().rev() {
+    fn set_modified_times(&mut self, log_level: u32) -> Result<()> {
+        for (path, mtime) in std::mem::take(&mut self.mtimes).iter().rev() {
             if log_level >= LOG_LEVEL_DEBUG {
                 writeln!(std::io::stderr(), "set mtime {} for '{}'", mtime, path)?;
             };
@@ -143,14 +709,15 @@ fn align_to_4_bytes(length: u32) -> u32 {
 /// Return the file name.
 fn read_filename_from_next_cpio_object<R: Read + SeekForward>(file: &mut R) -> Result<String> {
     let (filesize, filename) = Header::read_only_filesize_and_filename(file)?;
-    let skip = filesize + align_to_4_bytes(filesize);
-    file.seek_forward(skip.into())?;
+    let skip = u64::from(filesize) + u64::from(align_to_4_bytes(filesize));
+    file.seek_forward(skip)?;
     Ok(filename)
 }
 
-fn read_magic_header<R: Read + Seek>(file: &mut R) -> Option<Result<Command>> {
+fn read_magic_header<R: Read + Seek>(file: &mut R) -> Option<Result<Compression>> {
+    let mut parser = Parser::new();
     let mut buffer = [0; 4];
-    while buffer == [0, 0, 0, 0] {
+    loop {
         match file.read_exact(&mut buffer) {
             Ok(()) => {}
             Err(e) => match e.kind() {
@@ -158,94 +725,214 @@ fn read_magic_header<R: Read + Seek>(file: &mut R) -> Option<Result<Command>> {
                 _ => return Some(Err(e)),
             },
         };
-    }
-    let command = match buffer {
-        [0x42, 0x5A, 0x68, _] => {
-            let mut cmd = Command::new("bzip2");
-            cmd.arg("-cd");
-            cmd
-        }
-        [0x30, 0x37, 0x30, 0x37] => Command::new("cpio"),
-        [0x1F, 0x8B, _, _] => {
-            let mut cmd = Command::new("gzip");
-            cmd.arg("-cd");
-            cmd
-        }
-        // Different magic numbers (little endian) for lz4:
-        // v0.1-v0.9: 0x184C2102
-        // v1.0-v1.3: 0x184C2103
-        // v1.4+: 0x184D2204
-        [0x02, 0x21, 0x4C, 0x18] | [0x03, 0x21, 0x4C, 0x18] | [0x04, 0x22, 0x4D, 0x18] => {
-            let mut cmd = Command::new("lz4");
-            cmd.arg("-cd");
-            cmd
-        }
-        [0x5D, _, _, _] => {
-            let mut cmd = Command::new("lzma");
-            cmd.arg("-cd");
-            cmd
-        }
-        // Full magic number for lzop: [0x89, 0x4C, 0x5A, 0x4F, 0x00, 0x0D, 0x0A, 0x1A, 0x0A]
-        [0x89, 0x4C, 0x5A, 0x4F] => {
-            let mut cmd = Command::new("lzop");
-            cmd.arg("-cd");
-            cmd
-        }
-        // Full magic number for xz: [0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00]
-        [0xFD, 0x37, 0x7A, 0x58] => {
-            let mut cmd = Command::new("xz");
-            cmd.arg("-cd");
-            cmd
-        }
-        [0x28, 0xB5, 0x2F, 0xFD] => {
-            let mut cmd = Command::new("zstd");
-            cmd.arg("-cdq");
-            cmd
+        match parser.next(&buffer) {
+            Ok((_, Event::NeedMore(_))) => {}
+            Ok((_, Event::Archive(compression))) => {
+                return Some(file.seek(SeekFrom::Current(-4)).map(|_| compression))
+            }
+            Ok((_, event)) => unreachable!("unexpected event {:?} before the archive", event),
+            Err(e) => return Some(Err(e)),
         }
-        _ => {
-            return Some(Err(Error::new(
-                ErrorKind::InvalidData,
-                format!(
-                    "Failed to determine CPIO or compression magic number: 0x{:02x}{:02x}{:02x}{:02x} (big endian)",
-                    buffer[0], buffer[1], buffer[2], buffer[3]
-                ),
-            )));
+    }
+}
+
+/// Compare entry names ignoring a leading `./` or `/`.
+fn entry_name_matches(filename: &str, name: &str) -> bool {
+    strip_entry_name(filename) == strip_entry_name(name)
+}
+
+/// Strip a leading `./` or `/` from the entry name.
+fn strip_entry_name(name: &str) -> &str {
+    let name = name.strip_prefix("./").unwrap_or(name);
+    name.strip_prefix('/').unwrap_or(name)
+}
+
+/// Patterns for selecting entries by name
+#[derive(Clone, Debug, Default)]
+struct Patterns {
+    mode: MatchMode,
+    /// Compiled patterns for the glob modes
+    globs: Vec<glob::Pattern>,
+    /// Names without leading `./` or `/` and trailing `/` for the prefix
+    /// and exact modes
+    names: Vec<String>,
+}
+
+impl Patterns {
+    fn is_empty(&self) -> bool {
+        self.globs.is_empty() && self.names.is_empty()
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let options = glob::MatchOptions {
+            require_literal_separator: self.mode == MatchMode::Globstar,
+            ..glob::MatchOptions::new()
+        };
+        match self.mode {
+            MatchMode::Glob | MatchMode::Globstar => self
+                .globs
+                .iter()
+                .any(|pattern| pattern.matches_with(name, options)),
+            MatchMode::Prefix => self.names.iter().any(|prefix| {
+                prefix.is_empty()
+                    || name
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            }),
+            MatchMode::Exact => self.names.iter().any(|exact| exact == name),
         }
+    }
+}
+
+/// Compile the patterns for selecting entries by name.
+fn compile_patterns(patterns: &[String], mode: MatchMode) -> Result<Patterns> {
+    let mut compiled = Patterns {
+        mode,
+        globs: Vec::new(),
+        names: Vec::new(),
     };
-    match file.seek(SeekFrom::Current(-4)) {
-        Ok(_) => {}
-        Err(e) => {
-            return Some(Err(e));
+    match mode {
+        MatchMode::Glob | MatchMode::Globstar => {
+            for pattern in patterns {
+                let glob = glob::Pattern::new(pattern).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Invalid pattern '{}': {}", pattern, e),
+                    )
+                })?;
+                compiled.globs.push(glob);
+            }
         }
-    };
-    Some(Ok(command))
-}
-
-fn decompress(command: &mut Command, file: File) -> Result<ChildStdout> {
-    // TODO: Propper error message if spawn fails
-    let cmd = command
-        .stdin(file)
-        .stdout(Stdio::piped())
-        .spawn()
-        .map_err(|e| match e.kind() {
-            ErrorKind::NotFound => Error::other(format!(
-                "Program '{}' not found in PATH.",
-                command.get_program().to_str().unwrap()
-            )),
-            _ => e,
-        })?;
-    // TODO: Should unwrap be replaced by returning Result?
-    Ok(cmd.stdout.unwrap())
+        MatchMode::Prefix | MatchMode::Exact => {
+            compiled.names = patterns
+                .iter()
+                .map(|name| strip_entry_name(name).trim_end_matches('/').to_string())
+                .collect();
+        }
+    }
+    Ok(compiled)
+}
+
+/// Check if the entry name (without leading `./` or `/`) matches one of the
+/// patterns. No patterns select all entries.
+fn patterns_match(patterns: &Patterns, filename: &str) -> bool {
+    patterns.is_empty() || patterns.matches(strip_entry_name(filename))
+}
+
+/// Search the cpio archive for the entry with the given name.
+///
+/// Return the header of the entry and leave the reader positioned at the
+/// start of the entry's data. Return `None` if the trailer is reached. The
+/// data of hard-linked files is stored with the last link, so for a matching
+/// hard-link without data the search continues until the data is found.
+fn find_entry<R: Read + SeekForward>(file: &mut R, name: &str) -> Result<Option<Header>> {
+    let mut hard_link: Option<Header> = None;
+    loop {
+        let header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            return Ok(hard_link);
+        }
+        match hard_link {
+            Some(ref mut link) => {
+                if header.ino_and_dev() == link.ino_and_dev() && header.filesize > 0 {
+                    link.filesize = header.filesize;
+                    return Ok(hard_link);
+                }
+            }
+            None => {
+                if entry_name_matches(&header.filename, name) {
+                    if header.file_type() == Some(FileType::RegularFile)
+                        && header.nlink > 1
+                        && header.filesize == 0
+                    {
+                        hard_link = Some(header);
+                        continue;
+                    }
+                    return Ok(Some(header));
+                }
+            }
+        }
+        header.skip_file_content(file)?;
+    }
+}
+
+/// Open the entry with the given name from the cpio archive.
+///
+/// Search through all cpio archives (decompressing them as needed) for the
+/// first entry with the given name. A leading `./` or `/` is ignored when
+/// comparing the names. Return the header of the entry and a reader that
+/// is limited to the content of the entry (e.g. the target for symlinks).
+pub fn open_entry(archive: File, name: &str) -> Result<(Header, impl Read)> {
+    let mut file = BufReader::with_capacity(DEFAULT_IO_BUFFER_SIZE, archive);
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if compression.is_uncompressed() {
+            if let Some(header) = find_entry(&mut file, name)? {
+                let reader: Box<dyn Read> = Box::new(file);
+                let filesize = header.filesize.into();
+                return Ok((header, reader.take(filesize)));
+            }
+        } else {
+            let mut decompressed =
+                decompress_buffered(compression, file, DEFAULT_IO_BUFFER_SIZE, LOG_LEVEL_WARNING)?;
+            if let Some(header) = find_entry(&mut decompressed, name)? {
+                let reader: Box<dyn Read> = Box::new(decompressed);
+                let filesize = header.filesize.into();
+                return Ok((header, reader.take(filesize)));
+            }
+            break;
+        }
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        format!("Entry '{}' not found in the cpio archive.", name),
+    ))
+}
+
+/// Check that the decompressor for the compressed cpio archive is available.
+///
+/// Skip over the uncompressed cpio archives to determine the compression
+/// of the last cpio archive and fail early in case the decompressor is
+/// missing. The file position is restored afterwards.
+fn check_decompressor_available<R: Read + Seek + SeekForward>(file: &mut R) -> Result<()> {
+    let start = file.stream_position()?;
+    loop {
+        let compression = match read_magic_header(file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if compression.is_uncompressed() {
+            seek_to_cpio_end(file)?;
+        } else {
+            compression.check_decompressor_available()?;
+            break;
+        }
+    }
+    file.seek(SeekFrom::Start(start))?;
+    Ok(())
 }
 
 fn read_cpio_and_print_filenames<R: Read + SeekForward, W: Write>(
     file: &mut R,
     out: &mut W,
+    options: &ListOptions,
+    patterns: &Patterns,
 ) -> Result<()> {
     let cpio = CpioFilenameReader { file };
     for f in cpio {
         let filename = f?;
-        writeln!(out, "{}", filename)?;
+        if !patterns_match(patterns, &filename) {
+            continue;
+        }
+        write!(
+            out,
+            "{}{}",
+            options.quoting_style.quote(&filename),
+            options.terminator()
+        )?;
     }
     Ok(())
 }
@@ -253,9 +940,13 @@ fn read_cpio_and_print_filenames<R: Read + SeekForward, W: Write>(
 fn read_cpio_and_print_long_format<R: Read + SeekForward, W: Write>(
     file: &mut R,
     out: &mut W,
-    now: i64,
+    local_time: &LocalTime,
+    options: &ListOptions,
+    patterns: &Patterns,
+    show_dev: bool,
     user_group_cache: &mut UserGroupCache,
 ) -> Result<()> {
+    let quoting_style = options.quoting_style;
     // Files can have the same mtime (especially when using SOURCE_DATE_EPOCH).
     // Cache the time string of the last mtime.
     let mut last_mtime = 0;
@@ -271,6 +962,10 @@ fn read_cpio_and_print_long_format<R: Read + SeekForward, W: Write>(
             }
             Err(e) => return Err(e),
         };
+        if !patterns_match(patterns, &header.filename) {
+            header.skip_file_content(file)?;
+            continue;
+        }
 
         let user = match user_group_cache.get_user(header.uid)? {
             Some(name) => name,
@@ -283,30 +978,34 @@ fn read_cpio_and_print_long_format<R: Read + SeekForward, W: Write>(
         let mode_string = header.mode_string();
         if header.mtime != last_mtime || time_string.is_empty() {
             last_mtime = header.mtime;
-            time_string = format_time(header.mtime, now)?;
+            time_string = format_time(header.mtime, local_time)?;
         };
+        if show_dev {
+            write!(out, "{:>7} ", format!("{}:{}", header.major, header.minor))?;
+        }
 
-        match header.mode & MODE_FILETYPE_MASK {
-            FILETYPE_SYMLINK => {
+        match header.file_type() {
+            Some(FileType::Symlink) => {
                 let target = header.read_symlink_target(file)?;
-                writeln!(
+                write!(
                     out,
-                    "{} {:>3} {:<8} {:<8} {:>8} {} {} -> {}",
+                    "{} {:>3} {:<8} {:<8} {:>8} {} {} -> {}{}",
                     std::str::from_utf8(&mode_string).unwrap(),
                     header.nlink,
                     user,
                     group,
                     header.filesize,
                     time_string,
-                    header.filename,
-                    target
+                    quoting_style.quote(&header.filename),
+                    quoting_style.quote(&target),
+                    options.terminator()
                 )?;
             }
-            FILETYPE_BLOCK_DEVICE | FILETYPE_CHARACTER_DEVICE => {
+            Some(FileType::BlockDevice | FileType::CharacterDevice) => {
                 header.skip_file_content(file)?;
-                writeln!(
+                write!(
                     out,
-                    "{} {:>3} {:<8} {:<8} {:>3}, {:>3} {} {}",
+                    "{} {:>3} {:<8} {:<8} {:>3}, {:>3} {} {}{}",
                     std::str::from_utf8(&mode_string).unwrap(),
                     header.nlink,
                     user,
@@ -314,21 +1013,23 @@ fn read_cpio_and_print_long_format<R: Read + SeekForward, W: Write>(
                     header.rmajor,
                     header.rminor,
                     time_string,
-                    header.filename
+                    quoting_style.quote(&header.filename),
+                    options.terminator()
                 )?;
             }
             _ => {
                 header.skip_file_content(file)?;
-                writeln!(
+                write!(
                     out,
-                    "{} {:>3} {:<8} {:<8} {:>8} {} {}",
+                    "{} {:>3} {:<8} {:<8} {:>8} {} {}{}",
                     std::str::from_utf8(&mode_string).unwrap(),
                     header.nlink,
                     user,
                     group,
                     header.filesize,
                     time_string,
-                    header.filename
+                    quoting_style.quote(&header.filename),
+                    options.terminator()
                 )?;
             }
         };
@@ -336,6 +1037,31 @@ fn read_cpio_and_print_long_format<R: Read + SeekForward, W: Write>(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
+fn read_cpio_and_print<R: Read + SeekForward, W: Write>(
+    file: &mut R,
+    out: &mut W,
+    options: &ListOptions,
+    patterns: &Patterns,
+    log_level: u32,
+    local_time: &LocalTime,
+    user_group_cache: &mut UserGroupCache,
+) -> Result<()> {
+    if log_level >= LOG_LEVEL_INFO {
+        read_cpio_and_print_long_format(
+            file,
+            out,
+            local_time,
+            options,
+            patterns,
+            log_level >= LOG_LEVEL_DEBUG,
+            user_group_cache,
+        )
+    } else {
+        read_cpio_and_print_filenames(file, out, options, patterns)
+    }
+}
+
 fn create_dir_ignore_existing<P: AsRef<std::path::Path>>(path: P) -> Result<()> {
     if let Err(e) = create_dir(&path) {
         if e.kind() != ErrorKind::AlreadyExists {
@@ -352,7 +1078,7 @@ fn create_dir_ignore_existing<P: AsRef<std::path::Path>>(path: P) -> Result<()>
 
 fn write_directory(
     header: &Header,
-    preserve_permissions: bool,
+    options: &ExtractOptions,
     log_level: u32,
     mtimes: &mut BTreeMap<String, i64>,
 ) -> Result<()> {
@@ -370,8 +1096,8 @@ fn write_directory(
             std::io::stderr(),
             "Creating directory '{}' with mode {:o}{}",
             header.filename,
-            header.mode_perm(),
-            if preserve_permissions {
+            options.mode_perm(header),
+            if options.preserve_permissions {
                 format!(" and owner {}:{}", header.uid, header.gid)
             } else {
                 String::new()
@@ -379,11 +1105,13 @@ fn write_directory(
         )?;
     };
     create_dir_ignore_existing(&header.filename)?;
-    if preserve_permissions {
+    if options.preserve_permissions {
         chown(&header.filename, Some(header.uid), Some(header.gid))?;
     }
-    set_permissions(&header.filename, header.permission())?;
-    mtimes.insert(header.filename.to_string(), header.mtime.into());
+    set_permissions(&header.filename, options.permission(header, log_level)?)?;
+    if !options.touch {
+        mtimes.insert(header.filename.to_string(), header.mtime.into());
+    }
     Ok(())
 }
 
@@ -394,7 +1122,7 @@ fn from_mtime(mtime: u32) -> SystemTime {
 fn write_file<R: Read + SeekForward>(
     cpio_file: &mut R,
     header: &Header,
-    preserve_permissions: bool,
+    options: &ExtractOptions,
     seen_files: &mut SeenFiles,
     log_level: u32,
 ) -> Result<()> {
@@ -406,8 +1134,8 @@ fn write_file<R: Read + SeekForward>(
                 "Creating hard-link '{}' -> '{}' with permission {:o}{} and {} bytes",
                 header.filename,
                 target,
-                header.mode_perm(),
-                if preserve_permissions {
+                options.mode_perm(header),
+                if options.preserve_permissions {
                     format!(" and owner {}:{}", header.uid, header.gid)
                 } else {
                     String::new()
@@ -433,8 +1161,8 @@ fn write_file<R: Read + SeekForward>(
                 std::io::stderr(),
                 "Creating file '{}' with permission {:o}{} and {} bytes",
                 header.filename,
-                header.mode_perm(),
-                if preserve_permissions {
+                options.mode_perm(header),
+                if options.preserve_permissions {
                     format!(" and owner {}:{}", header.uid, header.gid)
                 } else {
                     String::new()
@@ -445,31 +1173,59 @@ fn write_file<R: Read + SeekForward>(
         file = File::create(&header.filename)?
     };
     header.mark_seen(seen_files);
+    if options.metadata_only {
+        header.skip_file_content(cpio_file)?;
+    } else {
+        write_file_content(cpio_file, header, options, &mut file)?;
+    }
+    if options.preserve_permissions {
+        fchown(&file, Some(header.uid), Some(header.gid))?;
+    }
+    file.set_permissions(options.permission(header, log_level)?)?;
+    if !options.touch {
+        file.set_modified(from_mtime(header.mtime))?;
+    }
+    Ok(())
+}
+
+fn write_file_content<R: Read + SeekForward>(
+    cpio_file: &mut R,
+    header: &Header,
+    options: &ExtractOptions,
+    file: &mut File,
+) -> Result<()> {
     let mut reader = cpio_file.take(header.filesize.into());
     // TODO: check writing hard-link with length == 0
     // TODO: check overwriting existing files/hardlinks
-    let written = std::io::copy(&mut reader, &mut file)?;
+    let written = if options.verify_checksums && header.check.is_some() {
+        let mut reader = ChecksumReader::new(reader);
+        let written = std::io::copy(&mut reader, file)?;
+        check_checksum(header, reader.checksum)?;
+        written
+    } else {
+        std::io::copy(&mut reader, file)?
+    };
     if written != header.filesize.into() {
         return Err(Error::other(format!(
             "Wrong amound of bytes written to '{}': {} != {}.",
             header.filename, written, header.filesize
         )));
     }
-    let skip = align_to_4_bytes(header.filesize);
-    cpio_file.seek_forward(skip.into())?;
-    if preserve_permissions {
-        fchown(&file, Some(header.uid), Some(header.gid))?;
+    if options.no_cache {
+        // Dirty pages cannot be dropped from the page cache.
+        file.sync_data()?;
+        fadvise(file, Advice::DontNeed)?;
     }
-    file.set_permissions(header.permission())?;
-    file.set_modified(from_mtime(header.mtime))?;
-    Ok(())
+    let skip = align_to_4_bytes(header.filesize);
+    cpio_file.seek_forward(skip.into())
 }
 
 fn write_symbolic_link<R: Read + SeekForward>(
     cpio_file: &mut R,
     header: &Header,
-    preserve_permissions: bool,
+    options: &ExtractOptions,
     log_level: u32,
+    warnings: &mut Warnings,
 ) -> Result<()> {
     let target = header.read_symlink_target(cpio_file)?;
     if log_level >= LOG_LEVEL_DEBUG {
@@ -492,86 +1248,263 @@ fn write_symbolic_link<R: Read + SeekForward>(
             }
         }
     }
-    if preserve_permissions {
+    if options.preserve_permissions {
         lchown(&header.filename, Some(header.uid), Some(header.gid))?;
     }
     if header.mode_perm() != 0o777 {
-        return Err(Error::new(
-            ErrorKind::Unsupported,
-            format!(
-                "Symlink '{}' has mode {:o}, but only mode 777 is supported.",
-                header.filename,
-                header.mode_perm()
-            ),
-        ));
+        // Linux does not support changing the mode of symlinks (lchmod).
+        if let Err(e) = set_symlink_permissions(&header.filename, options.mode_perm(header)) {
+            if e.kind() != ErrorKind::Unsupported {
+                return Err(e);
+            }
+            warnings.warn(
+                "symlink mode",
+                format_args!(
+                    "Symlink '{}' has mode {:o}, but only mode 777 is supported. Ignoring mode.",
+                    header.filename,
+                    header.mode_perm()
+                ),
+                log_level,
+            )?;
+        }
     };
-    set_modified(&header.filename, header.mtime.into())?;
+    if !options.touch {
+        set_modified(&header.filename, header.mtime.into())?;
+    }
     Ok(())
 }
 
-fn read_cpio_and_extract<R: Read + SeekForward>(
-    file: &mut R,
-    preserve_permissions: bool,
-    log_level: u32,
+/// Create a character device, block device, or named pipe (FIFO).
+fn write_special_file<R: Read + SeekForward>(
+    cpio_file: &mut R,
+    header: &Header,
+    options: &ExtractOptions,
+    log_level: u32,
 ) -> Result<()> {
-    let mut extractor = Extractor::new();
-    loop {
-        let header = match Header::read(file) {
-            Ok(header) => {
-                if header.filename == "TRAILER!!!" {
-                    break;
-                } else {
-                    header
-                }
+    header.skip_file_content(cpio_file)?;
+    if log_level >= LOG_LEVEL_DEBUG {
+        writeln!(
+            std::io::stderr(),
+            "Creating special file '{}' with mode {:o} and device {}:{}{}",
+            header.filename,
+            header.mode,
+            header.rmajor,
+            header.rminor,
+            if options.preserve_permissions {
+                format!(" and owner {}:{}", header.uid, header.gid)
+            } else {
+                String::new()
+            },
+        )?;
+    };
+    let mode = header.mode & MODE_FILETYPE_MASK | options.mode_perm(header);
+    if let Err(e) = mknod(&header.filename, mode, header.rmajor, header.rminor) {
+        match e.kind() {
+            ErrorKind::AlreadyExists => {
+                remove_file(&header.filename)?;
+                mknod(&header.filename, mode, header.rmajor, header.rminor)?;
             }
-            Err(e) => return Err(e),
-        };
+            _ => {
+                return Err(Error::new(
+                    e.kind(),
+                    format!("Failed to create '{}': {}", header.filename, e),
+                ));
+            }
+        }
+    }
+    if options.preserve_permissions {
+        lchown(&header.filename, Some(header.uid), Some(header.gid))?;
+    }
+    set_permissions(&header.filename, options.permission(header, log_level)?)?;
+    if !options.touch {
+        set_modified(&header.filename, header.mtime.into())?;
+    }
+    Ok(())
+}
 
-        if log_level >= LOG_LEVEL_DEBUG {
-            writeln!(std::io::stderr(), "{:?}", header)?;
-        } else if log_level >= LOG_LEVEL_INFO {
-            writeln!(std::io::stderr(), "{}", header.filename)?;
+fn read_cpio_and_extract<R: Read + SeekForward>(
+    file: &mut R,
+    extractor: &mut Extractor,
+    options: &ExtractOptions,
+    log_level: u32,
+) -> Result<()> {
+    loop {
+        let header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            break;
+        }
+        if !patterns_match(&extractor.patterns, &header.filename) {
+            header.skip_file_content(file)?;
+            continue;
         }
+        extract_entry(file, header, extractor, options, log_level)?;
+    }
+    extractor.finish_archive(options.hardlink_policy, log_level)?;
+    extractor.set_modified_times(log_level)?;
+    Ok(())
+}
 
-        match header.mode & MODE_FILETYPE_MASK {
-            FILETYPE_DIRECTORY => write_directory(
-                &header,
-                preserve_permissions,
-                log_level,
-                &mut extractor.mtimes,
-            )?,
-            FILETYPE_REGULAR_FILE => write_file(
-                file,
-                &header,
-                preserve_permissions,
-                &mut extractor.seen_files,
-                log_level,
-            )?,
-            FILETYPE_SYMLINK => {
-                write_symbolic_link(file, &header, preserve_permissions, log_level)?
+/// Create an anonymous file in the directory for spooling data.
+///
+/// The file is removed right after creating it. So it vanishes when it is
+/// closed.
+fn create_spool_file(directory: &str) -> Result<File> {
+    let path = format!("{}/.3cpio-spool.{}", directory, std::process::id());
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    remove_file(&path)?;
+    Ok(file)
+}
+
+/// Extract the entries of the (decompressed) cpio archive sorted by name.
+///
+/// The cpio archive is spooled into an anonymous file in the target
+/// directory (instead of the memory) and then extracted like an
+/// uncompressed cpio archive. So only the headers are kept in memory.
+fn read_cpio_and_extract_sorted<R: Read>(
+    file: &mut R,
+    extractor: &mut Extractor,
+    options: &ExtractOptions,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let mut spool = create_spool_file(extractor.directory.as_deref().unwrap_or("."))?;
+    std::io::copy(file, &mut spool)?;
+    spool.rewind()?;
+    let mut spool = BufReader::with_capacity(io_buffer_size, spool);
+    read_cpio_and_extract_sorted_seekable(&mut spool, extractor, options, log_level)
+}
+
+/// Extract the entries of the uncompressed cpio archive sorted by name.
+///
+/// Only the headers and the offsets of the file content are kept in
+/// memory. The content is read by seeking back to these offsets. The
+/// reader is left at the end of the cpio archive.
+fn read_cpio_and_extract_sorted_seekable<R: Read + Seek + SeekForward>(
+    file: &mut R,
+    extractor: &mut Extractor,
+    options: &ExtractOptions,
+    log_level: u32,
+) -> Result<()> {
+    let mut entries = Vec::new();
+    loop {
+        let header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            break;
+        }
+        check_interrupted()?;
+        let offset = file.stream_position()?;
+        header.skip_file_content(file)?;
+        if patterns_match(&extractor.patterns, &header.filename) {
+            entries.push((header, offset));
+        }
+    }
+    let end = file.stream_position()?;
+    sort_by_entry_name(&mut entries);
+    for (header, offset) in entries {
+        file.seek(SeekFrom::Start(offset))?;
+        extract_entry(file, header, extractor, options, log_level)?;
+    }
+    file.seek(SeekFrom::Start(end))?;
+    extractor.finish_archive(options.hardlink_policy, log_level)?;
+    extractor.set_modified_times(log_level)?;
+    Ok(())
+}
+
+/// Sort the entries by name. Parent directories sort before their content.
+fn sort_by_entry_name<T>(entries: &mut [(Header, T)]) {
+    // The sort is stable. So duplicate entries are extracted in archive order.
+    entries
+        .sort_by(|(a, _), (b, _)| strip_entry_name(&a.filename).cmp(strip_entry_name(&b.filename)));
+}
+
+/// Extract the entry. The file content is read from `file`.
+fn extract_entry<R: Read + SeekForward>(
+    file: &mut R,
+    mut header: Header,
+    extractor: &mut Extractor,
+    options: &ExtractOptions,
+    log_level: u32,
+) -> Result<()> {
+    check_interrupted()?;
+
+    if log_level >= LOG_LEVEL_DEBUG {
+        writeln!(std::io::stderr(), "{:?}", header)?;
+    } else if log_level >= LOG_LEVEL_INFO {
+        writeln!(std::io::stderr(), "{}", header.filename)?;
+    }
+    // Address the entry relative to the target directory instead of
+    // changing the working directory of the process.
+    if let Some(ref directory) = extractor.directory {
+        header.filename = format!("{}/{}", directory, header.filename);
+    }
+    // The parent directories of selected entries might not be selected.
+    if !extractor.patterns.is_empty() {
+        if let Some(parent) = std::path::Path::new(&header.filename).parent() {
+            create_dir_all(parent)?;
+        }
+    }
+    if let Some(ref idmap) = options.idmap {
+        header.uid = idmap.map_uid(header.uid)?;
+        header.gid = idmap.map_gid(header.gid)?;
+    }
+
+    match header.file_type() {
+        Some(FileType::Directory) => {
+            write_directory(&header, options, log_level, &mut extractor.mtimes)?;
+            extractor.summary.directories += 1;
+        }
+        Some(FileType::RegularFile) => {
+            if header.nlink > 1 {
+                extractor.record_hard_link(&header);
             }
-            FILETYPE_FIFO | FILETYPE_CHARACTER_DEVICE | FILETYPE_BLOCK_DEVICE | FILETYPE_SOCKET => {
-                unimplemented!(
-                    "Mode {:o} (file {}) not implemented. Please open a bug report requesting support for this type.",
-                    header.mode, header.filename
-                )
+            if header
+                .try_get_hard_link_target(&extractor.seen_files)
+                .is_some()
+            {
+                extractor.summary.hard_links += 1;
+            } else {
+                extractor.summary.files += 1;
             }
-            _ => {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!(
-                        "Invalid/unknown filetype {:o}: {}",
-                        header.mode, header.filename
-                    ),
-                ))
+            write_file(file, &header, options, &mut extractor.seen_files, log_level)?;
+            if !options.metadata_only {
+                extractor.summary.bytes_written += u64::from(header.filesize);
             }
-        };
-    }
-    extractor.set_modified_times(log_level)?;
+        }
+        Some(FileType::Symlink) => {
+            write_symbolic_link(file, &header, options, log_level, &mut extractor.warnings)?;
+            extractor.summary.symlinks += 1;
+        }
+        Some(FileType::Fifo | FileType::CharacterDevice | FileType::BlockDevice) => {
+            write_special_file(file, &header, options, log_level)?;
+            extractor.summary.special_files += 1;
+        }
+        Some(FileType::Socket) => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "Cannot extract socket '{}' (mode {:o}): sockets are not supported.",
+                    header.filename, header.mode
+                ),
+            ))
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Invalid/unknown filetype {:o}: {}",
+                    header.mode, header.filename
+                ),
+            ))
+        }
+    };
     Ok(())
 }
 
-fn seek_to_cpio_end(file: &mut File) -> Result<()> {
+fn seek_to_cpio_end<R: Read + SeekForward>(file: &mut R) -> Result<()> {
     let cpio = CpioFilenameReader { file };
     for f in cpio {
         f?;
@@ -579,89 +1512,511 @@ fn seek_to_cpio_end(file: &mut File) -> Result<()> {
     Ok(())
 }
 
-pub fn examine_cpio_content<W: Write>(mut file: File, out: &mut W) -> Result<()> {
+/// Reader for the decompressed content of a compressed cpio archive
+enum Decompressed {
+    Stream(BufReader<Decompressor>),
+    SeekableZstd(BufReader<SeekableZstdReader>),
+}
+
+impl Read for Decompressed {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Self::Stream(reader) => reader.read(buf),
+            Self::SeekableZstd(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Decompressed {
+    /// Read the remaining output (after the cpio trailer) to check that
+    /// the decompression succeeds.
+    ///
+    /// Decompressors report a corrupt archive (e.g. a checksum mismatch)
+    /// only once they reach the end of their input.
+    fn finish(mut self) -> Result<()> {
+        std::io::copy(&mut self, &mut std::io::sink())?;
+        Ok(())
+    }
+}
+
+impl SeekForward for Decompressed {
+    fn seek_forward(&mut self, offset: u64) -> Result<()> {
+        match self {
+            Self::Stream(reader) => reader.seek_forward(offset),
+            Self::SeekableZstd(reader) => reader.seek_forward(offset),
+        }
+    }
+}
+
+/// Decompress the remaining content of the buffered cpio archive file.
+///
+/// The underlying file is positioned to the current read position of the
+/// buffered reader before it is handed over to the decompressor. Zstd
+/// compressed cpio archives in the seekable format are read with random
+/// access to skip over frames that contain only file data.
+fn decompress_buffered(
+    compression: Compression,
+    mut file: BufReader<File>,
+    io_buffer_size: usize,
+    log_level: u32,
+) -> Result<Decompressed> {
+    let position = file.stream_position()?;
+    let mut file = file.into_inner();
+    if compression == Compression::Zstd {
+        if let Some(frames) = read_seek_table(&mut file, position)? {
+            let reader = SeekableZstdReader::new(file, frames, log_level)?;
+            return Ok(Decompressed::SeekableZstd(BufReader::with_capacity(
+                io_buffer_size,
+                reader,
+            )));
+        }
+    }
+    file.seek(SeekFrom::Start(position))?;
+    let decompressed = compression.decompress(file, log_level)?;
+    Ok(Decompressed::Stream(BufReader::with_capacity(
+        io_buffer_size,
+        decompressed,
+    )))
+}
+
+/// Reader that counts the number of bytes read or skipped.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R> CountingReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += u64::try_from(read).unwrap();
+        Ok(read)
+    }
+}
+
+impl<R: SeekForward> SeekForward for CountingReader<R> {
+    fn seek_forward(&mut self, offset: u64) -> Result<()> {
+        self.inner.seek_forward(offset)?;
+        self.count += offset;
+        Ok(())
+    }
+}
+
+/// Read the cpio archives with the given function and advise the kernel
+/// to read ahead sequentially and not to keep the pages afterwards.
+fn read_with_advice<T>(file: File, read: impl FnOnce(File) -> Result<T>) -> Result<T> {
+    let archive = file.try_clone()?;
+    // The advice is only a hint. So ignore failures (e.g. for pipes).
+    let _ = fadvise(&archive, Advice::Sequential);
+    let _ = fadvise(&archive, Advice::WillNeed);
+    let result = read(file);
+    let _ = fadvise(&archive, Advice::NoReuse);
+    result
+}
+
+/// Extract the cpio archives into the current directory.
+///
+/// Return the number of extracted entries, written bytes, and warnings.
+///
+/// If the extraction is interrupted by a signal (see
+/// [`install_interrupt_handlers`]), the sub-directory that was created for
+/// the cpio archive being extracted is removed and an error of kind
+/// [`ErrorKind::Interrupted`] is returned.
+///
+/// The entries are extracted into the directory given in the options (or
+/// the working directory). The working directory of the process is never
+/// changed. Therefore this function can be called from multithreaded
+/// programs.
+pub fn extract_cpio_archive(
+    file: File,
+    options: &ExtractOptions,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<ExtractSummary> {
+    let mut created_subdirs = Vec::new();
+    let result = read_with_advice(file, |file| {
+        extract_cpio_segments(
+            file,
+            options,
+            log_level,
+            io_buffer_size,
+            &mut created_subdirs,
+        )
+    });
+    if result.is_ok() {
+        return result;
+    }
+    let Some(signal) = received_signal() else {
+        return result;
+    };
+    for dir in created_subdirs {
+        remove_dir_all(dir)?;
+    }
+    Err(Error::new(
+        ErrorKind::Interrupted,
+        format!("Interrupted by signal {}", signal),
+    ))
+}
+
+fn extract_cpio_segments(
+    file: File,
+    options: &ExtractOptions,
+    log_level: u32,
+    io_buffer_size: usize,
+    created_subdirs: &mut Vec<String>,
+) -> Result<ExtractSummary> {
+    let mut file = BufReader::with_capacity(io_buffer_size, file);
+    check_decompressor_available(&mut file)?;
+    if let (Some(subdir), true) = (&options.subdir, options.jobs > 1) {
+        return extract_cpio_segments_parallel(
+            &mut file,
+            subdir,
+            options,
+            log_level,
+            created_subdirs,
+        );
+    }
+    let mut count = 1;
+    // Hard-links without data are resolved against earlier cpio archives,
+    // because their data might be stored there. Each sub-directory is a
+    // separate tree though.
+    let mut extractor = Extractor::new();
+    extractor.directory = options.directory.clone();
+    extractor.patterns = compile_patterns(&options.names, options.match_mode)?;
     loop {
-        let command = match read_magic_header(&mut file) {
-            None => return Ok(()),
+        if let Some(ref s) = options.subdir {
+            let dir = options.target_path(&format!("{s}{count}"));
+            let existed = symlink_metadata(&dir).is_ok();
+            create_dir_ignore_existing(&dir)?;
+            extractor.directory = Some(dir.clone());
+            // Only remove the sub-directory on interruption if we created it.
+            created_subdirs.clear();
+            if !existed {
+                created_subdirs.push(dir);
+            }
+        }
+        let compression = match read_magic_header(&mut file) {
+            None => break,
             Some(x) => x?,
         };
-        writeln!(
-            out,
-            "{}\t{}",
-            file.stream_position()?,
-            command.get_program().to_str().unwrap()
-        )?;
-        if command.get_program() == "cpio" {
-            seek_to_cpio_end(&mut file)?;
+        if compression.is_uncompressed() {
+            if options.sorted {
+                read_cpio_and_extract_sorted_seekable(
+                    &mut file,
+                    &mut extractor,
+                    options,
+                    log_level,
+                )?;
+            } else {
+                read_cpio_and_extract(&mut file, &mut extractor, options, log_level)?;
+            }
         } else {
+            let mut decompressed =
+                decompress_buffered(compression, file, io_buffer_size, log_level)?;
+            if options.sorted {
+                read_cpio_and_extract_sorted(
+                    &mut decompressed,
+                    &mut extractor,
+                    options,
+                    log_level,
+                    io_buffer_size,
+                )?;
+            } else {
+                read_cpio_and_extract(&mut decompressed, &mut extractor, options, log_level)?;
+            }
+            decompressed.finish()?;
             break;
         }
+        if options.subdir.is_some() {
+            extractor.finish_hard_links(options.hardlink_policy, log_level)?;
+        }
+        count += 1;
     }
-    Ok(())
+    extractor.finish_hard_links(options.hardlink_policy, log_level)?;
+    extractor.warnings.report_suppressed(log_level)?;
+    extractor.summary.warnings = extractor.warnings.total;
+    Ok(extractor.summary)
 }
 
-pub fn extract_cpio_archive(
-    mut file: File,
-    preserve_permissions: bool,
-    subdir: Option<String>,
+/// Extract the cpio archives into sub-directories using parallel worker
+/// threads.
+///
+/// The cpio archives are processed in batches of `jobs` archives. Each
+/// worker thread extracts one cpio archive (uncompressed archives via
+/// positioned reads, the compressed one via its own decompressor) into its
+/// own sub-directory. Hard-links are not resolved across sub-directories,
+/// like in the sequential extraction.
+fn extract_cpio_segments_parallel(
+    file: &mut BufReader<File>,
+    subdir: &str,
+    options: &ExtractOptions,
     log_level: u32,
-) -> Result<()> {
+    created_subdirs: &mut Vec<String>,
+) -> Result<ExtractSummary> {
+    let patterns = compile_patterns(&options.names, options.match_mode)?;
+    let segments = read_cpio_segments(file)?;
+    let io_buffer_size = file.capacity();
+    let mut summary = ExtractSummary::default();
     let mut count = 1;
-    let base_dir = std::env::current_dir()?;
-    loop {
-        if let Some(ref s) = subdir {
-            let mut dir = base_dir.clone();
-            dir.push(format!("{s}{count}"));
+    for batch in segments.chunks(options.jobs) {
+        // Only remove the sub-directories on interruption if we created them.
+        created_subdirs.clear();
+        let mut dirs = Vec::new();
+        for _ in batch {
+            let dir = options.target_path(&format!("{subdir}{count}"));
+            let existed = symlink_metadata(&dir).is_ok();
             create_dir_ignore_existing(&dir)?;
-            std::env::set_current_dir(&dir)?;
+            if !existed {
+                created_subdirs.push(dir.clone());
+            }
+            dirs.push(dir);
+            count += 1;
         }
-        let mut command = match read_magic_header(&mut file) {
-            None => return Ok(()),
+        let summaries: Vec<Result<ExtractSummary>> = std::thread::scope(|scope| {
+            let workers: Vec<_> = batch
+                .iter()
+                .zip(dirs)
+                .map(|((offset, compression), dir)| {
+                    let file = file.get_ref();
+                    let patterns = &patterns;
+                    scope.spawn(move || -> Result<ExtractSummary> {
+                        let mut extractor = Extractor::new();
+                        extractor.directory = Some(dir);
+                        extractor.patterns = patterns.clone();
+                        if compression.is_uncompressed() {
+                            let mut reader = BufReader::with_capacity(
+                                io_buffer_size,
+                                FileAtReader::new(file, *offset),
+                            );
+                            if options.sorted {
+                                read_cpio_and_extract_sorted_seekable(
+                                    &mut reader,
+                                    &mut extractor,
+                                    options,
+                                    log_level,
+                                )?;
+                            } else {
+                                read_cpio_and_extract(
+                                    &mut reader,
+                                    &mut extractor,
+                                    options,
+                                    log_level,
+                                )?;
+                            }
+                        } else {
+                            let mut compressed = file.try_clone()?;
+                            compressed.seek(SeekFrom::Start(*offset))?;
+                            let mut decompressed = BufReader::with_capacity(
+                                io_buffer_size,
+                                compression.decompress(compressed, log_level)?,
+                            );
+                            if options.sorted {
+                                read_cpio_and_extract_sorted(
+                                    &mut decompressed,
+                                    &mut extractor,
+                                    options,
+                                    log_level,
+                                    io_buffer_size,
+                                )?;
+                            } else {
+                                read_cpio_and_extract(
+                                    &mut decompressed,
+                                    &mut extractor,
+                                    options,
+                                    log_level,
+                                )?;
+                            }
+                            // Check the decompression (like Decompressed::finish).
+                            std::io::copy(&mut decompressed, &mut std::io::sink())?;
+                        }
+                        extractor.finish_hard_links(options.hardlink_policy, log_level)?;
+                        extractor.warnings.report_suppressed(log_level)?;
+                        extractor.summary.warnings = extractor.warnings.total;
+                        Ok(extractor.summary)
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect()
+        });
+        for worker_summary in summaries {
+            summary.add(&worker_summary?);
+        }
+    }
+    Ok(summary)
+}
+
+/// Determine the offset and the decompression command of all cpio archives.
+///
+/// The list ends with the first compressed cpio archive, because the end
+/// of a compressed cpio archive cannot be determined without decompressing it.
+fn read_cpio_segments<R: Read + Seek + SeekForward>(
+    file: &mut R,
+) -> Result<Vec<(u64, Compression)>> {
+    let mut segments = Vec::new();
+    loop {
+        let compression = match read_magic_header(file) {
+            None => break,
             Some(x) => x?,
         };
-        if command.get_program() == "cpio" {
-            read_cpio_and_extract(&mut file, preserve_permissions, log_level)?;
+        let offset = file.stream_position()?;
+        segments.push((offset, compression));
+        if compression.is_uncompressed() {
+            seek_to_cpio_end(file)?;
         } else {
-            let mut decompressed = decompress(&mut command, file)?;
-            read_cpio_and_extract(&mut decompressed, preserve_permissions, log_level)?;
             break;
         }
-        count += 1;
+    }
+    Ok(segments)
+}
+
+/// List the content of the cpio archives using parallel worker threads.
+///
+/// The cpio archives are processed in batches of `jobs` archives. Each
+/// worker thread lists one cpio archive into a buffer and the buffers are
+/// written to `out` in the order of the cpio archives.
+fn list_cpio_content_parallel<W: Write>(
+    file: &mut BufReader<File>,
+    out: &mut W,
+    options: &ListOptions,
+    patterns: &Patterns,
+    log_level: u32,
+    local_time: &LocalTime,
+) -> Result<()> {
+    let segments = read_cpio_segments(file)?;
+    let io_buffer_size = file.capacity();
+    for batch in segments.chunks(options.jobs) {
+        let outputs: Vec<Result<Vec<u8>>> = std::thread::scope(|scope| {
+            let workers: Vec<_> = batch
+                .iter()
+                .map(|(offset, compression)| {
+                    let file = file.get_ref();
+                    scope.spawn(move || -> Result<Vec<u8>> {
+                        let mut output = Vec::new();
+                        let mut user_group_cache = UserGroupCache::new();
+                        if compression.is_uncompressed() {
+                            let mut reader = BufReader::with_capacity(
+                                io_buffer_size,
+                                FileAtReader::new(file, *offset),
+                            );
+                            read_cpio_and_print(
+                                &mut reader,
+                                &mut output,
+                                options,
+                                patterns,
+                                log_level,
+                                local_time,
+                                &mut user_group_cache,
+                            )?;
+                        } else {
+                            let mut compressed = file.try_clone()?;
+                            compressed.seek(SeekFrom::Start(*offset))?;
+                            let mut decompressed = BufReader::with_capacity(
+                                io_buffer_size,
+                                compression.decompress(compressed, log_level)?,
+                            );
+                            read_cpio_and_print(
+                                &mut decompressed,
+                                &mut output,
+                                options,
+                                patterns,
+                                log_level,
+                                local_time,
+                                &mut user_group_cache,
+                            )?;
+                            // Check the decompression (like Decompressed::finish).
+                            std::io::copy(&mut decompressed, &mut std::io::sink())?;
+                        }
+                        Ok(output)
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect()
+        });
+        for output in outputs {
+            out.write_all(&output?)?;
+        }
     }
     Ok(())
 }
 
-pub fn list_cpio_content<W: Write>(mut file: File, out: &mut W, log_level: u32) -> Result<()> {
+/// List the content of the cpio archives.
+///
+/// Print only the names or, with a log level of info or higher, a long
+/// listing similar to `ls -l`.
+/// Only the entries matching the name patterns of the options are listed.
+pub fn list_cpio_content<W: Write>(
+    file: File,
+    out: &mut W,
+    options: &ListOptions,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    read_with_advice(file, |file| {
+        list_cpio_segments(file, out, options, log_level, io_buffer_size)
+    })
+}
+
+fn list_cpio_segments<W: Write>(
+    file: File,
+    out: &mut W,
+    options: &ListOptions,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let patterns = compile_patterns(&options.names, options.match_mode)?;
+    let mut file = BufReader::with_capacity(io_buffer_size, file);
+    check_decompressor_available(&mut file)?;
     let mut user_group_cache = UserGroupCache::new();
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
-        .try_into()
-        .unwrap();
+    let local_time = LocalTime::new();
+    if options.jobs > 1 {
+        return list_cpio_content_parallel(
+            &mut file,
+            out,
+            options,
+            &patterns,
+            log_level,
+            &local_time,
+        );
+    }
     loop {
-        let mut command = match read_magic_header(&mut file) {
+        let compression = match read_magic_header(&mut file) {
             None => return Ok(()),
             Some(x) => x?,
         };
-        if command.get_program() == "cpio" {
-            if log_level >= LOG_LEVEL_INFO {
-                read_cpio_and_print_long_format(&mut file, out, now, &mut user_group_cache)?;
-            } else {
-                read_cpio_and_print_filenames(&mut file, out)?;
-            }
+        if compression.is_uncompressed() {
+            read_cpio_and_print(
+                &mut file,
+                out,
+                options,
+                &patterns,
+                log_level,
+                &local_time,
+                &mut user_group_cache,
+            )?;
         } else {
-            let mut decompressed = decompress(&mut command, file)?;
-            if log_level >= LOG_LEVEL_INFO {
-                read_cpio_and_print_long_format(
-                    &mut decompressed,
-                    out,
-                    now,
-                    &mut user_group_cache,
-                )?;
-            } else {
-                read_cpio_and_print_filenames(&mut decompressed, out)?;
-            }
+            let mut decompressed =
+                decompress_buffered(compression, file, io_buffer_size, log_level)?;
+            read_cpio_and_print(
+                &mut decompressed,
+                out,
+                options,
+                &patterns,
+                log_level,
+                &local_time,
+                &mut user_group_cache,
+            )?;
+            decompressed.finish()?;
             break;
         }
     }
@@ -670,11 +2025,8 @@ pub fn list_cpio_content<W: Write>(mut file: File, out: &mut W, log_level: u32)
 
 #[cfg(test)]
 mod tests {
-    use std::env;
-    use std::os::unix::fs::PermissionsExt;
-
     use super::*;
-    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
 
     fn getgid() -> u32 {
         unsafe { ::libc::getgid() }
@@ -684,10 +2036,6 @@ mod tests {
         unsafe { ::libc::getuid() }
     }
 
-    extern "C" {
-        fn tzset();
-    }
-
     impl UserGroupCache {
         fn insert_test_data(&mut self) {
             self.user_cache.insert(1000, Some("user".into()));
@@ -707,14 +2055,139 @@ mod tests {
     }
 
     #[test]
-    fn test_decompress_program_not_found() {
-        let file = File::open("tests/single.cpio").expect("test cpio should be present");
-        let mut cmd = Command::new("non-existing-program");
-        let got = decompress(&mut cmd, file).unwrap_err();
-        assert_eq!(got.kind(), ErrorKind::Other);
+    fn test_check_decompressor_available_uncompressed() {
+        let mut file = File::open("tests/single.cpio").expect("test cpio should be present");
+        check_decompressor_available(&mut file).unwrap();
+        assert_eq!(file.stream_position().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_entry_name_matches() {
+        assert!(entry_name_matches("./etc/os-release", "etc/os-release"));
+        assert!(entry_name_matches("etc/os-release", "/etc/os-release"));
+        assert!(!entry_name_matches("etc/os-release", "etc/os"));
+    }
+
+    #[test]
+    fn test_find_entry_hard_link() {
+        // Wrapped before mtime and filename
+        let cpio_data = b"0707010000000A000081A4000000000000000000000002\
+        6237389400000000000000000000000000000000000000000000000200000000\
+        a\0\
+        0707010000000A000081A4000000000000000000000002\
+        6237389400000004000000000000000000000000000000000000000200000000\
+        b\0data\
+        0707010000000000000000000000000000000000000001\
+        0000000000000000000000000000000000000000000000000000000B00000000\
+        TRAILER!!!\0\0\0\0";
+        let mut file = cpio_data.as_ref();
+        let header = find_entry(&mut file, "a").unwrap().unwrap();
+        assert_eq!(header.filename, "a");
+        assert_eq!(header.filesize, 4);
+        assert_eq!(
+            file,
+            b"data\
+        0707010000000000000000000000000000000000000001\
+        0000000000000000000000000000000000000000000000000000000B00000000\
+        TRAILER!!!\0\0\0\0"
+        );
+    }
+
+    #[test]
+    fn test_open_entry_compressed() {
+        let file = File::open("tests/xz.cpio").expect("test cpio should be present");
+        let (header, mut reader) = open_entry(file, "usr/bin/sh").unwrap();
+        assert_eq!(header.filename, "usr/bin/sh");
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        assert_eq!(
+            content,
+            "This is a fake busybox binary to simulate a POSIX shell\n"
+        );
+    }
+
+    #[test]
+    fn test_open_entry_not_found() {
+        let file = File::open("tests/single.cpio").expect("test cpio should be present");
+        let got = open_entry(file, "nonexistent").err().unwrap();
+        assert_eq!(got.kind(), ErrorKind::NotFound);
+        assert_eq!(
+            got.to_string(),
+            "Entry 'nonexistent' not found in the cpio archive."
+        );
+    }
+
+    #[test]
+    fn test_open_entry_uncompressed() {
+        let file = File::open("tests/single.cpio").expect("test cpio should be present");
+        let (header, mut reader) = open_entry(file, "./path/file").unwrap();
+        assert_eq!(header.filename, "path/file");
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "content\n");
+    }
+
+    #[test]
+    fn test_list_cpio_content_parallel() {
+        let file = File::open("tests/gzip.cpio").expect("test cpio should be present");
+        let mut output = Vec::new();
+        let options = ListOptions {
+            jobs: 4,
+            ..ListOptions::default()
+        };
+        list_cpio_content(
+            file,
+            &mut output,
+            &options,
+            LOG_LEVEL_WARNING,
+            DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            ".\npath\npath/file\n.\nusr\nusr/bin\nusr/bin/sh\n"
+        );
+    }
+
+    #[test]
+    fn test_list_cpio_content_names() {
+        let file = File::open("tests/gzip.cpio").expect("test cpio should be present");
+        let mut output = Vec::new();
+        let options = ListOptions {
+            names: vec!["usr/bin".into()],
+            match_mode: MatchMode::Prefix,
+            ..ListOptions::default()
+        };
+        list_cpio_content(
+            file,
+            &mut output,
+            &options,
+            LOG_LEVEL_WARNING,
+            DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "usr/bin\nusr/bin/sh\n");
+    }
+
+    #[test]
+    fn test_list_cpio_content_small_io_buffer() {
+        let file = File::open("tests/gzip.cpio").expect("test cpio should be present");
+        let mut output = Vec::new();
+        let options = ListOptions::default();
+        list_cpio_content(file, &mut output, &options, LOG_LEVEL_WARNING, 7).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            ".\npath\npath/file\n.\nusr\nusr/bin\nusr/bin/sh\n"
+        );
+    }
+
+    #[test]
+    fn test_read_cpio_segments() {
+        let mut file = File::open("tests/zstd.cpio").expect("test cpio should be present");
+        let segments = read_cpio_segments(&mut file).unwrap();
         assert_eq!(
-            got.to_string(),
-            "Program 'non-existing-program' not found in PATH."
+            segments,
+            vec![(0, Compression::Uncompressed), (512, Compression::Zstd)]
         );
     }
 
@@ -729,12 +2202,13 @@ mod tests {
         TRAILER!!!\0\0\0\0";
         let mut output = Vec::new();
         let mut user_group_cache = UserGroupCache::new();
-        env::set_var("TZ", "UTC");
-        unsafe { tzset() };
         read_cpio_and_print_long_format(
             &mut cpio_data.as_ref(),
             &mut output,
-            1728486311,
+            &LocalTime::utc(1728486311),
+            &ListOptions::default(),
+            &Patterns::default(),
+            false,
             &mut user_group_cache,
         )
         .unwrap();
@@ -756,12 +2230,13 @@ mod tests {
         let mut output = Vec::new();
         let mut user_group_cache = UserGroupCache::new();
         user_group_cache.insert_test_data();
-        env::set_var("TZ", "UTC");
-        unsafe { tzset() };
         read_cpio_and_print_long_format(
             &mut cpio_data.as_ref(),
             &mut output,
-            1722389471,
+            &LocalTime::utc(1722389471),
+            &ListOptions::default(),
+            &Patterns::default(),
+            false,
             &mut user_group_cache,
         )
         .unwrap();
@@ -784,12 +2259,13 @@ mod tests {
         let mut output = Vec::new();
         let mut user_group_cache = UserGroupCache::new();
         user_group_cache.insert_test_data();
-        env::set_var("TZ", "UTC");
-        unsafe { tzset() };
         read_cpio_and_print_long_format(
             &mut cpio_data.as_ref(),
             &mut output,
-            1722645915,
+            &LocalTime::utc(1722645915),
+            &ListOptions::default(),
+            &Patterns::default(),
+            false,
             &mut user_group_cache,
         )
         .unwrap();
@@ -799,6 +2275,66 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_read_cpio_and_print_long_format_null_terminated() {
+        // Wrapped before mtime and filename
+        let cpio_data = b"0707010000000D0000A1FF000000000000000000000001\
+        6237389400000007000000000000000000000000000000000000000400000000\
+        bin\0\0\0usr/bin\0\
+        0707010000000000000000000000000000000000000001\
+        0000000000000000000000000000000000000000000000000000000B00000000\
+        TRAILER!!!\0\0\0\0";
+        let mut output = Vec::new();
+        let mut user_group_cache = UserGroupCache::new();
+        user_group_cache.insert_test_data();
+        read_cpio_and_print_long_format(
+            &mut cpio_data.as_ref(),
+            &mut output,
+            &LocalTime::utc(1722645915),
+            &ListOptions {
+                null_terminated: true,
+                ..ListOptions::default()
+            },
+            &Patterns::default(),
+            false,
+            &mut user_group_cache,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "lrwxrwxrwx   1 root     root            7 Mar 20  2022 bin -> usr/bin\0"
+        );
+    }
+
+    #[test]
+    fn test_read_cpio_and_print_long_format_show_dev() {
+        // Wrapped before mtime and filename
+        let cpio_data = b"070701000036E4000081A4000003E8000007D000000001\
+        66A3285300000041000000000000002400000000000000000000000D00000000\
+        conf/modules\0\0\
+        linear\nmultipath\nraid0\nraid1\nraid456\nraid5\nraid6\nraid10\nefivarfs\0\0\0\0\
+        0707010000000000000000000000000000000000000001\
+        0000000000000000000000000000000000000000000000000000000B00000000\
+        TRAILER!!!\0\0\0\0";
+        let mut output = Vec::new();
+        let mut user_group_cache = UserGroupCache::new();
+        user_group_cache.insert_test_data();
+        read_cpio_and_print_long_format(
+            &mut cpio_data.as_ref(),
+            &mut output,
+            &LocalTime::utc(1722645915),
+            &ListOptions::default(),
+            &Patterns::default(),
+            true,
+            &mut user_group_cache,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "   0:36 -rw-r--r--   1 user     2000           65 Jul 26 04:38 conf/modules\n"
+        );
+    }
+
     #[test]
     fn test_read_cpio_and_print_long_format_symlink() {
         // Wrapped before mtime and filename
@@ -814,7 +2350,10 @@ mod tests {
         read_cpio_and_print_long_format(
             &mut cpio_data.as_ref(),
             &mut output,
-            1722645915,
+            &LocalTime::utc(1722645915),
+            &ListOptions::default(),
+            &Patterns::default(),
+            false,
             &mut user_group_cache,
         )
         .unwrap();
@@ -837,7 +2376,11 @@ mod tests {
             0,
             "./directory_with_setuid".into(),
         );
-        write_directory(&header, true, LOG_LEVEL_WARNING, &mut mtimes).unwrap();
+        let options = ExtractOptions {
+            preserve_permissions: true,
+            ..ExtractOptions::default()
+        };
+        write_directory(&header, &options, LOG_LEVEL_WARNING, &mut mtimes).unwrap();
 
         let attr = std::fs::metadata("directory_with_setuid").unwrap();
         assert!(attr.is_dir());
@@ -865,10 +2408,14 @@ mod tests {
             "./file_with_setuid".into(),
         );
         let cpio = b"!/bin/sh\n\0\0\0";
+        let options = ExtractOptions {
+            preserve_permissions: true,
+            ..ExtractOptions::default()
+        };
         write_file(
             &mut cpio.as_ref(),
             &header,
-            true,
+            &options,
             &mut seen_files,
             LOG_LEVEL_WARNING,
         )
@@ -884,6 +2431,492 @@ mod tests {
         std::fs::remove_file("file_with_setuid").unwrap();
     }
 
+    /// Create a cpio object in the newc format.
+    pub(crate) fn cpio_object(ino: u32, mode: u32, nlink: u32, name: &str, data: &[u8]) -> Vec<u8> {
+        let filesize = u32::try_from(data.len()).unwrap();
+        let mut object = Vec::new();
+        Header::new(ino, mode, 0, 0, nlink, 0, filesize, name.into())
+            .write(&mut object, 0, None)
+            .unwrap();
+        object.extend_from_slice(data);
+        object.resize(object.len() + align_to_4_bytes(filesize) as usize, 0);
+        object
+    }
+
+    #[test]
+    fn test_read_cpio_and_extract_hard_link_across_archives() {
+        let mut first = cpio_object(7, 0o100_644, 2, "./cross_segment_data", b"data\n");
+        first.extend(cpio_object(0, 0, 1, "TRAILER!!!", b""));
+        let mut second = cpio_object(7, 0o100_644, 2, "./cross_segment_link", b"");
+        second.extend(cpio_object(0, 0, 1, "TRAILER!!!", b""));
+        let options = ExtractOptions {
+            hardlink_policy: HardlinkPolicy::Strict,
+            ..ExtractOptions::default()
+        };
+        let mut extractor = Extractor::new();
+        for cpio in [first, second] {
+            read_cpio_and_extract(&mut cpio.as_slice(), &mut extractor, &options, 0).unwrap();
+        }
+        extractor
+            .finish_hard_links(options.hardlink_policy, 0)
+            .unwrap();
+        let expected = ExtractSummary {
+            files: 1,
+            hard_links: 1,
+            bytes_written: 5,
+            ..ExtractSummary::default()
+        };
+        assert_eq!(extractor.summary, expected);
+
+        let data = std::fs::metadata("cross_segment_data").unwrap();
+        let link = std::fs::metadata("cross_segment_link").unwrap();
+        assert_eq!(data.ino(), link.ino());
+        assert_eq!(std::fs::read("cross_segment_link").unwrap(), b"data\n");
+        std::fs::remove_file("cross_segment_data").unwrap();
+        std::fs::remove_file("cross_segment_link").unwrap();
+    }
+
+    #[test]
+    fn test_read_cpio_and_extract_colliding_inodes() {
+        // Each cpio archive numbers its inodes independently.
+        let mut first = cpio_object(1, 0o100_644, 2, "./collision_a1", b"");
+        first.extend(cpio_object(1, 0o100_644, 2, "./collision_a2", b"AAAA\n"));
+        first.extend(cpio_object(0, 0, 1, "TRAILER!!!", b""));
+        let mut second = cpio_object(1, 0o100_644, 2, "./collision_b1", b"");
+        second.extend(cpio_object(1, 0o100_644, 2, "./collision_b2", b"BB\n"));
+        second.extend(cpio_object(0, 0, 1, "TRAILER!!!", b""));
+        let options = ExtractOptions {
+            hardlink_policy: HardlinkPolicy::Strict,
+            ..ExtractOptions::default()
+        };
+        let mut extractor = Extractor::new();
+        for cpio in [first, second] {
+            read_cpio_and_extract(&mut cpio.as_slice(), &mut extractor, &options, 0).unwrap();
+        }
+        extractor
+            .finish_hard_links(options.hardlink_policy, 0)
+            .unwrap();
+        assert_eq!(extractor.warnings.total, 0);
+
+        for (name, content) in [
+            ("collision_a1", "AAAA\n"),
+            ("collision_a2", "AAAA\n"),
+            ("collision_b1", "BB\n"),
+            ("collision_b2", "BB\n"),
+        ] {
+            assert_eq!(std::fs::read_to_string(name).unwrap(), content);
+        }
+        let a1 = std::fs::metadata("collision_a1").unwrap();
+        let b1 = std::fs::metadata("collision_b1").unwrap();
+        assert_ne!(a1.ino(), b1.ino());
+        for name in ["a1", "a2", "b1", "b2"] {
+            std::fs::remove_file(format!("collision_{}", name)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_extract_cpio_archive_into_directory() {
+        let directory = crate::libc::make_temp_dir().unwrap();
+        let archive = File::open("tests/single.cpio").expect("test cpio should be present");
+        let cwd = std::env::current_dir().unwrap();
+        let options = ExtractOptions {
+            directory: Some(directory.to_str().unwrap().into()),
+            subdir: Some("cpio".into()),
+            ..ExtractOptions::default()
+        };
+        extract_cpio_archive(archive, &options, 0, DEFAULT_IO_BUFFER_SIZE).unwrap();
+
+        assert_eq!(std::env::current_dir().unwrap(), cwd);
+        let content = std::fs::read(directory.join("cpio1/path/file")).unwrap();
+        assert_eq!(content, b"content\n");
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_extract_cpio_archive_parallel() {
+        let directory = crate::libc::make_temp_dir().unwrap();
+        let archive = File::open("tests/gzip.cpio").expect("test cpio should be present");
+        let options = ExtractOptions {
+            directory: Some(directory.to_str().unwrap().into()),
+            subdir: Some("cpio".into()),
+            jobs: 2,
+            ..ExtractOptions::default()
+        };
+        let summary = extract_cpio_archive(archive, &options, 0, DEFAULT_IO_BUFFER_SIZE).unwrap();
+
+        let expected = ExtractSummary {
+            directories: 5,
+            files: 2,
+            bytes_written: 64,
+            ..ExtractSummary::default()
+        };
+        assert_eq!(summary, expected);
+        let content = std::fs::read(directory.join("cpio1/path/file")).unwrap();
+        assert_eq!(content, b"content\n");
+        assert!(directory.join("cpio2/usr/bin/sh").is_file());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_extract_cpio_archive_names() {
+        let directory = crate::libc::make_temp_dir().unwrap();
+        let archive = File::open("tests/gzip.cpio").expect("test cpio should be present");
+        let options = ExtractOptions {
+            directory: Some(directory.to_str().unwrap().into()),
+            names: vec!["*/sh".into()],
+            sorted: true,
+            ..ExtractOptions::default()
+        };
+        let summary = extract_cpio_archive(archive, &options, 0, DEFAULT_IO_BUFFER_SIZE).unwrap();
+
+        let expected = ExtractSummary {
+            files: 1,
+            bytes_written: 56,
+            ..ExtractSummary::default()
+        };
+        assert_eq!(summary, expected);
+        assert!(directory.join("usr/bin/sh").is_file());
+        assert!(!directory.join("path").exists());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_read_cpio_and_extract_into_subdir() {
+        let mut cpio = cpio_object(8, 0o100_644, 2, "./subdir_data", b"data\n");
+        cpio.extend(cpio_object(8, 0o100_644, 2, "./subdir_link", b""));
+        cpio.extend(cpio_object(0, 0, 1, "TRAILER!!!", b""));
+        std::fs::create_dir("extract_subdir1").unwrap();
+        let cwd = std::env::current_dir().unwrap();
+        let mut extractor = Extractor::new();
+        extractor.directory = Some("extract_subdir1".into());
+        let options = ExtractOptions::default();
+        read_cpio_and_extract(&mut cpio.as_slice(), &mut extractor, &options, 0).unwrap();
+
+        assert_eq!(std::env::current_dir().unwrap(), cwd);
+        let data = std::fs::metadata("extract_subdir1/subdir_data").unwrap();
+        let link = std::fs::metadata("extract_subdir1/subdir_link").unwrap();
+        assert_eq!(data.ino(), link.ino());
+        std::fs::remove_dir_all("extract_subdir1").unwrap();
+    }
+
+    #[test]
+    fn test_read_cpio_and_extract_sorted() {
+        // The directory and the hard-link data come after their users.
+        let mut cpio = cpio_object(1, 0o100_644, 1, "./extract_sorted/file", b"file\n");
+        cpio.extend(cpio_object(2, 0o040_755, 2, "./extract_sorted", b""));
+        cpio.extend(cpio_object(3, 0o100_644, 2, "./extract_sorted/b", b""));
+        cpio.extend(cpio_object(
+            3,
+            0o100_644,
+            2,
+            "./extract_sorted/a",
+            b"data\n",
+        ));
+        cpio.extend(cpio_object(0, 0, 1, "TRAILER!!!", b""));
+        let mut extractor = Extractor::new();
+        let options = ExtractOptions {
+            sorted: true,
+            ..ExtractOptions::default()
+        };
+        read_cpio_and_extract_sorted(
+            &mut cpio.as_slice(),
+            &mut extractor,
+            &options,
+            0,
+            DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string("extract_sorted/file").unwrap(),
+            "file\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string("extract_sorted/b").unwrap(),
+            "data\n"
+        );
+        let a = std::fs::metadata("extract_sorted/a").unwrap();
+        let b = std::fs::metadata("extract_sorted/b").unwrap();
+        assert_eq!(a.ino(), b.ino());
+        std::fs::remove_dir_all("extract_sorted").unwrap();
+    }
+
+    #[test]
+    fn test_read_cpio_and_extract_sorted_seekable() {
+        let directory = crate::libc::make_temp_dir().unwrap();
+        let mut cpio = cpio_object(1, 0o100_644, 1, "./dir/file", b"file\n");
+        cpio.extend(cpio_object(2, 0o040_755, 2, "./dir", b""));
+        cpio.extend(cpio_object(3, 0o100_644, 2, "./dir/b", b""));
+        cpio.extend(cpio_object(3, 0o100_644, 2, "./dir/a", b"data\n"));
+        cpio.extend(cpio_object(0, 0, 1, "TRAILER!!!", b""));
+        std::fs::write(directory.join("archive.cpio"), &cpio).unwrap();
+        let mut file = BufReader::new(File::open(directory.join("archive.cpio")).unwrap());
+        let mut extractor = Extractor::new();
+        extractor.directory = Some(directory.to_str().unwrap().into());
+        let options = ExtractOptions {
+            sorted: true,
+            ..ExtractOptions::default()
+        };
+        read_cpio_and_extract_sorted_seekable(&mut file, &mut extractor, &options, 0).unwrap();
+
+        assert_eq!(file.stream_position().unwrap(), cpio.len() as u64);
+        let dir = directory.join("dir");
+        assert_eq!(std::fs::read_to_string(dir.join("file")).unwrap(), "file\n");
+        assert_eq!(std::fs::read_to_string(dir.join("b")).unwrap(), "data\n");
+        let a = std::fs::metadata(dir.join("a")).unwrap();
+        let b = std::fs::metadata(dir.join("b")).unwrap();
+        assert_eq!(a.ino(), b.ino());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_finish_hard_links_missing_member() {
+        let mut extractor = Extractor::new();
+        let record = |extractor: &mut Extractor| {
+            extractor.record_hard_link(&Header::new(5, 0o100_644, 0, 0, 3, 0, 0, "a".into()));
+            extractor.record_hard_link(&Header::new(5, 0o100_644, 0, 0, 3, 0, 4, "b".into()));
+            extractor.record_hard_link(&Header::new(6, 0o100_644, 0, 0, 2, 0, 0, "c".into()));
+            extractor.record_hard_link(&Header::new(6, 0o100_644, 0, 0, 2, 0, 4, "d".into()));
+        };
+        record(&mut extractor);
+        extractor
+            .finish_hard_links(HardlinkPolicy::Lenient, LOG_LEVEL_WARNING)
+            .unwrap();
+        assert_eq!(extractor.warnings.total, 1);
+        record(&mut extractor);
+        let got = extractor
+            .finish_hard_links(HardlinkPolicy::Strict, LOG_LEVEL_WARNING)
+            .unwrap_err();
+        assert_eq!(got.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            got.to_string(),
+            "Hard-link 'a' has 2 entries in the archive, but a link count of 3."
+        );
+    }
+
+    #[test]
+    fn test_hardlink_policy_from_str() {
+        assert_eq!("strict".parse(), Ok(HardlinkPolicy::Strict));
+        assert_eq!(
+            "loose".parse::<HardlinkPolicy>(),
+            Err("Unknown hard-link policy 'loose'. Expected 'strict' or 'lenient'.".into())
+        );
+    }
+
+    #[test]
+    fn test_log_level() {
+        assert_eq!("info".parse(), Ok(LogLevel::Info));
+        assert!(LogLevel::Warning < LogLevel::Debug);
+        assert_eq!(u32::from(LogLevel::Error), LOG_LEVEL_ERROR);
+        assert_eq!(u32::from(LogLevel::Debug), LOG_LEVEL_DEBUG);
+    }
+
+    #[test]
+    fn test_match_mode_from_str() {
+        assert_eq!("globstar".parse(), Ok(MatchMode::Globstar));
+        assert_eq!(
+            "regex".parse::<MatchMode>(),
+            Err(
+                "Unknown match mode 'regex'. Expected 'glob', 'globstar', 'prefix', or 'exact'."
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_patterns_match_modes() {
+        let matches = |mode, pattern: &str, name| {
+            patterns_match(&compile_patterns(&[pattern.into()], mode).unwrap(), name)
+        };
+        assert!(matches(MatchMode::Glob, "usr/*", "./usr/bin/sh"));
+        assert!(!matches(MatchMode::Globstar, "usr/*", "./usr/bin/sh"));
+        assert!(matches(MatchMode::Globstar, "usr/*/sh", "./usr/bin/sh"));
+        assert!(matches(MatchMode::Globstar, "usr/**", "./usr/bin/sh"));
+        assert!(matches(MatchMode::Globstar, "**/sh", "./usr/bin/sh"));
+        assert!(matches(MatchMode::Prefix, "/usr/", "./usr/bin/sh"));
+        assert!(matches(MatchMode::Prefix, "usr", "usr"));
+        assert!(!matches(MatchMode::Prefix, "usr/b", "usr/bin/sh"));
+        assert!(matches(MatchMode::Exact, "usr/bin/sh", "./usr/bin/sh"));
+        assert!(!matches(MatchMode::Exact, "usr/bin/*", "usr/bin/sh"));
+    }
+
+    #[test]
+    fn test_extract_options_mode_perm() {
+        let header = Header::new(1, 0o106_775, 0, 0, 1, 0, 0, "setuid".into());
+        let options = ExtractOptions {
+            umask: Some(0o022),
+            ..ExtractOptions::default()
+        };
+        assert_eq!(options.mode_perm(&header), 0o6755);
+        let options = ExtractOptions {
+            no_setuid: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(options.mode_perm(&header), 0o775);
+    }
+
+    #[test]
+    fn test_extract_options_mode_perm_sanitize_modes() {
+        let header = Header::new(1, 0o43_777, 0, 0, 2, 0, 0, "tmp".into());
+        let options = ExtractOptions {
+            sanitize_modes: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(options.mode_perm(&header), 0o775);
+    }
+
+    #[test]
+    fn test_write_file_metadata_only() {
+        let mut seen_files = SeenFiles::new();
+        let header = Header::new(
+            1,
+            0o100_640,
+            getuid(),
+            getgid(),
+            0,
+            1720081471,
+            9,
+            "./file_placeholder".into(),
+        );
+        let cpio = b"!/bin/sh\n\0\0\0TRAILER";
+        let options = ExtractOptions {
+            metadata_only: true,
+            ..ExtractOptions::default()
+        };
+        let mut reader = cpio.as_ref();
+        write_file(
+            &mut reader,
+            &header,
+            &options,
+            &mut seen_files,
+            LOG_LEVEL_WARNING,
+        )
+        .unwrap();
+
+        assert_eq!(reader, b"TRAILER");
+        let attr = std::fs::metadata("file_placeholder").unwrap();
+        assert_eq!(attr.len(), 0);
+        assert_eq!(attr.permissions(), PermissionsExt::from_mode(header.mode));
+        assert_eq!(attr.modified().unwrap(), from_mtime(header.mtime));
+        std::fs::remove_file("file_placeholder").unwrap();
+    }
+
+    #[test]
+    fn test_extract_entry_metadata_only_char_device() {
+        let mut header = Header::new(
+            1,
+            0o20_600,
+            getuid(),
+            getgid(),
+            1,
+            1720081471,
+            0,
+            "./metadata_only_console".into(),
+        );
+        header.rmajor = 5;
+        header.rminor = 1;
+        let options = ExtractOptions {
+            metadata_only: true,
+            ..ExtractOptions::default()
+        };
+        let mut extractor = Extractor::new();
+        let result = extract_entry(
+            &mut b"TRAILER".as_ref(),
+            header,
+            &mut extractor,
+            &options,
+            LOG_LEVEL_WARNING,
+        );
+        if let Err(e) = result {
+            // Creating device nodes requires CAP_MKNOD.
+            assert_eq!(e.kind(), ErrorKind::PermissionDenied, "{e}");
+            return;
+        }
+        assert_eq!(extractor.summary.special_files, 1);
+
+        let attr = std::fs::symlink_metadata("metadata_only_console").unwrap();
+        assert!(attr.file_type().is_char_device());
+        assert_eq!(attr.rdev(), ::libc::makedev(5, 1));
+        assert_eq!(attr.permissions(), PermissionsExt::from_mode(0o20_600));
+        assert_eq!(attr.modified().unwrap(), from_mtime(1720081471));
+        std::fs::remove_file("metadata_only_console").unwrap();
+    }
+
+    #[test]
+    fn test_write_file_no_cache() {
+        let mut seen_files = SeenFiles::new();
+        let header = Header::new(
+            1,
+            0o100_644,
+            getuid(),
+            getgid(),
+            0,
+            1720081471,
+            9,
+            "./file_uncached".into(),
+        );
+        let cpio = b"!/bin/sh\n\0\0\0";
+        let options = ExtractOptions {
+            no_cache: true,
+            ..ExtractOptions::default()
+        };
+        write_file(
+            &mut cpio.as_ref(),
+            &header,
+            &options,
+            &mut seen_files,
+            LOG_LEVEL_WARNING,
+        )
+        .unwrap();
+
+        assert_eq!(std::fs::read("file_uncached").unwrap(), b"!/bin/sh\n");
+        std::fs::remove_file("file_uncached").unwrap();
+    }
+
+    #[test]
+    fn test_write_file_touch() {
+        let mut seen_files = SeenFiles::new();
+        let header = Header::new(
+            1,
+            0o100_644,
+            getuid(),
+            getgid(),
+            0,
+            1720081471,
+            9,
+            "./file_touched".into(),
+        );
+        let cpio = b"!/bin/sh\n\0\0\0";
+        let options = ExtractOptions {
+            touch: true,
+            ..ExtractOptions::default()
+        };
+        write_file(
+            &mut cpio.as_ref(),
+            &header,
+            &options,
+            &mut seen_files,
+            LOG_LEVEL_WARNING,
+        )
+        .unwrap();
+
+        let attr = std::fs::metadata("file_touched").unwrap();
+        assert_ne!(attr.modified().unwrap(), from_mtime(header.mtime));
+        std::fs::remove_file("file_touched").unwrap();
+    }
+
+    #[test]
+    fn test_warnings_rate_limited() {
+        let mut warnings = Warnings::default();
+        for _ in 0..12 {
+            warnings.warn("symlink mode", "repeated", 0).unwrap();
+        }
+        warnings.warn("hard-link", "once", 0).unwrap();
+        assert_eq!(warnings.total, 13);
+        assert_eq!(warnings.per_kind["symlink mode"], 12);
+        assert_eq!(warnings.per_kind["hard-link"], 1);
+    }
+
     #[test]
     fn test_write_symbolic_link() {
         let header = Header::new(
@@ -897,7 +2930,18 @@ mod tests {
             "./dead_symlink".into(),
         );
         let cpio = b"/nonexistent";
-        write_symbolic_link(&mut cpio.as_ref(), &header, true, LOG_LEVEL_WARNING).unwrap();
+        let options = ExtractOptions {
+            preserve_permissions: true,
+            ..ExtractOptions::default()
+        };
+        write_symbolic_link(
+            &mut cpio.as_ref(),
+            &header,
+            &options,
+            LOG_LEVEL_WARNING,
+            &mut Warnings::default(),
+        )
+        .unwrap();
 
         let attr = std::fs::symlink_metadata("dead_symlink").unwrap();
         assert_eq!(attr.len(), header.filesize.into());
@@ -908,4 +2952,33 @@ mod tests {
         assert_eq!(attr.gid(), header.gid);
         std::fs::remove_file("dead_symlink").unwrap();
     }
+
+    #[test]
+    fn test_write_symbolic_link_non_777_mode() {
+        let header = Header::new(
+            1,
+            0o120_755,
+            getuid(),
+            getgid(),
+            0,
+            1721427072,
+            12,
+            "./symlink_755".into(),
+        );
+        let cpio = b"/nonexistent";
+        let options = ExtractOptions::default();
+        write_symbolic_link(
+            &mut cpio.as_ref(),
+            &header,
+            &options,
+            LOG_LEVEL_WARNING,
+            &mut Warnings::default(),
+        )
+        .unwrap();
+
+        let attr = std::fs::symlink_metadata("symlink_755").unwrap();
+        assert!(attr.is_symlink());
+        assert_eq!(attr.modified().unwrap(), from_mtime(header.mtime));
+        std::fs::remove_file("symlink_755").unwrap();
+    }
 }
@@ -1,4 +1,5 @@
-use std::ffi::{CStr, CString};
+use std::ffi::CString;
+use std::fs::File;
 use std::io::{Error, Result};
 
 /// Get password file entry and return user name.
@@ -59,10 +60,33 @@ pub fn getgrgid_name(gid: u32) -> Result<Option<String>> {
     Ok(Some(name.to_string_lossy().to_string()))
 }
 
+/// Type of the seconds since the epoch (32-bit on some 32-bit platforms)
+pub type TimeT = libc::time_t;
+
+/// Convert the timestamp to `TimeT`.
+///
+/// Timestamps outside the range of `TimeT` (e.g. after 2038 with a 32-bit
+/// `time_t`) are clamped to the minimum or maximum value.
+pub fn to_time_t(timestamp: i64) -> TimeT {
+    TimeT::try_from(timestamp).unwrap_or(if timestamp < 0 {
+        TimeT::MIN
+    } else {
+        TimeT::MAX
+    })
+}
+
+/// Create a `timespec` for the timestamp (in seconds since the epoch).
+pub fn timespec_new(timestamp: i64) -> libc::timespec {
+    // Avoid the timespec struct literal, because it has private padding
+    // fields on some platforms.
+    let mut time: libc::timespec = unsafe { std::mem::zeroed() };
+    time.tv_sec = to_time_t(timestamp);
+    time
+}
+
 pub fn set_modified(path: &str, mtime: i64) -> Result<()> {
     let p = CString::new(path)?;
-    let mut modified: libc::timespec = unsafe { std::mem::zeroed() };
-    modified.tv_sec = mtime;
+    let modified = timespec_new(mtime);
     // times contains the access time followed by modfied time
     let times = [modified, modified];
     let rc = unsafe {
@@ -79,52 +103,116 @@ pub fn set_modified(path: &str, mtime: i64) -> Result<()> {
     Ok(())
 }
 
-// TODO: Use c"…" string literal for `format` once stable
-fn strftime(format: &[u8], tm: *mut libc::tm) -> Result<String> {
-    let mut s = [0u8; 19];
-    let length = unsafe {
-        libc::strftime(
-            s.as_mut_ptr() as *mut libc::c_char,
-            s.len(),
-            CStr::from_bytes_with_nul_unchecked(format).as_ptr(),
-            tm,
+/// Change the permissions of a symlink (without following it).
+///
+/// Linux does not support changing the mode of symlinks and fails with
+/// an error of kind `ErrorKind::Unsupported`.
+pub fn set_symlink_permissions(path: &str, mode: u32) -> Result<()> {
+    let p = CString::new(path)?;
+    let rc = unsafe {
+        libc::fchmodat(
+            libc::AT_FDCWD,
+            p.as_ptr(),
+            mode as libc::mode_t,
+            libc::AT_SYMLINK_NOFOLLOW,
         )
     };
-    if length == 0 {
-        return Err(Error::other("strftime returned 0"));
-    }
-    Ok(String::from_utf8_lossy(&s[..length]).to_string())
+    if rc != 0 {
+        return Err(Error::last_os_error());
+    };
+    Ok(())
 }
 
-pub fn strftime_local(format: &[u8], timestamp: u32) -> Result<String> {
-    let mut tm = std::mem::MaybeUninit::<libc::tm>::uninit();
-    let result = unsafe { libc::localtime_r(&timestamp.into(), tm.as_mut_ptr()) };
-    if result.is_null() {
+/// Create a device node or a named pipe (FIFO).
+///
+/// This function wraps the standard C library function mknod(). The `mode`
+/// contains the file type and the permissions. The device number is only
+/// used for character and block devices.
+pub fn mknod(path: &str, mode: u32, major: u32, minor: u32) -> Result<()> {
+    let p = CString::new(path)?;
+    let dev = libc::makedev(major as _, minor as _);
+    let rc = unsafe { libc::mknod(p.as_ptr(), mode as libc::mode_t, dev) };
+    if rc != 0 {
         return Err(Error::last_os_error());
     };
-    strftime(format, result)
+    Ok(())
+}
+
+/// Expected access pattern for file data (see posix_fadvise(2))
+#[derive(Clone, Copy)]
+pub enum Advice {
+    /// The data will be read sequentially.
+    Sequential,
+    /// The data will be accessed in the near future.
+    WillNeed,
+    /// The data will be accessed only once.
+    NoReuse,
+    /// The data will not be accessed in the near future.
+    DontNeed,
+}
+
+/// Announce the expected access pattern for the whole file.
+///
+/// This function wraps the standard C library function posix_fadvise().
+/// The advice is only a hint for the kernel. It does nothing on platforms
+/// without posix_fadvise() (e.g. macOS).
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub fn fadvise(file: &File, advice: Advice) -> Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let advice = match advice {
+        Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+        Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+        Advice::NoReuse => libc::POSIX_FADV_NOREUSE,
+        Advice::DontNeed => libc::POSIX_FADV_DONTNEED,
+    };
+    // posix_fadvise() returns the error number instead of setting errno.
+    let rc = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, advice) };
+    if rc != 0 {
+        return Err(Error::from_raw_os_error(rc));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+pub fn fadvise(_file: &File, _advice: Advice) -> Result<()> {
+    Ok(())
+}
+
+/// Create a unique temporary directory for tests.
+///
+/// This function wraps the standard C library function mkdtemp(), which
+/// picks a random name and retries on collisions. So it is safe to call
+/// from tests that run in parallel.
+#[cfg(test)]
+pub fn make_temp_dir() -> Result<std::path::PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+
+    let template = std::env::temp_dir().join("3cpio-XXXXXX");
+    let mut template = CString::new(template.into_os_string().into_vec())?.into_bytes_with_nul();
+    let rc = unsafe { libc::mkdtemp(template.as_mut_ptr() as *mut libc::c_char) };
+    if rc.is_null() {
+        return Err(Error::last_os_error());
+    }
+    template.pop();
+    Ok(std::ffi::OsString::from_vec(template).into())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::env::temp_dir;
-    use std::fs::{self, create_dir};
+    use std::fs;
     use std::path::PathBuf;
     use std::time::{Duration, SystemTime};
 
-    fn make_temp_dir() -> Result<PathBuf> {
-        let mut dir = temp_dir();
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap();
-        dir.push(format!("3cpio-{:?}", now));
-        create_dir(&dir)?;
-        Ok(dir)
-    }
-
-    extern "C" {
-        fn tzset();
+    #[test]
+    fn test_make_temp_dir() {
+        let first = make_temp_dir().unwrap();
+        let second = make_temp_dir().unwrap();
+        assert_ne!(first, second);
+        assert!(first.is_dir());
+        fs::remove_dir(first).unwrap();
+        fs::remove_dir(second).unwrap();
     }
 
     #[test]
@@ -153,6 +241,30 @@ mod tests {
         assert_eq!(got, None);
     }
 
+    #[test]
+    fn test_to_time_t() {
+        assert_eq!(to_time_t(1720735264), 1720735264);
+        if std::mem::size_of::<TimeT>() == 4 {
+            assert_eq!(to_time_t(4354819200), TimeT::MAX);
+            assert_eq!(to_time_t(-4354819200), TimeT::MIN);
+        } else {
+            assert_eq!(to_time_t(4354819200) as i64, 4354819200);
+        }
+    }
+
+    #[test]
+    fn test_fadvise() {
+        let file = File::open("tests/single.cpio").expect("test cpio should be present");
+        for advice in [
+            Advice::Sequential,
+            Advice::WillNeed,
+            Advice::NoReuse,
+            Advice::DontNeed,
+        ] {
+            fadvise(&file, advice).unwrap();
+        }
+    }
+
     #[test]
     // Create a temporary directory and set the mtime 10 seconds earlier
     // than the current mtime of the directory.
@@ -171,18 +283,4 @@ mod tests {
         assert_eq!(dir.metadata().unwrap().modified().unwrap(), new_modified);
         fs::remove_dir(dir).unwrap();
     }
-
-    #[test]
-    fn test_strftime_local_year() {
-        let time = strftime_local(b"%b %e  %Y\0", 2278410030).unwrap();
-        assert_eq!(time, "Mar 14  2042");
-    }
-
-    #[test]
-    fn test_strftime_local_hour() {
-        std::env::set_var("TZ", "UTC");
-        unsafe { tzset() };
-        let time = strftime_local(b"%b %e %H:%M\0", 1720735264).unwrap();
-        assert_eq!(time, "Jul 11 22:01");
-    }
 }
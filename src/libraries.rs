@@ -0,0 +1,217 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Check that the shared libraries needed by the ELF files are in the archive.
+//!
+//! The library search path of the dynamic loader depends on ld.so.conf and
+//! the architecture. So a needed library is considered present if any file
+//! or symlink in the cpio archives has its name. Needed libraries given as
+//! path and the interpreter (dynamic loader) are resolved like symlinks.
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Write};
+
+use crate::elf::{parse_dynamic_info, DynamicInfo, ELF_MAGIC};
+use crate::header::*;
+use crate::metadata::FileType;
+use crate::seek_forward::SeekForward;
+use crate::symlinks::{Kind, Namespace};
+use crate::{
+    align_to_4_bytes, check_decompressor_available, decompress_buffered, read_magic_header,
+    LOG_LEVEL_INFO, LOG_LEVEL_WARNING,
+};
+
+/// Entries of the cpio archives and the dynamically linked ELF files
+#[derive(Default)]
+struct Libraries {
+    namespace: Namespace,
+    /// File names (without directory) of all files and symlinks
+    names: BTreeSet<String>,
+    elf_files: Vec<(String, DynamicInfo)>,
+}
+
+impl Libraries {
+    fn insert(&mut self, name: &str, kind: Kind) {
+        if !matches!(kind, Kind::Directory) {
+            let basename = name.rsplit('/').next().unwrap_or(name);
+            self.names.insert(basename.to_string());
+        }
+        self.namespace.insert(name, kind);
+    }
+
+    fn is_present(&self, needed: &str) -> bool {
+        if needed.contains('/') {
+            self.namespace.resolve("", needed).is_ok()
+        } else {
+            self.names.contains(needed)
+        }
+    }
+
+    /// Return the interpreter and the needed libraries that are missing.
+    fn missing(&self, info: &DynamicInfo) -> Vec<String> {
+        info.interpreter
+            .iter()
+            .chain(info.needed.iter())
+            .filter(|needed| !self.is_present(needed))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Read the content of the regular file and parse it if it is an ELF file.
+fn read_dynamic_info<R: Read + SeekForward>(
+    file: &mut R,
+    header: &Header,
+    log_level: u32,
+) -> Result<Option<DynamicInfo>> {
+    let mut content = Vec::new();
+    let mut reader = file.take(header.filesize.into());
+    reader.by_ref().take(4).read_to_end(&mut content)?;
+    let info = if content == ELF_MAGIC {
+        reader.read_to_end(&mut content)?;
+        match parse_dynamic_info(&content) {
+            Ok(info) => Some(info),
+            Err(e) => {
+                if log_level >= LOG_LEVEL_WARNING {
+                    eprintln!("Warning: Failed to parse '{}': {}", header.filename, e);
+                }
+                None
+            }
+        }
+    } else {
+        std::io::copy(&mut reader, &mut std::io::sink())?;
+        None
+    };
+    if reader.limit() > 0 {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("Content of '{}' is truncated.", header.filename),
+        ));
+    }
+    file.seek_forward(align_to_4_bytes(header.filesize).into())?;
+    Ok(info.filter(|info| info.interpreter.is_some() || !info.needed.is_empty()))
+}
+
+fn read_cpio_and_collect_libraries<R: Read + SeekForward>(
+    file: &mut R,
+    libraries: &mut Libraries,
+    log_level: u32,
+) -> Result<()> {
+    loop {
+        let header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            break;
+        }
+        match header.file_type() {
+            Some(FileType::Directory) => {
+                header.skip_file_content(file)?;
+                libraries.insert(&header.filename, Kind::Directory);
+            }
+            Some(FileType::Symlink) => {
+                let target = header.read_symlink_target(file)?;
+                libraries.insert(&header.filename, Kind::Symlink(target));
+            }
+            Some(FileType::RegularFile) => {
+                if let Some(info) = read_dynamic_info(file, &header, log_level)? {
+                    libraries.elf_files.push((header.filename.clone(), info));
+                }
+                libraries.insert(&header.filename, Kind::Other(header.mode));
+            }
+            _ => {
+                header.skip_file_content(file)?;
+                libraries.insert(&header.filename, Kind::Other(header.mode));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that the libraries needed by the ELF files exist in the cpio archives.
+///
+/// Parse the dynamic section of all ELF files and report each needed
+/// library (and interpreter) that is not found in the cpio archives to
+/// `out` (and each ELF file without missing libraries as well if the log
+/// level is info or higher). Fail if at least one library is missing.
+pub fn check_libraries<W: Write>(
+    archive: File,
+    out: &mut W,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let mut file = BufReader::with_capacity(io_buffer_size, archive);
+    check_decompressor_available(&mut file)?;
+    let mut libraries = Libraries::default();
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if compression.is_uncompressed() {
+            read_cpio_and_collect_libraries(&mut file, &mut libraries, log_level)?;
+        } else {
+            let mut decompressed =
+                decompress_buffered(compression, file, io_buffer_size, log_level)?;
+            read_cpio_and_collect_libraries(&mut decompressed, &mut libraries, log_level)?;
+            decompressed.finish()?;
+            break;
+        }
+    }
+    let mut failed = 0;
+    for (name, info) in &libraries.elf_files {
+        let missing = libraries.missing(info);
+        if missing.is_empty() {
+            if log_level >= LOG_LEVEL_INFO {
+                writeln!(out, "{}: OK", name)?;
+            }
+            continue;
+        }
+        failed += 1;
+        for library in missing {
+            writeln!(out, "{}: {} not found", name, library)?;
+        }
+    }
+    if failed > 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} of {} dynamically linked ELF files have missing libraries.",
+                failed,
+                libraries.elf_files.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing() {
+        let mut libraries = Libraries::default();
+        libraries.insert("lib64", Kind::Symlink("usr/lib64".into()));
+        libraries.insert("usr/lib64/ld-linux-x86-64.so.2", Kind::Other(0o100_755));
+        libraries.insert("usr/lib/x86_64-linux-gnu/libc.so.6", Kind::Other(0o100_755));
+        let info = DynamicInfo {
+            interpreter: Some("/lib64/ld-linux-x86-64.so.2".into()),
+            needed: vec!["libgcc_s.so.1".into(), "libc.so.6".into()],
+        };
+        assert_eq!(libraries.missing(&info), vec!["libgcc_s.so.1".to_string()]);
+    }
+
+    #[test]
+    fn test_missing_interpreter() {
+        let mut libraries = Libraries::default();
+        libraries.insert("usr/lib/libc.so.6", Kind::Other(0o100_755));
+        let info = DynamicInfo {
+            interpreter: Some("/lib/ld-linux.so.2".into()),
+            needed: vec!["libc.so.6".into()],
+        };
+        assert_eq!(
+            libraries.missing(&info),
+            vec!["/lib/ld-linux.so.2".to_string()]
+        );
+    }
+}
@@ -0,0 +1,541 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! List the content of the cpio archives in alternative output formats.
+
+use std::fs::File;
+use std::io::{BufReader, Read, Result, Seek, Write};
+
+use crate::header::*;
+use crate::localtime::LocalTime;
+use crate::metadata::FileType;
+use crate::seek_forward::SeekForward;
+use crate::{
+    check_decompressor_available, compile_patterns, decompress_buffered, patterns_match,
+    read_magic_header, CountingReader, ListOptions, Patterns, UserGroupCache, LOG_LEVEL_WARNING,
+};
+
+/// Size of the blocks counted by GNU cpio
+const CPIO_BLOCK_SIZE: u64 = 512;
+
+/// Output format for listing the content of the cpio archives
+#[derive(Debug, PartialEq)]
+pub enum ListFormat {
+    /// Long listing matching the output of `cpio -tv` from GNU cpio
+    Cpio,
+    /// One line per entry following the template (e.g. `{mode} {size} {name}`)
+    Template(Vec<Token>),
+    /// Tab-separated offsets of the header and the data in the file, the
+    /// data length, and the name (only for uncompressed cpio archives)
+    Offsets,
+}
+
+/// Part of a list format template
+#[derive(Debug, PartialEq)]
+pub enum Token {
+    Literal(String),
+    Field(Field),
+}
+
+/// Entry field that can be used in a list format template
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    /// ls-style mode string (e.g. `-rw-r--r--`)
+    Mode,
+    Nlink,
+    /// User name (or user ID if the name is unknown)
+    User,
+    /// Group name (or group ID if the name is unknown)
+    Group,
+    Uid,
+    Gid,
+    /// File size in bytes
+    Size,
+    /// Modification time in seconds since the epoch
+    Mtime,
+    Name,
+    /// Target of symlinks (empty for other entries)
+    Target,
+    /// Number of the cpio archive (starting at 1)
+    Segment,
+    Ino,
+    /// Major and minor number of the device containing the file (e.g. `8:1`)
+    Dev,
+}
+
+impl std::str::FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let field = match s {
+            "mode" => Self::Mode,
+            "nlink" => Self::Nlink,
+            "user" => Self::User,
+            "group" => Self::Group,
+            "uid" => Self::Uid,
+            "gid" => Self::Gid,
+            "size" => Self::Size,
+            "mtime" => Self::Mtime,
+            "name" => Self::Name,
+            "target" => Self::Target,
+            "segment" => Self::Segment,
+            "ino" => Self::Ino,
+            "dev" => Self::Dev,
+            _ => return Err(format!("Unknown field '{{{}}}' in list format.", s)),
+        };
+        Ok(field)
+    }
+}
+
+/// Parse the list format template. `{{` and `}}` are literal braces.
+fn parse_template(template: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("Unclosed '{{{}' in list format.", name)),
+                    }
+                }
+                if !literal.is_empty() {
+                    tokens.push(Token::Literal(std::mem::take(&mut literal)));
+                }
+                tokens.push(Token::Field(name.parse()?));
+            }
+            '}' => return Err("Unmatched '}' in list format.".into()),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+    Ok(tokens)
+}
+
+impl std::str::FromStr for ListFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "cpio" => Ok(Self::Cpio),
+            _ if s.contains('{') => Ok(Self::Template(parse_template(s)?)),
+            _ => Err(format!("Unknown list format '{}'.", s)),
+        }
+    }
+}
+
+/// Format the time like GNU cpio does (based on ctime).
+///
+/// Files older than six months (of 30 days) or from the future show the
+/// year instead of the time of day.
+fn format_cpio_time(timestamp: u32, local_time: &LocalTime) -> Result<String> {
+    let age = local_time.now - i64::from(timestamp);
+    local_time.format(timestamp.into(), (0..=6 * 30 * 24 * 60 * 60).contains(&age))
+}
+
+fn read_cpio_and_print_cpio_format<R: Read + SeekForward, W: Write>(
+    file: &mut R,
+    out: &mut W,
+    options: &ListOptions,
+    patterns: &Patterns,
+    local_time: &LocalTime,
+    user_group_cache: &mut UserGroupCache,
+) -> Result<()> {
+    loop {
+        let header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            break;
+        }
+        if !patterns_match(patterns, &header.filename) {
+            header.skip_file_content(file)?;
+            continue;
+        }
+        let user = match user_group_cache.get_user(header.uid)? {
+            Some(name) => name,
+            None => header.uid.to_string(),
+        };
+        let group = match user_group_cache.get_group(header.gid)? {
+            Some(name) => name,
+            None => header.gid.to_string(),
+        };
+        let mode_string = header.mode_string();
+        let size = match header.file_type() {
+            Some(FileType::BlockDevice | FileType::CharacterDevice) => {
+                format!("{:>3}, {:>3}", header.rmajor, header.rminor)
+            }
+            _ => format!("{:>8}", header.filesize),
+        };
+        write!(
+            out,
+            "{} {:>3} {:<8.8} {:<8.8} {} {} {}",
+            std::str::from_utf8(&mode_string).unwrap(),
+            header.nlink,
+            user,
+            group,
+            size,
+            format_cpio_time(header.mtime, local_time)?,
+            options.quoting_style.quote(&header.filename)
+        )?;
+        if header.file_type() == Some(FileType::Symlink) {
+            let target = header.read_symlink_target(file)?;
+            write!(out, " -> {}", options.quoting_style.quote(&target))?;
+        } else {
+            header.skip_file_content(file)?;
+        }
+        out.write_all(options.terminator().as_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_cpio_and_print_template<R: Read + SeekForward, W: Write>(
+    file: &mut R,
+    out: &mut W,
+    tokens: &[Token],
+    options: &ListOptions,
+    patterns: &Patterns,
+    segment: u32,
+    user_group_cache: &mut UserGroupCache,
+) -> Result<()> {
+    let needs_target = tokens.contains(&Token::Field(Field::Target));
+    loop {
+        let header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            break;
+        }
+        if !patterns_match(patterns, &header.filename) {
+            header.skip_file_content(file)?;
+            continue;
+        }
+        let target = if needs_target && header.file_type() == Some(FileType::Symlink) {
+            header.read_symlink_target(file)?
+        } else {
+            header.skip_file_content(file)?;
+            String::new()
+        };
+        for token in tokens {
+            let field = match token {
+                Token::Literal(literal) => {
+                    out.write_all(literal.as_bytes())?;
+                    continue;
+                }
+                Token::Field(field) => field,
+            };
+            match field {
+                Field::Mode => out.write_all(&header.mode_string())?,
+                Field::Nlink => write!(out, "{}", header.nlink)?,
+                Field::User => match user_group_cache.get_user(header.uid)? {
+                    Some(name) => write!(out, "{}", name)?,
+                    None => write!(out, "{}", header.uid)?,
+                },
+                Field::Group => match user_group_cache.get_group(header.gid)? {
+                    Some(name) => write!(out, "{}", name)?,
+                    None => write!(out, "{}", header.gid)?,
+                },
+                Field::Uid => write!(out, "{}", header.uid)?,
+                Field::Gid => write!(out, "{}", header.gid)?,
+                Field::Size => write!(out, "{}", header.filesize)?,
+                Field::Mtime => write!(out, "{}", header.mtime)?,
+                Field::Name => write!(out, "{}", options.quoting_style.quote(&header.filename))?,
+                Field::Target => write!(out, "{}", options.quoting_style.quote(&target))?,
+                Field::Segment => write!(out, "{}", segment)?,
+                Field::Ino => write!(out, "{}", header.ino)?,
+                Field::Dev => write!(out, "{}:{}", header.major, header.minor)?,
+            }
+        }
+        out.write_all(options.terminator().as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Print the offsets of the entries. `start` is the offset of the reader in the file.
+fn read_cpio_and_print_offsets<R: Read + SeekForward, W: Write>(
+    file: &mut CountingReader<R>,
+    out: &mut W,
+    options: &ListOptions,
+    patterns: &Patterns,
+    start: u64,
+) -> Result<()> {
+    loop {
+        let header_offset = start + file.count;
+        let header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            break;
+        }
+        if !patterns_match(patterns, &header.filename) {
+            header.skip_file_content(file)?;
+            continue;
+        }
+        write!(
+            out,
+            "{}\t{}\t{}\t{}{}",
+            header_offset,
+            start + file.count,
+            header.filesize,
+            options.quoting_style.quote(&header.filename),
+            options.terminator()
+        )?;
+        header.skip_file_content(file)?;
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn read_cpio_and_print<R: Read + SeekForward, W: Write>(
+    file: &mut CountingReader<R>,
+    out: &mut W,
+    format: &ListFormat,
+    options: &ListOptions,
+    patterns: &Patterns,
+    segment: u32,
+    start: u64,
+    local_time: &LocalTime,
+    user_group_cache: &mut UserGroupCache,
+) -> Result<()> {
+    match format {
+        ListFormat::Offsets => read_cpio_and_print_offsets(file, out, options, patterns, start),
+        ListFormat::Cpio => read_cpio_and_print_cpio_format(
+            file,
+            out,
+            options,
+            patterns,
+            local_time,
+            user_group_cache,
+        ),
+        ListFormat::Template(tokens) => read_cpio_and_print_template(
+            file,
+            out,
+            tokens,
+            options,
+            patterns,
+            segment,
+            user_group_cache,
+        ),
+    }
+}
+
+/// List the content of all cpio archives in the given format.
+///
+/// For the `cpio` format, the number of read 512-byte blocks is printed to
+/// stderr at the end like GNU cpio does (which only lists the first cpio
+/// archive though). The offsets format stops with a warning at the first
+/// compressed cpio archive.
+///
+/// Only the entries matching the name patterns of the options are listed.
+/// The names (and symlink targets) are quoted and terminated as specified
+/// in the options. The number of jobs is ignored.
+pub fn list_cpio_content_formatted<W: Write>(
+    archive: File,
+    out: &mut W,
+    format: &ListFormat,
+    options: &ListOptions,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let patterns = compile_patterns(&options.names, options.match_mode)?;
+    let mut file = BufReader::with_capacity(io_buffer_size, archive);
+    check_decompressor_available(&mut file)?;
+    let mut user_group_cache = UserGroupCache::new();
+    let local_time = LocalTime::new();
+    let mut bytes = 0;
+    let mut segment = 1;
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if compression.is_uncompressed() {
+            let start = file.stream_position()?;
+            let mut counting = CountingReader::new(file);
+            read_cpio_and_print(
+                &mut counting,
+                out,
+                format,
+                options,
+                &patterns,
+                segment,
+                start,
+                &local_time,
+                &mut user_group_cache,
+            )?;
+            bytes += counting.count;
+            file = counting.inner;
+        } else if *format == ListFormat::Offsets {
+            if log_level >= LOG_LEVEL_WARNING {
+                eprintln!(
+                    "Warning: Skipping the {} compressed cpio archive at offset {} (file offsets are only available for uncompressed cpio archives).",
+                    compression,
+                    file.stream_position()?
+                );
+            }
+            break;
+        } else {
+            let decompressed = decompress_buffered(compression, file, io_buffer_size, log_level)?;
+            let mut counting = CountingReader::new(decompressed);
+            read_cpio_and_print(
+                &mut counting,
+                out,
+                format,
+                options,
+                &patterns,
+                segment,
+                0,
+                &local_time,
+                &mut user_group_cache,
+            )?;
+            bytes += counting.count;
+            counting.inner.finish()?;
+            break;
+        }
+        segment += 1;
+    }
+    if *format == ListFormat::Cpio {
+        let blocks = bytes.div_ceil(CPIO_BLOCK_SIZE);
+        writeln!(
+            std::io::stderr(),
+            "{} {}",
+            blocks,
+            if blocks == 1 { "block" } else { "blocks" }
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_cpio_time() {
+        let local_time = LocalTime::utc(1713104326);
+        let timestamp = 1713104326 - 170 * 24 * 60 * 60;
+        assert_eq!(
+            format_cpio_time(timestamp, &local_time).unwrap(),
+            "Oct 27 14:18"
+        );
+        let timestamp = 1713104326 - 190 * 24 * 60 * 60;
+        assert_eq!(
+            format_cpio_time(timestamp, &local_time).unwrap(),
+            "Oct  7  2023"
+        );
+        let future = 1713104326 + 60;
+        assert_eq!(
+            format_cpio_time(future, &local_time).unwrap(),
+            "Apr 14  2024"
+        );
+    }
+
+    #[test]
+    fn test_list_cpio_content_template() {
+        let archive = File::open("tests/gzip.cpio").expect("test cpio should be present");
+        let format = "{segment}:{ino} {mode} {size} {name}".parse().unwrap();
+        let mut output = Vec::new();
+        list_cpio_content_formatted(
+            archive,
+            &mut output,
+            &format,
+            &ListOptions::default(),
+            LOG_LEVEL_WARNING,
+            crate::DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert!(lines[0].starts_with("1:"), "{}", lines[0]);
+        assert!(lines[0].ends_with(" drwxrwxr-x 0 ."), "{}", lines[0]);
+        assert!(lines[6].starts_with("2:"), "{}", lines[6]);
+        assert!(
+            lines[6].ends_with(" -rw-rw-r-- 56 usr/bin/sh"),
+            "{}",
+            lines[6]
+        );
+    }
+
+    #[test]
+    fn test_list_cpio_content_offsets() {
+        let archive = File::open("tests/single.cpio").expect("test cpio should be present");
+        let mut output = Vec::new();
+        list_cpio_content_formatted(
+            archive,
+            &mut output,
+            &ListFormat::Offsets,
+            &ListOptions::default(),
+            LOG_LEVEL_WARNING,
+            crate::DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "0\t112\t0\t.\n112\t228\t0\tpath\n228\t348\t8\tpath/file\n"
+        );
+    }
+
+    #[test]
+    fn test_list_cpio_content_template_quoted() {
+        let archive = File::open("tests/single.cpio").expect("test cpio should be present");
+        let format = "{size} {name}".parse().unwrap();
+        let options = ListOptions {
+            quoting_style: crate::QuotingStyle::C,
+            null_terminated: true,
+            ..ListOptions::default()
+        };
+        let mut output = Vec::new();
+        list_cpio_content_formatted(
+            archive,
+            &mut output,
+            &format,
+            &options,
+            LOG_LEVEL_WARNING,
+            crate::DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "0 \".\"\x000 \"path\"\x008 \"path/file\"\x00"
+        );
+    }
+
+    #[test]
+    fn test_parse_template() {
+        assert_eq!(
+            parse_template("{{{name}}} {size}").unwrap(),
+            vec![
+                Token::Literal("{".into()),
+                Token::Field(Field::Name),
+                Token::Literal("} ".into()),
+                Token::Field(Field::Size),
+            ]
+        );
+        assert_eq!(
+            parse_template("{name").unwrap_err(),
+            "Unclosed '{name' in list format."
+        );
+        assert_eq!(
+            parse_template("{size} {owner}").unwrap_err(),
+            "Unknown field '{owner}' in list format."
+        );
+    }
+
+    #[test]
+    fn test_list_format_from_str() {
+        assert_eq!("cpio".parse(), Ok(ListFormat::Cpio));
+        assert_eq!(
+            "tar".parse::<ListFormat>(),
+            Err("Unknown list format 'tar'.".into())
+        );
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Format timestamps in the local time zone.
+
+use std::io::{Error, Result};
+use std::time::SystemTime;
+
+use tz::{DateTime, TimeZone};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Current time and local time zone for formatting timestamps
+pub(crate) struct LocalTime {
+    /// Current time in seconds since the Unix epoch
+    pub(crate) now: i64,
+    time_zone: TimeZone,
+}
+
+impl LocalTime {
+    /// Use the current time and the local time zone (from the `TZ`
+    /// environment variable or /etc/localtime). Fall back to UTC in case
+    /// the local time zone cannot be determined (like the C library does).
+    pub(crate) fn new() -> Self {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            .try_into()
+            .unwrap();
+        let time_zone = TimeZone::local().unwrap_or_else(|_| TimeZone::utc());
+        Self { now, time_zone }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn utc(now: i64) -> Self {
+        Self {
+            now,
+            time_zone: TimeZone::utc(),
+        }
+    }
+
+    /// Format the timestamp like strftime() with the format `%b %e %H:%M`
+    /// (when `time_of_day` is set) or `%b %e  %Y`.
+    pub(crate) fn format(&self, timestamp: i64, time_of_day: bool) -> Result<String> {
+        let time = DateTime::from_timespec(timestamp, 0, self.time_zone.as_ref())
+            .map_err(|e| Error::other(format!("Failed to convert time {}: {}", timestamp, e)))?;
+        let month = MONTHS[usize::from(time.month() - 1)];
+        if time_of_day {
+            Ok(format!(
+                "{} {:>2} {:02}:{:02}",
+                month,
+                time.month_day(),
+                time.hour(),
+                time.minute()
+            ))
+        } else {
+            Ok(format!(
+                "{} {:>2}  {}",
+                month,
+                time.month_day(),
+                time.year()
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_year() {
+        let local_time = LocalTime::utc(0);
+        let time = local_time.format(2278410030, false).unwrap();
+        assert_eq!(time, "Mar 14  2042");
+    }
+
+    #[test]
+    fn test_format_time_of_day() {
+        let local_time = LocalTime::utc(0);
+        let time = local_time.format(1720735264, true).unwrap();
+        assert_eq!(time, "Jul 11 22:01");
+    }
+
+    #[test]
+    fn test_format_after_2106() {
+        let local_time = LocalTime::utc(0);
+        let time = local_time.format(4354819200, false).unwrap();
+        assert_eq!(time, "Jan  1  2108");
+    }
+}
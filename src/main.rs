@@ -1,55 +1,310 @@
 // Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
 // SPDX-License-Identifier: ISC
 
-use std::env::set_current_dir;
-use std::fs::{create_dir, read_dir, File};
-use std::io::ErrorKind;
+use std::fs::{create_dir, read_dir, File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Seek, Write};
+use std::os::fd::FromRawFd;
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
 use std::process::ExitCode;
 
 use lexopt::prelude::*;
 
 use threecpio::{
-    examine_cpio_content, extract_cpio_archive, list_cpio_content, LOG_LEVEL_DEBUG, LOG_LEVEL_INFO,
+    cat_cpio_content, check_boot, check_libraries, check_symlinks, concatenate_cpio_files,
+    count_cpio_archives, count_cpio_content, count_matching_entries, delete_entries, diff_manifest,
+    drop_capabilities, examine_cpio_content, export_index, extract_cpio_archive, extract_objects,
+    grep_cpio_content, install_interrupt_handlers, list_cpio_content, list_cpio_content_formatted,
+    received_signal, rename_entries, replace_entry, replace_entry_in_place,
+    set_decompress_memlimit, set_mtime, set_mtime_in_place, verify_cpio_content, DataAlign,
+    ExamineFormat, ExtractOptions, ExtractSummary, HardlinkPolicy, IdMap, ListFormat, ListOptions,
+    LogLevel, MatchMode, QuotingStyle, DEFAULT_IO_BUFFER_SIZE, LOG_LEVEL_DEBUG, LOG_LEVEL_INFO,
     LOG_LEVEL_WARNING,
 };
 
+use crate::config::Config;
+use crate::options::{find_option, write_completion, Opt, Shell};
+
+mod config;
+mod options;
+
 #[derive(Debug)]
 struct Args {
+    cat: bool,
+    check_boot: bool,
+    check_libraries: bool,
+    check_symlinks: bool,
+    count: bool,
+    data_align: Option<DataAlign>,
+    decompress_memlimit: Option<u64>,
+    delete: Vec<String>,
+    diff_manifest: Option<String>,
     directory: String,
     examine: bool,
+    examine_format: ExamineFormat,
     extract: bool,
     force: bool,
+    format: Option<ListFormat>,
+    grep: Option<String>,
+    hardlink_policy: HardlinkPolicy,
+    idmap: Option<IdMap>,
+    index: bool,
+    no_cache: bool,
+    no_setuid: bool,
+    io_buffer_size: usize,
+    jobs: usize,
     list: bool,
     log_level: u32,
+    match_mode: MatchMode,
+    metadata_only: bool,
     file: String,
+    names: Vec<String>,
+    null: bool,
+    objects: Option<String>,
+    output: Option<String>,
     preserve_permissions: bool,
+    quoting_style: QuotingStyle,
+    rename: Vec<(String, String)>,
+    replace: Option<(String, String)>,
+    sanitize_modes: bool,
+    segments: bool,
+    set_mtime: Option<u32>,
+    sorted: bool,
     subdir: Option<String>,
+    summary: bool,
+    to_stdout: bool,
+    touch: bool,
+    umask: Option<u32>,
+    verify: bool,
+    verify_on_extract: bool,
+    write_names_to: Option<i32>,
 }
 
 fn print_help() {
     let executable = std::env::args().next().unwrap();
     println!(
         "Usage:
-    {executable} {{-e|--examine}} FILE
-    {executable} {{-t|--list}} FILE
-    {executable} {{-x|--extract}} [-v|--debug] [-C DIR] [-p] [-s NAME] [--force] FILE
+    {executable} --cat [--data-align BYTES] [-o OUTPUT] FILE [FILE...]
+    {executable} --check-boot FILE
+    {executable} --check-libraries [-v] FILE
+    {executable} --check-symlinks [-v] FILE
+    {executable} --count [-v] [--segments] FILE
+    {executable} {{-e|--examine}} [-v] [--format FORMAT] FILE
+    {executable} {{-t|--list}} [-v] [-j N] [-0] [--quoting-style STYLE] [--match MODE] FILE [NAME...]
+    {executable} {{-t|--list}} --format FORMAT [--match MODE] FILE [NAME...]
+    {executable} {{-t|--list}} --offsets [--match MODE] FILE [NAME...]
+    {executable} --delete PATTERN [--delete PATTERN...] [--match MODE] [-v] [--data-align BYTES] [-o OUTPUT] FILE
+    {executable} --diff-manifest MANIFEST FILE
+    {executable} --grep PATTERN [--match MODE] FILE [NAME...]
+    {executable} --index [-o OUTPUT] FILE
+    {executable} --rename OLD=NEW [--rename OLD=NEW...] [-v] [--data-align BYTES] [-o OUTPUT] FILE
+    {executable} --replace NAME=FILE [--data-align BYTES] [-o OUTPUT] FILE
+    {executable} --set-mtime EPOCH [--match MODE] [--data-align BYTES] [-o OUTPUT] FILE [NAME...]
+    {executable} --verify [-v] FILE
+    {executable} {{-x|--extract}} [-v|--debug] [-C DIR] [-p|--no-preserve-permissions]
+            [-s NAME [-j N]] [--force] [--hardlink-policy POLICY] [--idmap MAP] [--metadata-only] [--no-cache]
+            [--no-setuid] [--sanitize-modes] [--sorted] [--summary] [--touch]
+            [--verify-on-extract] [--match MODE] FILE [NAME...]
+    {executable} {{-x|--extract}} --to-stdout [--match MODE] [--write-names-to FD] FILE [NAME...]
+    {executable} {{-x|--extract}} --objects INDEX [-v|--debug] [-C DIR] [--force] FILE
 
 Optional arguments:
+  --cat          Concatenate the given cpio files (keeping their compression)
+                 and pad each one with zeros to a multiple of four bytes.
+                 Fail if a file is not a well-formed sequence of cpio
+                 archives or if a file except the last one ends with a
+                 compressed cpio archive.
+  --check-boot   Check that /init is an executable file, /dev and /proc are
+                 directories, and /dev/console is a character device and
+                 print the result of each check.
+  --check-libraries
+                 Parse the ELF files and print the needed shared libraries
+                 (and dynamic loaders) that are missing in the cpio archives.
+                 With --verbose, also print the ELF files without missing
+                 libraries.
+  --check-symlinks
+                 Resolve the targets of all symlinks against the entries of
+                 the cpio archives and print the dangling ones and the ones
+                 pointing outside of the archive. With --verbose, also print
+                 the resolvable ones.
+  --count        Print the number of entries in the cpio archives. With
+                 --verbose, print the compression and number of entries of
+                 each cpio archive first.
+  --data-align=BYTES
+                 Align the file data of the regular files to a multiple of
+                 BYTES (a multiple of 4) by padding their names with NUL
+                 bytes when rewriting the cpio archives. For --cat, pad
+                 each file to a multiple of BYTES instead of four bytes to
+                 keep the alignment of the following files. Uncompressed
+                 cpio archives are always rewritten with this option.
+  --align-min-size=BYTES
+                 Only align the file data of files with at least BYTES bytes
+                 (for --data-align).
+  --align-pattern=PATTERN
+                 Only align the file data of files whose names match one of
+                 the glob patterns (can be specified multiple times, for
+                 --data-align).
+  --delete=PATTERN
+                 Rewrite the cpio archives without the entries whose names
+                 match the glob PATTERN (can be specified multiple times).
+                 Compressed cpio archives are compressed again. FILE is
+                 replaced unless --output is specified.
+  --diff-manifest=MANIFEST
+                 Compare the entries of the cpio archives against MANIFEST
+                 (in the format of the kernel's gen_init_cpio) and print the
+                 entries missing on either side and the mismatching type,
+                 mode, owner, symlink target, or device number.
   -e, --examine  List the offsets of the cpio archives and their compression.
-  -t, --list     List the contents of the cpio archives.
-  -x, --extract  Extract cpio archives.
+                 With --verbose, also print the number of entries, the
+                 overhead (bytes spent on headers, name and data padding,
+                 and alignment), and the bytes after the trailer of the
+                 last cpio archive.
+  --grep=PATTERN Search the content of the regular files for PATTERN and print
+                 the name and offset of each match. Only search files whose
+                 names match one of the given glob patterns (if any).
+  --index        Write a JSON catalog of all entries (with name, type, size,
+                 mode, owner, mtime, SHA-256 hash, cpio archive number, and
+                 offset).
+  -t, --list     List the contents of the cpio archives (only the entries
+                 whose names match one of the given glob patterns, if any).
+  --rename=OLD=NEW
+                 Rewrite the cpio archives with the entry OLD and all entries
+                 below it renamed to NEW (can be specified multiple times).
+                 Symlink targets are not changed.
+  --replace=NAME=FILE
+                 Replace the content of the entry NAME by the content of FILE
+                 (keeping its metadata). Uncompressed cpio archives are
+                 changed in place if the size does not change. Otherwise the
+                 cpio archives are rewritten like for --delete.
+  --set-mtime=EPOCH
+                 Set the modification time of all entries (or the entries
+                 whose names match one of the given glob patterns) to EPOCH
+                 (seconds since 1970-01-01). Uncompressed cpio archives are
+                 changed in place. Otherwise the cpio archives are rewritten
+                 like for --delete.
+  --verify       Verify the checksums of the regular files in cpio archives
+                 in the crc format (070702) and the SHA-256 hashes listed in
+                 a '.checksums' entry (in sha256sum format) and print the
+                 mismatching ones. With --verbose, also print the matching
+                 ones.
+  -x, --extract  Extract cpio archives (only the entries whose names match
+                 one of the given glob patterns, if any, creating their
+                 missing parent directories).
   -C, --directory=DIR  Change directory before performing any operation.
+  --format=FORMAT
+                 Examine the cpio archives in the given format: 'raw'
+                 (default, tab-separated), 'human' (table with sizes), or
+                 'json'.
+                 List the contents in the given format: 'cpio' (matches the
+                 output of GNU cpio -tv) or a template with one or more of
+                 the fields {{mode}}, {{nlink}}, {{user}}, {{group}}, {{uid}},
+                 {{gid}}, {{size}}, {{mtime}}, {{name}}, {{target}},
+                 {{segment}}, {{ino}}, and {{dev}} (e.g.
+                 \"{{mode}} {{size}} {{name}}\").
+  --hardlink-policy=POLICY
+                 How to handle hard-links whose number of entries in the
+                 archive does not match their link count: 'strict' fails,
+                 'lenient' warns (default: lenient).
+  --idmap=MAP    Translate the owners of the extracted entries (if permissions
+                 are preserved): 'auto' keeps the IDs that are mapped in the
+                 user namespace (see /proc/self/uid_map) and uses the own IDs
+                 for the others. A number is added as offset to all IDs.
+  --io-buffer-size=BYTES
+                 Size of the read buffer (default: {DEFAULT_IO_BUFFER_SIZE}).
+  -j, --jobs=N   Number of worker threads for listing the cpio archives in
+                 parallel or for extracting them in parallel into separate
+                 directories (with --subdir) (default: 1).
+  --match=MODE   How the name patterns of --align-pattern, --delete, --extract,
+                 --grep, --list, and --set-mtime select the entries: 'glob'
+                 matches glob patterns where * also matches /, 'globstar'
+                 matches glob patterns where only ** matches across
+                 directories, 'prefix' selects the given names and everything
+                 below them, and 'exact' selects only the given names
+                 (default: glob).
+  --objects=INDEX
+                 Extract the content of the regular files into a
+                 content-addressed object store (in the objects directory) and
+                 write the metadata of all entries to INDEX. Extracting multiple
+                 archives into the same directory stores identical files once.
+  --offsets      List the offsets of the header and of the file data in FILE,
+                 the size of the file data, and the name of each entry
+                 (tab-separated). Compressed cpio archives are skipped.
+  --segments     Count the cpio archives instead of the entries (for
+                 --count). The cpio archives are not decompressed.
+  -o, --output=OUTPUT
+                 Write the output to the file OUTPUT instead of stdout (or
+                 instead of replacing FILE for --delete, --rename,
+                 --replace, and --set-mtime).
+  -0, --null     Terminate the listed entries with a NUL byte instead of a
+                 newline (implies --quoting-style=literal).
   -p, --preserve-permissions
                  Set permissions of extracted files to those recorded in the
                  archive (default for superuser).
+  --no-preserve-permissions
+                 Do not set the owner of extracted files and apply the umask
+                 to the permissions recorded in the archive.
+  --quoting-style=STYLE
+                 Quote the listed names and symlink targets: 'literal'
+                 prints them as they are, 'escape' uses backslash sequences
+                 like ls -b, and 'c' encloses them in double quotes
+                 (default: escape if stdout is a terminal, literal
+                 otherwise).
+  --metadata-only
+                 Create the directories, symlinks, device nodes, FIFOs, and
+                 empty placeholders for the regular files (with the recorded
+                 names, permissions, owners, and modification times) without
+                 writing the file content. Useful for inspecting the tree
+                 layout quickly.
+  --no-cache     Write each extracted file to disk and drop it from the page
+                 cache. This reduces the memory pressure on small systems.
+  --no-setuid    Clear the setuid and setgid bits of extracted files.
+  --sanitize-modes
+                 Clear setuid, setgid, and sticky bits as well as write
+                 permission for others of extracted files and log each change.
+                 Recommended for extracting untrusted archives.
+  --sorted       Extract the entries of each cpio archive sorted by name
+                 (parents first) instead of in archive order. This results in
+                 identical trees regardless of the order in the archive, but
+                 buffers the headers of each cpio archive in memory and
+                 spools compressed cpio archives into a temporary file in the
+                 target directory.
   -s, --subdir   Extract the cpio archives into separate directories (using the
                  given name plus an incrementing number)
+  --summary      Print the number of extracted entries, written bytes, and
+                 warnings after extracting.
+  --to-stdout    Write the content of the regular files (whose names match
+                 one of the given glob patterns, if any) to stdout instead of
+                 extracting them.
+  --touch        Do not restore the modification times recorded in the
+                 archive. Extracted files get the extraction time.
+  --verify-on-extract
+                 Verify the checksums of the extracted files (for cpio
+                 archives in the crc format) and fail on a mismatch.
+  --write-names-to=FD
+                 Write the names of the files written by --to-stdout
+                 NUL-separated to the file descriptor FD (3 or higher).
+  --decompress-memlimit=BYTES
+                 Limit the memory usage of the xz, lzma, and zstd
+                 decompressors. Decompressing fails instead of exhausting
+                 the memory (e.g. for untrusted archives).
+  --log-level=LEVEL
+                 Set the verbosity of the messages on stderr: 'error' (only
+                 errors, no warnings), 'warning' (default), 'info' (same as
+                 --verbose), or 'debug' (same as --debug).
   -v, --verbose  Verbose output
   --debug        Debug output
+  --dump-completions=SHELL
+                 Print the completion script for the shell ('bash' or 'zsh')
+                 and exit.
   --force        Force overwriting existing files
   -h, --help     print help message
-  -V, --version  print version number and exit",
+  -V, --version  print version number and exit
+
+Default values for --hardlink-policy, --io-buffer-size, --jobs, --log-level,
+and --quoting-style can be set in /etc/3cpio.conf and ~/.config/3cpio.conf
+(one 'option = value' per line, '#' starts a comment). The environment variables THREECPIO_JOBS
+and THREECPIO_LOG_LEVEL ('error', 'warning', 'info', or 'debug') override
+these files, but not the command line.",
     );
 }
 
@@ -60,64 +315,421 @@ fn print_version() {
 }
 
 fn parse_args() -> Result<Args, lexopt::Error> {
+    let config = Config::load()?;
+    let mut cat = 0;
+    let mut check_boot = 0;
+    let mut check_libraries = 0;
+    let mut check_symlinks = 0;
+    let mut count = 0;
+    let mut align_min_size = None;
+    let mut align_patterns = Vec::new();
+    let mut data_align = None;
+    let mut decompress_memlimit = None;
+    let mut delete = Vec::new();
+    let mut diff_manifest = None;
     let mut examine = 0;
     let mut extract = 0;
     let mut force = false;
+    let mut format = None;
+    let mut grep = None;
+    let mut hardlink_policy = config.hardlink_policy.unwrap_or_default();
+    let mut idmap = None;
+    let mut index = 0;
+    let mut no_cache = false;
+    let mut no_setuid = false;
+    let mut objects = None;
+    let mut offsets = false;
+    let mut output = None;
+    let mut io_buffer_size = config.io_buffer_size.unwrap_or(DEFAULT_IO_BUFFER_SIZE);
+    let mut jobs = config.jobs.unwrap_or(1);
+    let mut jobs_option = false;
     let mut preserve_permissions = is_root();
     let mut list = 0;
-    let mut log_level = LOG_LEVEL_WARNING;
+    let mut log_level = config.log_level.map_or(LOG_LEVEL_WARNING, u32::from);
+    let mut match_mode = None;
+    let mut metadata_only = false;
     let mut directory = ".".into();
     let mut file = None;
+    let mut names = Vec::new();
+    let mut null = false;
+    let mut quoting_style = config.quoting_style;
+    let mut rename = Vec::new();
+    let mut replace = None;
+    let mut sanitize_modes = false;
+    let mut set_mtime = None;
+    let mut segments = false;
+    let mut sorted = false;
     let mut subdir: Option<String> = None;
+    let mut summary = false;
+    let mut to_stdout = false;
+    let mut touch = false;
+    let mut umask = None;
+    let mut verify = 0;
+    let mut verify_on_extract = false;
+    let mut write_names_to = None;
     let mut parser = lexopt::Parser::from_env();
     while let Some(arg) = parser.next()? {
-        match arg {
-            Short('C') | Long("directory") => {
+        let option = match arg {
+            Value(val) if file.is_none() => {
+                file = Some(val.string()?);
+                continue;
+            }
+            Value(val) => {
+                names.push(val.string()?);
+                continue;
+            }
+            _ => match find_option(&arg) {
+                Some(option) => option,
+                None => return Err(arg.unexpected()),
+            },
+        };
+        match option.opt {
+            Opt::AlignMinSize => {
+                align_min_size = Some(parser.value()?.parse()?);
+            }
+            Opt::AlignPattern => {
+                align_patterns.push(parser.value()?.string()?);
+            }
+            Opt::Cat => {
+                cat = 1;
+            }
+            Opt::CheckBoot => {
+                check_boot = 1;
+            }
+            Opt::CheckLibraries => {
+                check_libraries = 1;
+            }
+            Opt::CheckSymlinks => {
+                check_symlinks = 1;
+            }
+            Opt::Count => {
+                count = 1;
+            }
+            Opt::DataAlign => {
+                data_align = Some(parser.value()?.parse()?);
+            }
+            Opt::DecompressMemlimit => {
+                let limit = parser.value()?.parse()?;
+                if limit == 0 {
+                    return Err("The decompressor memory limit must be at least 1 byte!".into());
+                }
+                decompress_memlimit = Some(limit);
+            }
+            Opt::Delete => {
+                delete.push(parser.value()?.string()?);
+            }
+            Opt::DiffManifest => {
+                diff_manifest = Some(parser.value()?.string()?);
+            }
+            Opt::Directory => {
                 directory = parser.value()?.string()?;
             }
-            Long("debug") => {
+            Opt::Debug => {
                 log_level = LOG_LEVEL_DEBUG;
             }
-            Short('e') | Long("examine") => {
+            Opt::DumpCompletions => {
+                let shell: Shell = parser.value()?.parse()?;
+                let executable = std::option_env!("CARGO_BIN_NAME").unwrap();
+                write_completion(&mut std::io::stdout(), shell, executable)
+                    .map_err(|e| e.to_string())?;
+                std::process::exit(0);
+            }
+            Opt::Examine => {
                 examine = 1;
             }
-            Long("force") => {
+            Opt::Force => {
                 force = true;
             }
-            Short('h') | Long("help") => {
+            Opt::Format => {
+                format = Some(parser.value()?.string()?);
+            }
+            Opt::Grep => {
+                grep = Some(parser.value()?.string()?);
+            }
+            Opt::Help => {
                 print_help();
                 std::process::exit(0);
             }
-            Short('p') | Long("preserve-permissions") => {
+            Opt::HardlinkPolicy => {
+                hardlink_policy = parser.value()?.parse()?;
+            }
+            Opt::Idmap => {
+                idmap = Some(parser.value()?.parse()?);
+            }
+            Opt::Index => {
+                index = 1;
+            }
+            Opt::IoBufferSize => {
+                io_buffer_size = parser.value()?.parse()?;
+                if io_buffer_size == 0 {
+                    return Err("The I/O buffer size must be at least 1 byte!".into());
+                }
+            }
+            Opt::Jobs => {
+                jobs = parser.value()?.parse()?;
+                if jobs == 0 {
+                    return Err("The number of jobs must be at least 1!".into());
+                }
+                jobs_option = true;
+            }
+            Opt::NoPreservePermissions => {
+                preserve_permissions = false;
+                umask = Some(get_umask());
+            }
+            Opt::NoCache => {
+                no_cache = true;
+            }
+            Opt::NoSetuid => {
+                no_setuid = true;
+            }
+            Opt::Objects => {
+                objects = Some(parser.value()?.string()?);
+            }
+            Opt::Null => {
+                null = true;
+            }
+            Opt::Offsets => {
+                offsets = true;
+            }
+            Opt::Output => {
+                output = Some(parser.value()?.string()?);
+            }
+            Opt::PreservePermissions => {
                 preserve_permissions = true;
+                umask = None;
             }
-            Short('s') | Long("subdir") => {
+            Opt::QuotingStyle => {
+                quoting_style = Some(parser.value()?.parse()?);
+            }
+            Opt::Rename => {
+                let value = parser.value()?.string()?;
+                let Some((old, new)) = value.split_once('=') else {
+                    return Err(format!("Expected OLD=NEW for --rename: {}", value).into());
+                };
+                rename.push((old.to_string(), new.to_string()));
+            }
+            Opt::Replace => {
+                let value = parser.value()?.string()?;
+                let Some((name, path)) = value.split_once('=') else {
+                    return Err(format!("Expected NAME=FILE for --replace: {}", value).into());
+                };
+                replace = Some((name.to_string(), path.to_string()));
+            }
+            Opt::SanitizeModes => {
+                sanitize_modes = true;
+            }
+            Opt::SetMtime => {
+                set_mtime = Some(parser.value()?.parse()?);
+            }
+            Opt::Segments => {
+                segments = true;
+            }
+            Opt::Sorted => {
+                sorted = true;
+            }
+            Opt::Subdir => {
                 subdir = Some(parser.value()?.string()?);
             }
-            Short('t') | Long("list") => {
+            Opt::Summary => {
+                summary = true;
+            }
+            Opt::List => {
                 list = 1;
             }
-            Short('v') | Long("verbose") => {
+            Opt::LogLevel => {
+                log_level = parser.value()?.parse::<LogLevel>()?.into();
+            }
+            Opt::Match => {
+                match_mode = Some(parser.value()?.parse()?);
+            }
+            Opt::MetadataOnly => {
+                metadata_only = true;
+            }
+            Opt::Verbose => {
                 if log_level <= LOG_LEVEL_INFO {
                     log_level = LOG_LEVEL_INFO;
                 }
             }
-            Short('V') | Long("version") => {
+            Opt::ToStdout => {
+                to_stdout = true;
+            }
+            Opt::Touch => {
+                touch = true;
+            }
+            Opt::Verify => {
+                verify = 1;
+            }
+            Opt::VerifyOnExtract => {
+                verify_on_extract = true;
+            }
+            Opt::Version => {
                 print_version();
                 std::process::exit(0);
             }
-            Short('x') | Long("extract") => {
-                extract = 1;
+            Opt::WriteNamesTo => {
+                write_names_to = Some(parser.value()?.parse()?);
             }
-            Value(val) if file.is_none() => {
-                file = Some(val.string()?);
+            Opt::Extract => {
+                extract = 1;
             }
-            _ => return Err(arg.unexpected()),
         }
     }
 
-    if examine + extract + list != 1 {
-        return Err("Either --examine, --extract or --list must be specified!".into());
+    if log_level >= LOG_LEVEL_WARNING {
+        for warning in &config.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+
+    if cat
+        + check_boot
+        + check_libraries
+        + check_symlinks
+        + count
+        + i32::from(!delete.is_empty())
+        + i32::from(diff_manifest.is_some())
+        + examine
+        + extract
+        + i32::from(grep.is_some())
+        + index
+        + list
+        + i32::from(set_mtime.is_some())
+        + i32::from(!rename.is_empty())
+        + i32::from(replace.is_some())
+        + verify
+        != 1
+    {
+        return Err(
+            "Either --cat, --check-boot, --check-libraries, --check-symlinks, --count, --delete, --diff-manifest, --examine, --extract, --grep, --index, --list, --rename, --replace, --set-mtime or --verify must be specified!"
+                .into(),
+        );
+    }
+
+    // --format is interpreted depending on the operation.
+    let mut examine_format = ExamineFormat::default();
+    let mut format: Option<ListFormat> = match format {
+        Some(format) if examine == 1 => {
+            examine_format = format.parse()?;
+            None
+        }
+        Some(format) => Some(format.parse()?),
+        None => None,
+    };
+    if offsets {
+        if list == 0 || format.is_some() {
+            return Err("--offsets requires --list and cannot be combined with --format!".into());
+        }
+        format = Some(ListFormat::Offsets);
+    }
+
+    if !names.is_empty()
+        && cat == 0
+        && extract == 0
+        && grep.is_none()
+        && list == 0
+        && set_mtime.is_none()
+    {
+        return Err(format!(
+            "Name patterns are only supported by --extract, --grep, --list, and --set-mtime: {}",
+            names.join(" ")
+        )
+        .into());
+    }
+    if !names.is_empty() && objects.is_some() {
+        return Err("--objects cannot be combined with name patterns!".into());
+    }
+
+    if match_mode.is_some()
+        && align_patterns.is_empty()
+        && delete.is_empty()
+        && extract == 0
+        && grep.is_none()
+        && list == 0
+        && set_mtime.is_none()
+    {
+        return Err(
+            "--match requires --align-pattern, --delete, --extract, --grep, --list, or --set-mtime!"
+                .into(),
+        );
+    }
+    let match_mode = match_mode.unwrap_or_default();
+
+    if data_align.is_some()
+        && cat == 0
+        && delete.is_empty()
+        && rename.is_empty()
+        && replace.is_none()
+        && set_mtime.is_none()
+    {
+        return Err(
+            "--data-align requires --cat, --delete, --rename, --replace, or --set-mtime!".into(),
+        );
+    }
+
+    if (align_min_size.is_some() || !align_patterns.is_empty())
+        && (data_align.is_none() || cat == 1)
+    {
+        return Err(
+            "--align-min-size and --align-pattern require --data-align and cannot be combined with --cat!"
+                .into(),
+        );
+    }
+    let data_align = data_align
+        .map(|bytes| {
+            DataAlign::new(
+                bytes,
+                align_min_size.unwrap_or(0),
+                &align_patterns,
+                match_mode,
+            )
+        })
+        .transpose()
+        .map_err(|e| e.to_string())?;
+
+    if write_names_to.is_some() && !to_stdout {
+        return Err("--write-names-to requires --to-stdout!".into());
+    }
+    if let Some(fd) = write_names_to {
+        if fd < 3 {
+            return Err(format!(
+                "--write-names-to requires a file descriptor above 2 (not {})!",
+                fd
+            )
+            .into());
+        }
+    }
+
+    if segments && count == 0 {
+        return Err("--segments requires --count!".into());
+    }
+
+    for (enabled, option) in [(metadata_only, "--metadata-only"), (sorted, "--sorted")] {
+        if enabled && (to_stdout || objects.is_some()) {
+            return Err(format!(
+                "{} cannot be combined with --to-stdout or --objects!",
+                option
+            )
+            .into());
+        }
+    }
+
+    // Escape the names on terminals to not let them mess with the terminal.
+    let quoting_style = quoting_style.unwrap_or_else(|| {
+        if !null && is_terminal(1) {
+            QuotingStyle::Escape
+        } else {
+            QuotingStyle::Literal
+        }
+    });
+
+    // Only separate directories keep the cpio archives independent of each
+    // other. Otherwise later cpio archives overwrite entries of earlier ones.
+    if jobs_option
+        && jobs > 1
+        && extract == 1
+        && (subdir.is_none() || to_stdout || objects.is_some())
+    {
+        return Err("--jobs requires --subdir for --extract!".into());
     }
 
     if let Some(ref s) = subdir {
@@ -127,15 +739,54 @@ fn parse_args() -> Result<Args, lexopt::Error> {
     }
 
     Ok(Args {
+        cat: cat == 1,
+        check_boot: check_boot == 1,
+        check_libraries: check_libraries == 1,
+        check_symlinks: check_symlinks == 1,
+        count: count == 1,
+        data_align,
+        decompress_memlimit,
+        delete,
+        diff_manifest,
         directory,
         examine: examine == 1,
+        examine_format,
         extract: extract == 1,
         force,
+        format,
+        grep,
+        hardlink_policy,
+        idmap,
+        index: index == 1,
+        no_cache,
+        no_setuid,
+        io_buffer_size,
+        jobs,
         list: list == 1,
         log_level,
+        match_mode,
+        metadata_only,
         file: file.ok_or("missing argument FILE")?,
+        names,
+        null,
+        objects,
+        output,
         preserve_permissions,
+        quoting_style,
+        rename,
+        replace,
+        sanitize_modes,
+        set_mtime,
+        segments,
+        sorted,
         subdir,
+        summary,
+        to_stdout,
+        touch,
+        umask,
+        verify: verify == 1,
+        verify_on_extract,
+        write_names_to,
     })
 }
 
@@ -143,25 +794,52 @@ fn is_empty_directory<P: AsRef<Path>>(path: P) -> std::io::Result<bool> {
     Ok(read_dir(path)?.next().is_none())
 }
 
+/// Return the file mode creation mask of the process.
+///
+/// There is no function to just read the umask. So set it to zero and
+/// restore the previous value.
+fn get_umask() -> u32 {
+    let umask = unsafe { libc::umask(0) };
+    unsafe { libc::umask(umask) };
+    // mode_t is u16 on macOS
+    umask as u32
+}
+
+fn is_terminal(fd: i32) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+/// Duplicate the file descriptor `fd` given by the caller.
+///
+/// The duplicate is owned by the returned file. So an invalid file
+/// descriptor is reported as error and the original file descriptor
+/// is never closed.
+fn duplicate_fd(fd: i32) -> std::io::Result<File> {
+    let duplicate = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 3) };
+    if duplicate < 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(std::io::Error::new(
+            e.kind(),
+            format!("Failed to open file descriptor {}: {}", fd, e),
+        ));
+    }
+    // Safety: The file descriptor was just created and is not used elsewhere.
+    Ok(unsafe { File::from_raw_fd(duplicate) })
+}
+
 fn is_root() -> bool {
     let uid = unsafe { libc::getuid() };
     uid == 0
 }
 
-fn create_and_set_current_dir(path: &str, force: bool) -> Result<(), String> {
-    if let Err(e) = set_current_dir(path) {
-        if e.kind() != ErrorKind::NotFound {
-            return Err(format!("Failed to change directory to '{}': {}", path, e));
-        }
-        if let Err(e) = create_dir(path) {
+fn create_target_dir(path: &str, force: bool) -> Result<(), String> {
+    if let Err(e) = create_dir(path) {
+        if e.kind() != ErrorKind::AlreadyExists {
             return Err(format!("Failed to create directory '{}': {}", path, e));
         }
-        if let Err(e) = set_current_dir(path) {
-            return Err(format!("Failed to change directory to '{}': {}", path, e));
-        }
     }
     if !force {
-        match is_empty_directory(".") {
+        match is_empty_directory(path) {
             Err(e) => {
                 return Err(format!(
                     "Failed to check content of directory '{}': {}",
@@ -180,6 +858,232 @@ fn create_and_set_current_dir(path: &str, force: bool) -> Result<(), String> {
     Ok(())
 }
 
+/// Writer that creates the file on the first write (or flush)
+///
+/// An operation that fails before writing anything leaves an existing
+/// file untouched.
+struct LazyFile<'a> {
+    path: &'a str,
+    file: Option<BufWriter<File>>,
+}
+
+impl LazyFile<'_> {
+    fn file(&mut self) -> std::io::Result<&mut BufWriter<File>> {
+        if self.file.is_none() {
+            self.file = Some(BufWriter::new(File::create(self.path)?));
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+}
+
+impl Write for LazyFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file()?.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file()?.flush()
+    }
+}
+
+/// Let `write` write to OUTPUT (if specified) or stdout.
+///
+/// OUTPUT is only created once `write` writes to it (or succeeds).
+fn write_to_output<F: FnOnce(&mut dyn Write) -> std::io::Result<()>>(
+    output: &Option<String>,
+    write: F,
+) -> std::io::Result<()> {
+    match output {
+        Some(path) => {
+            let mut out = LazyFile { path, file: None };
+            write(&mut out)?;
+            out.flush()
+        }
+        None => write(&mut std::io::stdout()),
+    }
+}
+
+/// Concatenate FILE and the given NAMEs to OUTPUT (if specified) or stdout.
+fn concatenate_files(args: &Args) -> std::io::Result<()> {
+    let mut paths = vec![&args.file];
+    paths.extend(&args.names);
+    if let Some(ref output) = args.output {
+        if let Some(path) = paths.iter().find(|path| is_same_file(output, path)) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The output {} is the same file as the input {}.",
+                    output, path
+                ),
+            ));
+        }
+    }
+    write_to_output(&args.output, |mut out| {
+        concatenate_cpio_files(
+            &paths,
+            &mut out,
+            args.data_align.as_ref().map(DataAlign::bytes),
+            args.io_buffer_size,
+        )
+    })
+}
+
+/// Check if both paths refer to the same (existing) file.
+fn is_same_file<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> bool {
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Create a new temporary file next to `path` and return it with its name.
+fn create_temp_file(path: &str) -> std::io::Result<(File, String)> {
+    let mut count = 0;
+    loop {
+        let temp_path = format!("{}.3cpio-tmp.{}.{}", path, std::process::id(), count);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(file) => return Ok((file, temp_path)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists && count < 100 => count += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Let `rewrite` write the new archive to OUTPUT (if specified) or replace FILE.
+///
+/// FILE is replaced atomically by writing to a temporary file next to it
+/// and renaming it over FILE (keeping the permissions of FILE). This is
+/// also done if OUTPUT is the same file as FILE.
+fn rewrite_archive<F: FnOnce(File) -> std::io::Result<()>>(
+    path: &str,
+    output: &Option<String>,
+    rewrite: F,
+) -> std::io::Result<()> {
+    if let Some(output) = output.as_ref().filter(|output| !is_same_file(output, path)) {
+        return rewrite(File::create(output)?);
+    }
+    let (temp, temp_path) = create_temp_file(path)?;
+    let result = std::fs::metadata(path)
+        .and_then(|metadata| temp.set_permissions(metadata.permissions()))
+        .and_then(|()| rewrite(temp))
+        .and_then(|()| std::fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn replace_entry_in_archive(
+    file: File,
+    args: &Args,
+    name: &str,
+    path: &Path,
+) -> std::io::Result<()> {
+    let content = std::fs::read(path).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("Failed to read '{}': {}", path.display(), e),
+        )
+    })?;
+    if args.output.is_none() && args.data_align.is_none() {
+        let archive = OpenOptions::new().read(true).write(true).open(&args.file)?;
+        if replace_entry_in_place(&archive, name, &content, args.io_buffer_size)? {
+            return Ok(());
+        }
+    }
+    rewrite_archive(&args.file, &args.output, |output| {
+        replace_entry(
+            file,
+            output,
+            name,
+            &content,
+            args.data_align.as_ref(),
+            args.log_level,
+            args.io_buffer_size,
+        )
+    })
+}
+
+fn delete_entries_in_archive(mut file: File, args: &Args) -> std::io::Result<()> {
+    let matches = count_matching_entries(
+        &file,
+        &args.delete,
+        args.match_mode,
+        args.log_level,
+        args.io_buffer_size,
+    )?;
+    file.rewind()?;
+    if matches == 0 && args.data_align.is_none() {
+        if args.log_level >= LOG_LEVEL_WARNING {
+            eprintln!("Warning: No entry matches the patterns. Leaving the archive unchanged.");
+        }
+        // Still write OUTPUT (as an unchanged copy) if it is a different file.
+        if let Some(output) = args.output.as_ref() {
+            if !is_same_file(output, &args.file) {
+                std::io::copy(&mut file, &mut File::create(output)?)?;
+            }
+        }
+        return Ok(());
+    }
+    rewrite_archive(&args.file, &args.output, |output| {
+        delete_entries(
+            file,
+            output,
+            &args.delete,
+            args.match_mode,
+            args.data_align.as_ref(),
+            args.log_level,
+            args.io_buffer_size,
+        )
+    })
+}
+
+fn set_mtime_in_archive(file: File, args: &Args, mtime: u32) -> std::io::Result<()> {
+    if args.output.is_none() && args.data_align.is_none() {
+        let archive = OpenOptions::new().read(true).write(true).open(&args.file)?;
+        if set_mtime_in_place(
+            &archive,
+            mtime,
+            &args.names,
+            args.match_mode,
+            args.io_buffer_size,
+        )? {
+            return Ok(());
+        }
+    }
+    rewrite_archive(&args.file, &args.output, |output| {
+        set_mtime(
+            file,
+            output,
+            mtime,
+            &args.names,
+            args.match_mode,
+            args.data_align.as_ref(),
+            args.log_level,
+            args.io_buffer_size,
+        )
+    })
+}
+
+fn print_extract_summary(summary: &ExtractSummary) -> std::io::Result<()> {
+    let mut stderr = std::io::stderr();
+    writeln!(
+        stderr,
+        "{} directories, {} files, {} hard-links, {} symlinks, {} special files",
+        summary.directories,
+        summary.files,
+        summary.hard_links,
+        summary.symlinks,
+        summary.special_files
+    )?;
+    writeln!(stderr, "{} bytes written", summary.bytes_written)?;
+    writeln!(stderr, "{} warnings", summary.warnings)
+}
+
 fn main() -> ExitCode {
     let executable = std::env::args().next().unwrap();
     let args = match parse_args() {
@@ -201,23 +1105,226 @@ fn main() -> ExitCode {
         }
     };
 
-    if args.extract {
-        if let Err(e) = create_and_set_current_dir(&args.directory, args.force) {
+    if args.extract && !args.to_stdout {
+        // Only changing the owner and creating device nodes need root.
+        if args.preserve_permissions && is_root() {
+            if let Err(e) = drop_capabilities() {
+                eprintln!("{}: Error: Failed to drop capabilities: {}", executable, e);
+                return ExitCode::FAILURE;
+            }
+        }
+        if let Err(e) = create_target_dir(&args.directory, args.force) {
             eprintln!("{}: Error: {}", executable, e);
             return ExitCode::FAILURE;
         }
+        if args.objects.is_none() {
+            if let Err(e) = install_interrupt_handlers() {
+                eprintln!(
+                    "{}: Error: Failed to install signal handlers: {}",
+                    executable, e
+                );
+                return ExitCode::FAILURE;
+            }
+        }
     }
 
+    set_decompress_memlimit(args.decompress_memlimit);
     let mut stdout = std::io::stdout();
-    let (operation, result) = if args.examine {
-        ("examine", examine_cpio_content(file, &mut stdout))
+    let (operation, result) = if args.cat {
+        ("concatenate", concatenate_files(&args))
+    } else if args.check_boot {
+        (
+            "check",
+            check_boot(file, &mut stdout, args.log_level, args.io_buffer_size),
+        )
+    } else if args.check_libraries {
+        (
+            "check",
+            check_libraries(file, &mut stdout, args.log_level, args.io_buffer_size),
+        )
+    } else if args.check_symlinks {
+        (
+            "check",
+            check_symlinks(file, &mut stdout, args.log_level, args.io_buffer_size),
+        )
+    } else if args.count && args.segments {
+        (
+            "count",
+            count_cpio_archives(file, &mut stdout, args.log_level, args.io_buffer_size),
+        )
+    } else if args.count {
+        (
+            "count",
+            count_cpio_content(file, &mut stdout, args.log_level, args.io_buffer_size),
+        )
+    } else if args.examine {
+        (
+            "examine",
+            examine_cpio_content(
+                file,
+                &mut stdout,
+                args.examine_format,
+                args.log_level,
+                args.io_buffer_size,
+            ),
+        )
+    } else if !args.delete.is_empty() {
+        ("rewrite", delete_entries_in_archive(file, &args))
+    } else if let Some(mtime) = args.set_mtime {
+        ("rewrite", set_mtime_in_archive(file, &args, mtime))
+    } else if !args.rename.is_empty() {
+        (
+            "rewrite",
+            rewrite_archive(&args.file, &args.output, |output| {
+                rename_entries(
+                    file,
+                    output,
+                    &args.rename,
+                    args.data_align.as_ref(),
+                    args.log_level,
+                    args.io_buffer_size,
+                )
+            }),
+        )
+    } else if let Some((ref name, ref path)) = args.replace {
+        (
+            "rewrite",
+            replace_entry_in_archive(file, &args, name, Path::new(path)),
+        )
+    } else if let Some(ref manifest) = args.diff_manifest {
+        (
+            "diff",
+            diff_manifest(
+                file,
+                Path::new(manifest),
+                &mut stdout,
+                args.log_level,
+                args.io_buffer_size,
+            ),
+        )
+    } else if let Some(ref pattern) = args.grep {
+        (
+            "grep",
+            grep_cpio_content(
+                file,
+                pattern.as_bytes(),
+                &args.names,
+                args.match_mode,
+                &mut stdout,
+                args.io_buffer_size,
+            ),
+        )
+    } else if args.index {
+        (
+            "index",
+            write_to_output(&args.output, |mut out| {
+                export_index(file, &mut out, args.io_buffer_size)
+            }),
+        )
+    } else if args.extract && args.to_stdout {
+        (
+            "extract",
+            args.write_names_to
+                .map(duplicate_fd)
+                .transpose()
+                .and_then(|names_out| {
+                    let mut names_out = names_out.map(BufWriter::new);
+                    cat_cpio_content(
+                        file,
+                        &args.names,
+                        args.match_mode,
+                        &mut stdout,
+                        names_out.as_mut().map(|out| out as &mut dyn Write),
+                        args.io_buffer_size,
+                    )
+                }),
+        )
+    } else if let (true, Some(index)) = (args.extract, &args.objects) {
+        (
+            "extract",
+            extract_objects(
+                file,
+                Path::new(&args.directory),
+                &Path::new(&args.directory).join(index),
+                args.log_level,
+                args.io_buffer_size,
+            ),
+        )
     } else if args.extract {
         (
             "extract",
-            extract_cpio_archive(file, args.preserve_permissions, args.subdir, args.log_level),
+            extract_cpio_archive(
+                file,
+                &ExtractOptions {
+                    // Keep the entry names in the messages unchanged when
+                    // extracting into the working directory.
+                    directory: (args.directory != ".").then_some(args.directory),
+                    preserve_permissions: args.preserve_permissions,
+                    subdir: args.subdir,
+                    jobs: args.jobs,
+                    touch: args.touch,
+                    umask: args.umask,
+                    no_setuid: args.no_setuid,
+                    sanitize_modes: args.sanitize_modes,
+                    hardlink_policy: args.hardlink_policy,
+                    verify_checksums: args.verify_on_extract,
+                    no_cache: args.no_cache,
+                    metadata_only: args.metadata_only,
+                    sorted: args.sorted,
+                    idmap: args.idmap,
+                    names: args.names,
+                    match_mode: args.match_mode,
+                },
+                args.log_level,
+                args.io_buffer_size,
+            )
+            .and_then(|summary| {
+                if args.summary {
+                    print_extract_summary(&summary)?;
+                }
+                Ok(())
+            }),
+        )
+    } else if let (true, Some(format)) = (args.list, &args.format) {
+        (
+            "list",
+            list_cpio_content_formatted(
+                file,
+                &mut stdout,
+                format,
+                &ListOptions {
+                    jobs: args.jobs,
+                    quoting_style: args.quoting_style,
+                    null_terminated: args.null,
+                    names: args.names,
+                    match_mode: args.match_mode,
+                },
+                args.log_level,
+                args.io_buffer_size,
+            ),
+        )
+    } else if args.verify {
+        (
+            "verify",
+            verify_cpio_content(file, &mut stdout, args.log_level, args.io_buffer_size),
         )
     } else if args.list {
-        ("list", list_cpio_content(file, &mut stdout, args.log_level))
+        (
+            "list",
+            list_cpio_content(
+                file,
+                &mut stdout,
+                &ListOptions {
+                    jobs: args.jobs,
+                    quoting_style: args.quoting_style,
+                    null_terminated: args.null,
+                    names: args.names,
+                    match_mode: args.match_mode,
+                },
+                args.log_level,
+                args.io_buffer_size,
+            ),
+        )
     } else {
         unreachable!("no operation specified");
     };
@@ -225,6 +1332,11 @@ fn main() -> ExitCode {
     if let Err(e) = result {
         match e.kind() {
             ErrorKind::BrokenPipe => {}
+            ErrorKind::Interrupted => {
+                eprintln!("{}: {}", executable, e);
+                let signal = received_signal().unwrap_or(libc::SIGINT);
+                return ExitCode::from(128 + signal as u8);
+            }
             _ => {
                 eprintln!(
                     "{}: Error: Failed to {} content of '{}': {}",
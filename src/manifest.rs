@@ -0,0 +1,345 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Compare the entries of the cpio archives against a manifest.
+//!
+//! The manifest uses the format of the Linux kernel's gen_init_cpio (see
+//! usr/gen_init_cpio.c), which initramfs generators can produce as spec:
+//!
+//! ```text
+//! file <name> <location> <mode> <uid> <gid> [<hard links>...]
+//! dir <name> <mode> <uid> <gid>
+//! nod <name> <mode> <uid> <gid> <dev_type> <maj> <min>
+//! slink <name> <target> <mode> <uid> <gid>
+//! pipe <name> <mode> <uid> <gid>
+//! sock <name> <mode> <uid> <gid>
+//! ```
+//!
+//! The file content (at `<location>`) is not compared.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+
+use crate::header::*;
+use crate::index::file_type;
+use crate::metadata::FileType;
+use crate::seek_forward::SeekForward;
+use crate::{
+    check_decompressor_available, decompress_buffered, read_magic_header, strip_entry_name,
+};
+
+/// Metadata of an entry that is compared
+#[derive(Debug, PartialEq)]
+struct Entry {
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    /// Symlink target
+    target: Option<String>,
+    /// Major and minor number of character and block devices
+    rdev: Option<(u32, u32)>,
+}
+
+/// Entries by name (without leading `./` or `/`)
+type Entries = BTreeMap<String, Entry>;
+
+fn entry_name(name: &str) -> Option<String> {
+    let name = strip_entry_name(name).trim_end_matches('/');
+    if name.is_empty() || name == "." {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+fn parse_number(field: &str, radix: u32) -> std::result::Result<u32, String> {
+    u32::from_str_radix(field, radix).map_err(|_| format!("Invalid number '{}'.", field))
+}
+
+/// Parse a manifest line (split into fields) and add its entries.
+fn parse_line(fields: &[&str], entries: &mut Entries) -> std::result::Result<(), String> {
+    let (filetype, names, rest) = match fields {
+        ["file", name, _location, rest @ ..] if rest.len() >= 3 => {
+            let mut names = vec![*name];
+            names.extend(&rest[3..]);
+            (FileType::RegularFile, names, &rest[..3])
+        }
+        ["dir", name, rest @ ..] if rest.len() == 3 => (FileType::Directory, vec![*name], rest),
+        ["nod", name, rest @ ..] if rest.len() == 6 => {
+            let filetype = match rest[3] {
+                "c" => FileType::CharacterDevice,
+                "b" => FileType::BlockDevice,
+                dev_type => {
+                    return Err(format!(
+                        "Unknown device type '{}'. Expected 'b' or 'c'.",
+                        dev_type
+                    ))
+                }
+            };
+            (filetype, vec![*name], rest)
+        }
+        ["slink", name, _target, rest @ ..] if rest.len() == 3 => {
+            (FileType::Symlink, vec![*name], rest)
+        }
+        ["pipe", name, rest @ ..] if rest.len() == 3 => (FileType::Fifo, vec![*name], rest),
+        ["sock", name, rest @ ..] if rest.len() == 3 => (FileType::Socket, vec![*name], rest),
+        [kind, ..] => {
+            return Err(format!(
+                "Unknown or incomplete entry '{}'. Expected file, dir, nod, slink, pipe, or sock.",
+                kind
+            ))
+        }
+        [] => return Ok(()),
+    };
+    let rdev = if fields[0] == "nod" {
+        Some((parse_number(rest[4], 10)?, parse_number(rest[5], 10)?))
+    } else {
+        None
+    };
+    let target = (fields[0] == "slink").then(|| fields[2].to_string());
+    let mode = filetype.to_mode() | parse_number(rest[0], 8)?;
+    let uid = parse_number(rest[1], 10)?;
+    let gid = parse_number(rest[2], 10)?;
+    for name in names {
+        if let Some(name) = entry_name(name) {
+            let entry = Entry {
+                mode,
+                uid,
+                gid,
+                target: target.clone(),
+                rdev,
+            };
+            entries.insert(name, entry);
+        }
+    }
+    Ok(())
+}
+
+fn parse_manifest(content: &str) -> std::result::Result<Entries, String> {
+    let mut entries = Entries::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        parse_line(&fields, &mut entries).map_err(|e| format!("line {}: {}", number + 1, e))?;
+    }
+    Ok(entries)
+}
+
+fn read_cpio_entries<R: Read + SeekForward>(file: &mut R, entries: &mut Entries) -> Result<()> {
+    loop {
+        let header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            break;
+        }
+        let file_type = header.file_type();
+        let target = if file_type == Some(FileType::Symlink) {
+            Some(header.read_symlink_target(file)?)
+        } else {
+            header.skip_file_content(file)?;
+            None
+        };
+        let rdev = matches!(
+            file_type,
+            Some(FileType::CharacterDevice | FileType::BlockDevice)
+        )
+        .then_some((header.rmajor, header.rminor));
+        if let Some(name) = entry_name(&header.filename) {
+            // Later entries overwrite earlier ones (like the kernel does).
+            let entry = Entry {
+                mode: header.mode,
+                uid: header.uid,
+                gid: header.gid,
+                target,
+                rdev,
+            };
+            entries.insert(name, entry);
+        }
+    }
+    Ok(())
+}
+
+/// Return the differences between the entry in the archive and in the manifest.
+fn differences(archive: &Entry, manifest: &Entry) -> Vec<String> {
+    let mut differences = Vec::new();
+    let mut compare = |field: &str, archive: String, manifest: String| {
+        if archive != manifest {
+            differences.push(format!(
+                "{} {} in the archive, {} in the manifest",
+                field, archive, manifest
+            ));
+        }
+    };
+    compare(
+        "type",
+        file_type(archive.mode).into(),
+        file_type(manifest.mode).into(),
+    );
+    if FileType::from_mode(archive.mode) != FileType::from_mode(manifest.mode) {
+        return differences;
+    }
+    compare(
+        "mode",
+        format!("{:04o}", archive.mode & !MODE_FILETYPE_MASK),
+        format!("{:04o}", manifest.mode & !MODE_FILETYPE_MASK),
+    );
+    compare("uid", archive.uid.to_string(), manifest.uid.to_string());
+    compare("gid", archive.gid.to_string(), manifest.gid.to_string());
+    if let (Some(archive), Some(manifest)) = (&archive.target, &manifest.target) {
+        compare("target", archive.clone(), manifest.clone());
+    }
+    if let (Some(archive), Some(manifest)) = (archive.rdev, manifest.rdev) {
+        compare(
+            "device",
+            format!("{}:{}", archive.0, archive.1),
+            format!("{}:{}", manifest.0, manifest.1),
+        );
+    }
+    differences
+}
+
+/// Compare the entries of the cpio archives against the manifest.
+///
+/// Print the entries that are only in the archive or only in the manifest
+/// and the metadata mismatches (type, mode, owner, symlink target, and device
+/// number) to `out`. Fail if at least one difference is found.
+pub fn diff_manifest<W: Write>(
+    archive: File,
+    manifest: &Path,
+    out: &mut W,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let content = std::fs::read_to_string(manifest).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("Failed to read manifest {}: {}", manifest.display(), e),
+        )
+    })?;
+    let expected = parse_manifest(&content).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: {}", manifest.display(), e),
+        )
+    })?;
+
+    let mut file = BufReader::with_capacity(io_buffer_size, archive);
+    check_decompressor_available(&mut file)?;
+    let mut entries = Entries::new();
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if compression.is_uncompressed() {
+            read_cpio_entries(&mut file, &mut entries)?;
+        } else {
+            let mut decompressed =
+                decompress_buffered(compression, file, io_buffer_size, log_level)?;
+            read_cpio_entries(&mut decompressed, &mut entries)?;
+            decompressed.finish()?;
+            break;
+        }
+    }
+
+    let mut count = 0;
+    for (name, entry) in &entries {
+        match expected.get(name) {
+            None => {
+                count += 1;
+                writeln!(out, "{}: only in the archive", name)?;
+            }
+            Some(expected) => {
+                for difference in differences(entry, expected) {
+                    count += 1;
+                    writeln!(out, "{}: {}", name, difference)?;
+                }
+            }
+        }
+    }
+    for name in expected.keys().filter(|name| !entries.contains_key(*name)) {
+        count += 1;
+        writeln!(out, "{}: only in the manifest", name)?;
+    }
+    if count > 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} differences between the archive and the manifest found.",
+                count
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let content = "# initramfs spec\n\
+            dir /dev 0755 0 0\n\
+            nod /dev/console 0600 0 0 c 5 1\n\
+            file /init /tmp/init 0755 0 0 /sbin/init\n\
+            slink /bin /usr/bin 0777 0 0\n";
+        let entries = parse_manifest(content).unwrap();
+        assert_eq!(
+            entries.keys().collect::<Vec<_>>(),
+            vec!["bin", "dev", "dev/console", "init", "sbin/init"]
+        );
+        assert_eq!(
+            entries["dev/console"],
+            Entry {
+                mode: 0o020_600,
+                uid: 0,
+                gid: 0,
+                target: None,
+                rdev: Some((5, 1)),
+            }
+        );
+        assert_eq!(entries["bin"].target, Some("/usr/bin".into()));
+    }
+
+    #[test]
+    fn test_parse_manifest_invalid() {
+        assert_eq!(
+            parse_manifest("dir /dev 0755 0 0\nfile /init 0755 0 0\n"),
+            Err("line 2: Unknown or incomplete entry 'file'. Expected file, dir, nod, slink, pipe, or sock.".into())
+        );
+        assert_eq!(
+            parse_manifest("dir /dev 0855 0 0\n"),
+            Err("line 1: Invalid number '0855'.".into())
+        );
+    }
+
+    #[test]
+    fn test_differences() {
+        let archive = Entry {
+            mode: 0o100_644,
+            uid: 1000,
+            gid: 0,
+            target: None,
+            rdev: None,
+        };
+        let manifest = Entry {
+            mode: 0o100_755,
+            uid: 0,
+            gid: 0,
+            target: None,
+            rdev: None,
+        };
+        assert_eq!(
+            differences(&archive, &manifest),
+            vec![
+                "mode 0644 in the archive, 0755 in the manifest",
+                "uid 1000 in the archive, 0 in the manifest",
+            ]
+        );
+    }
+}
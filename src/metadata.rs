@@ -0,0 +1,159 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Stable view of the metadata of the cpio entries
+//!
+//! The fields of `Header` follow the on-disk cpio format and may change
+//! between versions. `EntryMetadata` is the documented interface for
+//! external crates and keeps its accessors stable across semver-compatible
+//! versions.
+
+use crate::header::*;
+
+/// Type of a cpio entry (as encoded in the upper bits of the mode)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FileType {
+    Fifo,
+    CharacterDevice,
+    Directory,
+    BlockDevice,
+    RegularFile,
+    Symlink,
+    Socket,
+}
+
+impl FileType {
+    /// Return the file type of the mode or `None` for an unknown file type.
+    pub fn from_mode(mode: u32) -> Option<Self> {
+        let file_type = match mode & MODE_FILETYPE_MASK {
+            FILETYPE_FIFO => Self::Fifo,
+            FILETYPE_CHARACTER_DEVICE => Self::CharacterDevice,
+            FILETYPE_DIRECTORY => Self::Directory,
+            FILETYPE_BLOCK_DEVICE => Self::BlockDevice,
+            FILETYPE_REGULAR_FILE => Self::RegularFile,
+            FILETYPE_SYMLINK => Self::Symlink,
+            FILETYPE_SOCKET => Self::Socket,
+            _ => return None,
+        };
+        Some(file_type)
+    }
+
+    /// Return the file type bits of the mode.
+    pub fn to_mode(self) -> u32 {
+        match self {
+            Self::Fifo => FILETYPE_FIFO,
+            Self::CharacterDevice => FILETYPE_CHARACTER_DEVICE,
+            Self::Directory => FILETYPE_DIRECTORY,
+            Self::BlockDevice => FILETYPE_BLOCK_DEVICE,
+            Self::RegularFile => FILETYPE_REGULAR_FILE,
+            Self::Symlink => FILETYPE_SYMLINK,
+            Self::Socket => FILETYPE_SOCKET,
+        }
+    }
+}
+
+/// Read-only view of the metadata of a cpio entry
+#[derive(Clone, Copy, Debug)]
+pub struct EntryMetadata<'a> {
+    header: &'a Header,
+}
+
+impl<'a> EntryMetadata<'a> {
+    pub(crate) fn new(header: &'a Header) -> Self {
+        Self { header }
+    }
+
+    /// Name of the entry as stored in the archive (e.g. `./usr/bin/sh`)
+    pub fn name(&self) -> &'a str {
+        &self.header.filename
+    }
+
+    /// File type or `None` if the mode contains an unknown file type
+    pub fn file_type(&self) -> Option<FileType> {
+        self.header.file_type()
+    }
+
+    /// Complete mode (file type and permission bits)
+    pub fn mode(&self) -> u32 {
+        self.header.mode
+    }
+
+    /// Permission bits including the setuid, setgid, and sticky bits
+    pub fn permissions(&self) -> u32 {
+        self.header.mode_perm()
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.header.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.header.gid
+    }
+
+    /// Number of hard links
+    pub fn nlink(&self) -> u32 {
+        self.header.nlink
+    }
+
+    /// Modification time in seconds since the epoch
+    pub fn mtime(&self) -> u32 {
+        self.header.mtime
+    }
+
+    /// Size of the file data in bytes (the target length for symlinks)
+    pub fn size(&self) -> u64 {
+        self.header.filesize.into()
+    }
+
+    pub fn ino(&self) -> u32 {
+        self.header.ino
+    }
+
+    /// Major and minor number of the device containing the file
+    pub fn dev(&self) -> (u32, u32) {
+        (self.header.major, self.header.minor)
+    }
+
+    /// Major and minor number of character and block devices
+    pub fn rdev(&self) -> Option<(u32, u32)> {
+        match self.file_type() {
+            Some(FileType::CharacterDevice | FileType::BlockDevice) => {
+                Some((self.header.rmajor, self.header.rminor))
+            }
+            _ => None,
+        }
+    }
+
+    /// Checksum of the file data (only for the crc format 070702)
+    pub fn checksum(&self) -> Option<u32> {
+        self.header.check
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_type_from_mode() {
+        assert_eq!(FileType::from_mode(0o100_644), Some(FileType::RegularFile));
+        assert_eq!(FileType::from_mode(0o040_755), Some(FileType::Directory));
+        assert_eq!(FileType::from_mode(0o170_000), None);
+        assert_eq!(FileType::Symlink.to_mode() | 0o777, 0o120_777);
+    }
+
+    #[test]
+    fn test_entry_metadata() {
+        let mut header = Header::new(1, 0o020_600, 0, 5, 1, 1713104326, 0, "dev/console".into());
+        header.rmajor = 5;
+        header.rminor = 1;
+        let metadata = header.metadata();
+        assert_eq!(metadata.name(), "dev/console");
+        assert_eq!(metadata.file_type(), Some(FileType::CharacterDevice));
+        assert_eq!(metadata.permissions(), 0o600);
+        assert_eq!(metadata.gid(), 5);
+        assert_eq!(metadata.rdev(), Some((5, 1)));
+        assert_eq!(metadata.dev(), (0, 0));
+    }
+}
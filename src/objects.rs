@@ -0,0 +1,400 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Extract the cpio archives into a content-addressed object store.
+//!
+//! The content of each regular file is stored once in the `objects`
+//! directory named after its SHA-256 hash (the first two hex digits form a
+//! sub-directory). The metadata of all entries is written to the `index`
+//! file with one tab-separated line per entry:
+//!
+//! ```text
+//! <type> <mode> <uid> <gid> <mtime> <reference> <name>
+//! ```
+//!
+//! The type is the first character of the `ls -l` mode string and the mode
+//! is the octal permission. The reference is the SHA-256 hash for regular
+//! files, the target for symlinks, `<major>:<minor>` for device files, and
+//! `-` for everything else. Backslashes, tabs, and newlines in the reference
+//! and name are escaped as `\\`, `\t`, and `\n`.
+//!
+//! Extracting multiple initrd versions into the same directory (with
+//! separate index files) stores identical files only once.
+
+use std::collections::HashMap;
+use std::fs::{create_dir_all, remove_file, rename, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use sha2::{Digest, Sha256};
+
+use crate::header::*;
+use crate::metadata::FileType;
+use crate::seek_forward::SeekForward;
+use crate::{
+    align_to_4_bytes, check_decompressor_available, decompress_buffered, read_magic_header,
+    LOG_LEVEL_DEBUG, LOG_LEVEL_INFO,
+};
+
+/// Number of the next incoming object file (unique within the process)
+static INCOMING_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Reference to the content of an entry in the index
+enum Reference {
+    Object(String),
+    /// Hard-link without data; resolved at the end of its cpio archive.
+    HardLink(u128),
+    Text(String),
+    None,
+}
+
+struct IndexEntry {
+    header: Header,
+    reference: Reference,
+}
+
+/// Writer that computes the SHA-256 hash of the written data.
+pub(crate) struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Flush the writer and return the SHA-256 hash as hex string.
+    pub(crate) fn finish(mut self) -> Result<String> {
+        self.inner.flush()?;
+        Ok(hex(&self.hasher.finalize()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+struct ObjectStore {
+    objects_dir: PathBuf,
+    entries: Vec<IndexEntry>,
+    /// Hashes of the hard-linked files of the current cpio archive
+    hard_links: HashMap<u128, String>,
+    empty_hash: Option<String>,
+}
+
+impl ObjectStore {
+    fn new(directory: &Path) -> Result<Self> {
+        let objects_dir = directory.join("objects");
+        create_dir_all(&objects_dir)?;
+        Ok(Self {
+            objects_dir,
+            entries: Vec::new(),
+            hard_links: HashMap::new(),
+            empty_hash: None,
+        })
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.objects_dir.join(&hash[..2]).join(&hash[2..])
+    }
+
+    /// Create a new file for an incoming object and return it with its path.
+    ///
+    /// Concurrent extractions into the same directory use separate files.
+    fn create_incoming(&self) -> Result<(File, PathBuf)> {
+        loop {
+            let count = INCOMING_COUNT.fetch_add(1, Ordering::Relaxed);
+            let path = self
+                .objects_dir
+                .join(format!("incoming.{}.{}", std::process::id(), count));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((file, path)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Store the content of the file in the object store and return its hash.
+    fn store<R: Read>(&self, content: &mut R, filesize: u64) -> Result<String> {
+        let (incoming, incoming_path) = self.create_incoming()?;
+        let result = self.store_incoming(incoming, &incoming_path, content, filesize);
+        if result.is_err() {
+            let _ = remove_file(&incoming_path);
+        }
+        result
+    }
+
+    fn store_incoming<R: Read>(
+        &self,
+        incoming: File,
+        incoming_path: &Path,
+        content: &mut R,
+        filesize: u64,
+    ) -> Result<String> {
+        let mut writer = HashingWriter::new(BufWriter::new(incoming));
+        let copied = std::io::copy(&mut content.take(filesize), &mut writer)?;
+        if copied != filesize {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "Expected {} bytes of file content, got {}.",
+                    filesize, copied
+                ),
+            ));
+        }
+        let hash = writer.finish()?;
+        let path = self.object_path(&hash);
+        if path.exists() {
+            remove_file(incoming_path)?;
+        } else {
+            create_dir_all(path.parent().unwrap())?;
+            rename(incoming_path, &path)?;
+        }
+        Ok(hash)
+    }
+
+    /// Return the hash of the empty object (storing it on first use).
+    fn empty_hash(&mut self) -> Result<String> {
+        if self.empty_hash.is_none() {
+            self.empty_hash = Some(self.store(&mut std::io::empty(), 0)?);
+        }
+        Ok(self.empty_hash.clone().unwrap())
+    }
+
+    /// Resolve the hard-links without data of the entries starting at `first`.
+    ///
+    /// Each cpio archive numbers its inodes independently. So the hard-links
+    /// are forgotten at the end of each cpio archive.
+    fn resolve_hard_links(&mut self, first: usize) -> Result<()> {
+        for i in first..self.entries.len() {
+            if let Reference::HardLink(ino_and_dev) = self.entries[i].reference {
+                let hash = match self.hard_links.get(&ino_and_dev) {
+                    Some(hash) => hash.clone(),
+                    None => self.empty_hash()?,
+                };
+                self.entries[i].reference = Reference::Object(hash);
+            }
+        }
+        self.hard_links.clear();
+        Ok(())
+    }
+
+    fn read_file<R: Read + SeekForward>(
+        &mut self,
+        file: &mut R,
+        header: &Header,
+        log_level: u32,
+    ) -> Result<Reference> {
+        if header.nlink > 1 && header.filesize == 0 {
+            return Ok(Reference::HardLink(header.ino_and_dev()));
+        }
+        let hash = self.store(file, header.filesize.into())?;
+        file.seek_forward(align_to_4_bytes(header.filesize).into())?;
+        if log_level >= LOG_LEVEL_DEBUG {
+            writeln!(
+                std::io::stderr(),
+                "Stored '{}' as object {}",
+                header.filename,
+                hash
+            )?;
+        }
+        if header.nlink > 1 {
+            self.hard_links.insert(header.ino_and_dev(), hash.clone());
+        }
+        Ok(Reference::Object(hash))
+    }
+
+    fn read_cpio<R: Read + SeekForward>(&mut self, file: &mut R, log_level: u32) -> Result<()> {
+        let first = self.entries.len();
+        loop {
+            let header = Header::read(file)?;
+            if header.filename == "TRAILER!!!" {
+                break;
+            }
+            if log_level >= LOG_LEVEL_DEBUG {
+                writeln!(std::io::stderr(), "{:?}", header)?;
+            } else if log_level >= LOG_LEVEL_INFO {
+                writeln!(std::io::stderr(), "{}", header.filename)?;
+            }
+
+            let reference = match header.file_type() {
+                Some(FileType::RegularFile) => self.read_file(file, &header, log_level)?,
+                Some(FileType::Symlink) => Reference::Text(header.read_symlink_target(file)?),
+                Some(FileType::BlockDevice | FileType::CharacterDevice) => {
+                    header.skip_file_content(file)?;
+                    Reference::Text(format!("{}:{}", header.rmajor, header.rminor))
+                }
+                _ => {
+                    header.skip_file_content(file)?;
+                    Reference::None
+                }
+            };
+            self.entries.push(IndexEntry { header, reference });
+        }
+        self.resolve_hard_links(first)
+    }
+
+    /// Write the index of all entries.
+    fn write_index<W: Write>(&self, out: &mut W) -> Result<()> {
+        for entry in &self.entries {
+            let header = &entry.header;
+            let reference = match &entry.reference {
+                Reference::Object(hash) => hash.clone(),
+                Reference::HardLink(_) => unreachable!("hard-links are resolved at the trailer"),
+                Reference::Text(text) => escape(text),
+                Reference::None => "-".into(),
+            };
+            writeln!(
+                out,
+                "{}\t{:o}\t{}\t{}\t{}\t{}\t{}",
+                char::from(header.mode_string()[0]),
+                header.mode_perm(),
+                header.uid,
+                header.gid,
+                header.mtime,
+                reference,
+                escape(&header.filename)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Extract the cpio archives into a content-addressed object store.
+///
+/// Store the content of the regular files in the `objects` directory below
+/// `directory` and write the metadata of all entries to the `index` file.
+/// See the module documentation for the format of the index.
+pub fn extract_objects(
+    archive: File,
+    directory: &Path,
+    index: &Path,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let mut file = BufReader::with_capacity(io_buffer_size, archive);
+    check_decompressor_available(&mut file)?;
+    let mut store = ObjectStore::new(directory)?;
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if compression.is_uncompressed() {
+            store.read_cpio(&mut file, log_level)?;
+        } else {
+            let mut decompressed =
+                decompress_buffered(compression, file, io_buffer_size, log_level)?;
+            store.read_cpio(&mut decompressed, log_level)?;
+            decompressed.finish()?;
+            break;
+        }
+    }
+    let mut out = BufWriter::new(File::create(index)?);
+    store.write_index(&mut out)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::cpio_object;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\tb\nc\\d"), "a\\tb\\nc\\\\d");
+    }
+
+    #[test]
+    fn test_extract_objects() {
+        let directory = crate::libc::make_temp_dir().unwrap();
+        let archive = File::open("tests/single.cpio").expect("test cpio should be present");
+        let index = directory.join("index");
+        extract_objects(
+            archive,
+            &directory,
+            &index,
+            0,
+            crate::DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+
+        let index = std::fs::read_to_string(index).unwrap();
+        let hash = "434728a410a78f56fc1b5899c3593436e61ab0c731e9072d95e96db290205e53";
+        assert_eq!(
+            index,
+            format!(
+                "d\t775\t1000\t1000\t1713104326\t-\t.\n\
+                 d\t775\t1000\t1000\t1713104326\t-\tpath\n\
+                 -\t664\t1000\t1000\t1713104326\t{hash}\tpath/file\n"
+            )
+        );
+        let object = directory.join("objects").join(&hash[..2]).join(&hash[2..]);
+        assert_eq!(std::fs::read(object).unwrap(), b"content\n");
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_extract_objects_colliding_inodes() {
+        // Each cpio archive numbers its inodes independently.
+        let mut cpio = Vec::new();
+        for (name, data) in [("a", b"AAAA\n".as_slice()), ("b", b"BB\n".as_slice())] {
+            cpio.extend(cpio_object(1, 0o100_644, 2, &format!("{}1", name), b""));
+            cpio.extend(cpio_object(1, 0o100_644, 2, &format!("{}2", name), data));
+            cpio.extend(cpio_object(0, 0, 1, "TRAILER!!!", b""));
+        }
+        let directory = crate::libc::make_temp_dir().unwrap();
+        let archive = directory.join("archive.cpio");
+        std::fs::write(&archive, cpio).unwrap();
+        let index = directory.join("index");
+        extract_objects(
+            File::open(archive).unwrap(),
+            &directory,
+            &index,
+            0,
+            crate::DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+
+        let index = std::fs::read_to_string(index).unwrap();
+        let references: Vec<&str> = index
+            .lines()
+            .map(|line| line.split('\t').nth(5).unwrap())
+            .collect();
+        let hash_a = "4677942dfa3e74b5dea7484661a2485bb73ba422eb72d311fdb39372c019c615";
+        let hash_b = "68cd080c537d3f1355f357189f74f3fe1c68dd13cf406a84aedc934c90a0df31";
+        assert_eq!(references, vec![hash_a, hash_a, hash_b, hash_b]);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,591 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Table of the command line options
+//!
+//! The parser only accepts the options listed here and the shell
+//! completions are generated from this table.
+
+use std::io::{Result, Write};
+
+use lexopt::Arg;
+
+/// Identifier of a command line option
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Opt {
+    AlignMinSize,
+    AlignPattern,
+    Cat,
+    CheckBoot,
+    CheckLibraries,
+    CheckSymlinks,
+    Count,
+    DataAlign,
+    Debug,
+    DecompressMemlimit,
+    Delete,
+    DiffManifest,
+    Directory,
+    DumpCompletions,
+    Examine,
+    Extract,
+    Force,
+    Format,
+    Grep,
+    HardlinkPolicy,
+    Help,
+    Idmap,
+    Index,
+    IoBufferSize,
+    Jobs,
+    List,
+    LogLevel,
+    Match,
+    MetadataOnly,
+    NoCache,
+    NoPreservePermissions,
+    NoSetuid,
+    Null,
+    Objects,
+    Offsets,
+    Output,
+    PreservePermissions,
+    QuotingStyle,
+    Rename,
+    Replace,
+    SanitizeModes,
+    Segments,
+    SetMtime,
+    Sorted,
+    Subdir,
+    Summary,
+    ToStdout,
+    Touch,
+    Verbose,
+    Verify,
+    VerifyOnExtract,
+    Version,
+    WriteNamesTo,
+}
+
+/// Value of a command line option
+pub enum Value {
+    /// The option takes no value.
+    None,
+    /// The option takes an arbitrary value.
+    Any(&'static str),
+    /// The option takes a directory.
+    Directory,
+    /// The option takes a file name.
+    File,
+    /// The option takes one of the given values.
+    Choices(&'static [&'static str]),
+}
+
+pub struct CliOption {
+    pub opt: Opt,
+    pub short: Option<char>,
+    pub long: &'static str,
+    pub value: Value,
+    /// Short description (used for the zsh completion)
+    pub description: &'static str,
+}
+
+const fn option(
+    opt: Opt,
+    short: Option<char>,
+    long: &'static str,
+    value: Value,
+    description: &'static str,
+) -> CliOption {
+    CliOption {
+        opt,
+        short,
+        long,
+        value,
+        description,
+    }
+}
+
+pub const OPTIONS: &[CliOption] = &[
+    option(
+        Opt::AlignMinSize,
+        None,
+        "align-min-size",
+        Value::Any("BYTES"),
+        "only align the data of files with at least BYTES bytes",
+    ),
+    option(
+        Opt::AlignPattern,
+        None,
+        "align-pattern",
+        Value::Any("PATTERN"),
+        "only align the data of files matching the pattern",
+    ),
+    option(
+        Opt::Cat,
+        None,
+        "cat",
+        Value::None,
+        "concatenate the cpio files with correct padding",
+    ),
+    option(
+        Opt::CheckBoot,
+        None,
+        "check-boot",
+        Value::None,
+        "check that the initramfs contains what is needed for booting",
+    ),
+    option(
+        Opt::CheckLibraries,
+        None,
+        "check-libraries",
+        Value::None,
+        "report shared libraries needed by ELF files but missing",
+    ),
+    option(
+        Opt::CheckSymlinks,
+        None,
+        "check-symlinks",
+        Value::None,
+        "report dangling and out-of-tree symlinks",
+    ),
+    option(
+        Opt::Count,
+        None,
+        "count",
+        Value::None,
+        "print the number of entries",
+    ),
+    option(
+        Opt::DataAlign,
+        None,
+        "data-align",
+        Value::Any("BYTES"),
+        "align the file data to a multiple of BYTES",
+    ),
+    option(Opt::Debug, None, "debug", Value::None, "debug output"),
+    option(
+        Opt::DecompressMemlimit,
+        None,
+        "decompress-memlimit",
+        Value::Any("BYTES"),
+        "limit the memory usage of the decompressors",
+    ),
+    option(
+        Opt::Delete,
+        None,
+        "delete",
+        Value::Any("PATTERN"),
+        "delete the entries matching the pattern",
+    ),
+    option(
+        Opt::DiffManifest,
+        None,
+        "diff-manifest",
+        Value::File,
+        "compare the entries against a gen_init_cpio manifest",
+    ),
+    option(
+        Opt::Directory,
+        Some('C'),
+        "directory",
+        Value::Directory,
+        "change directory before performing any operation",
+    ),
+    option(
+        Opt::DumpCompletions,
+        None,
+        "dump-completions",
+        Value::Choices(&["bash", "zsh"]),
+        "print the shell completion script",
+    ),
+    option(
+        Opt::Examine,
+        Some('e'),
+        "examine",
+        Value::None,
+        "list the offsets and compression of the cpio archives",
+    ),
+    option(
+        Opt::Extract,
+        Some('x'),
+        "extract",
+        Value::None,
+        "extract cpio archives",
+    ),
+    option(
+        Opt::Force,
+        None,
+        "force",
+        Value::None,
+        "force overwriting existing files",
+    ),
+    option(
+        Opt::Format,
+        None,
+        "format",
+        Value::Any("FORMAT"),
+        "output format",
+    ),
+    option(
+        Opt::Grep,
+        None,
+        "grep",
+        Value::Any("PATTERN"),
+        "search the content of the regular files",
+    ),
+    option(
+        Opt::HardlinkPolicy,
+        None,
+        "hardlink-policy",
+        Value::Choices(&["strict", "lenient"]),
+        "how to handle inconsistent hard-links",
+    ),
+    option(
+        Opt::Help,
+        Some('h'),
+        "help",
+        Value::None,
+        "print help message",
+    ),
+    option(
+        Opt::Idmap,
+        None,
+        "idmap",
+        Value::Any("MAP"),
+        "translate the owners of the extracted entries",
+    ),
+    option(
+        Opt::Index,
+        None,
+        "index",
+        Value::None,
+        "write a JSON catalog of all entries",
+    ),
+    option(
+        Opt::IoBufferSize,
+        None,
+        "io-buffer-size",
+        Value::Any("BYTES"),
+        "size of the read buffer",
+    ),
+    option(
+        Opt::Jobs,
+        Some('j'),
+        "jobs",
+        Value::Any("N"),
+        "number of worker threads for listing",
+    ),
+    option(
+        Opt::List,
+        Some('t'),
+        "list",
+        Value::None,
+        "list the contents of the cpio archives",
+    ),
+    option(
+        Opt::LogLevel,
+        None,
+        "log-level",
+        Value::Choices(&["error", "warning", "info", "debug"]),
+        "verbosity of the messages on stderr",
+    ),
+    option(
+        Opt::Match,
+        None,
+        "match",
+        Value::Choices(&["glob", "globstar", "prefix", "exact"]),
+        "how the name patterns select the entries",
+    ),
+    option(
+        Opt::MetadataOnly,
+        None,
+        "metadata-only",
+        Value::None,
+        "extract empty placeholders instead of the file content",
+    ),
+    option(
+        Opt::NoPreservePermissions,
+        None,
+        "no-preserve-permissions",
+        Value::None,
+        "do not set the owner and apply the umask",
+    ),
+    option(
+        Opt::NoCache,
+        None,
+        "no-cache",
+        Value::None,
+        "drop the extracted files from the page cache",
+    ),
+    option(
+        Opt::NoSetuid,
+        None,
+        "no-setuid",
+        Value::None,
+        "clear the setuid and setgid bits",
+    ),
+    option(
+        Opt::Null,
+        Some('0'),
+        "null",
+        Value::None,
+        "terminate the listed names with a NUL byte",
+    ),
+    option(
+        Opt::Objects,
+        None,
+        "objects",
+        Value::File,
+        "extract into a content-addressed object store",
+    ),
+    option(
+        Opt::Offsets,
+        None,
+        "offsets",
+        Value::None,
+        "list the file offsets of the entries",
+    ),
+    option(
+        Opt::Output,
+        Some('o'),
+        "output",
+        Value::File,
+        "write the output to the given file",
+    ),
+    option(
+        Opt::PreservePermissions,
+        Some('p'),
+        "preserve-permissions",
+        Value::None,
+        "set the permissions recorded in the archive",
+    ),
+    option(
+        Opt::QuotingStyle,
+        None,
+        "quoting-style",
+        Value::Choices(&["literal", "escape", "c"]),
+        "quote the listed names",
+    ),
+    option(
+        Opt::Rename,
+        None,
+        "rename",
+        Value::Any("OLD=NEW"),
+        "rename an entry (and the entries below it)",
+    ),
+    option(
+        Opt::Replace,
+        None,
+        "replace",
+        Value::Any("NAME=FILE"),
+        "replace the content of an entry",
+    ),
+    option(
+        Opt::SanitizeModes,
+        None,
+        "sanitize-modes",
+        Value::None,
+        "clear setuid, setgid, sticky, and world-writable bits",
+    ),
+    option(
+        Opt::Segments,
+        None,
+        "segments",
+        Value::None,
+        "count the cpio archives instead of the entries",
+    ),
+    option(
+        Opt::SetMtime,
+        None,
+        "set-mtime",
+        Value::Any("EPOCH"),
+        "set the modification time of the entries",
+    ),
+    option(
+        Opt::Sorted,
+        None,
+        "sorted",
+        Value::None,
+        "extract the entries sorted by name",
+    ),
+    option(
+        Opt::Subdir,
+        Some('s'),
+        "subdir",
+        Value::Any("NAME"),
+        "extract the cpio archives into separate directories",
+    ),
+    option(
+        Opt::Summary,
+        None,
+        "summary",
+        Value::None,
+        "print a summary after extracting",
+    ),
+    option(
+        Opt::ToStdout,
+        None,
+        "to-stdout",
+        Value::None,
+        "write the content of the regular files to stdout",
+    ),
+    option(
+        Opt::Touch,
+        None,
+        "touch",
+        Value::None,
+        "do not restore the modification times",
+    ),
+    option(
+        Opt::Verbose,
+        Some('v'),
+        "verbose",
+        Value::None,
+        "verbose output",
+    ),
+    option(
+        Opt::Verify,
+        None,
+        "verify",
+        Value::None,
+        "verify the checksums of crc format cpio archives",
+    ),
+    option(
+        Opt::VerifyOnExtract,
+        None,
+        "verify-on-extract",
+        Value::None,
+        "verify the checksums while extracting",
+    ),
+    option(
+        Opt::Version,
+        Some('V'),
+        "version",
+        Value::None,
+        "print version number and exit",
+    ),
+    option(
+        Opt::WriteNamesTo,
+        None,
+        "write-names-to",
+        Value::Any("FD"),
+        "write the names of the written files to the file descriptor",
+    ),
+];
+
+/// Look up the command line option in the option table.
+pub fn find_option(arg: &Arg) -> Option<&'static CliOption> {
+    OPTIONS.iter().find(|option| match arg {
+        Arg::Short(short) => option.short == Some(*short),
+        Arg::Long(long) => option.long == *long,
+        Arg::Value(_) => false,
+    })
+}
+
+/// Shell for the completion script
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+}
+
+impl std::str::FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            _ => Err(format!(
+                "Unsupported shell '{}'. Expected 'bash' or 'zsh'.",
+                s
+            )),
+        }
+    }
+}
+
+fn option_names(option: &CliOption) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Some(short) = option.short {
+        names.push(format!("-{}", short));
+    }
+    names.push(format!("--{}", option.long));
+    names
+}
+
+fn write_bash_completion<W: Write>(out: &mut W, executable: &str) -> Result<()> {
+    writeln!(out, "_3cpio() {{")?;
+    writeln!(out, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"")?;
+    writeln!(out, "    local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"")?;
+    writeln!(out, "    case \"$prev\" in")?;
+    for option in OPTIONS {
+        let reply = match option.value {
+            Value::None => continue,
+            Value::Any(_) => "()".to_string(),
+            Value::Directory => "($(compgen -d -- \"$cur\"))".to_string(),
+            Value::File => "($(compgen -f -- \"$cur\"))".to_string(),
+            Value::Choices(choices) => {
+                format!("($(compgen -W \"{}\" -- \"$cur\"))", choices.join(" "))
+            }
+        };
+        writeln!(
+            out,
+            "        {})\n            COMPREPLY={}\n            return\n            ;;",
+            option_names(option).join("|"),
+            reply
+        )?;
+    }
+    writeln!(out, "    esac")?;
+    let all: Vec<String> = OPTIONS.iter().flat_map(option_names).collect();
+    writeln!(out, "    if [[ \"$cur\" == -* ]]; then")?;
+    writeln!(
+        out,
+        "        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+        all.join(" ")
+    )?;
+    writeln!(out, "    else")?;
+    writeln!(out, "        COMPREPLY=($(compgen -f -- \"$cur\"))")?;
+    writeln!(out, "    fi")?;
+    writeln!(out, "}}")?;
+    writeln!(out, "complete -F _3cpio {}", executable)?;
+    Ok(())
+}
+
+fn write_zsh_completion<W: Write>(out: &mut W, executable: &str) -> Result<()> {
+    writeln!(out, "#compdef {}", executable)?;
+    writeln!(out)?;
+    writeln!(out, "_arguments -s \\")?;
+    for option in OPTIONS {
+        let (value_suffix, action) = match option.value {
+            Value::None => ("", String::new()),
+            Value::Any(name) => ("=", format!(":{}: ", name)),
+            Value::Directory => ("=", ":DIR:_files -/".to_string()),
+            Value::File => ("=", ":FILE:_files".to_string()),
+            Value::Choices(choices) => ("=", format!(":VALUE:({})", choices.join(" "))),
+        };
+        let long = format!("--{}{}", option.long, value_suffix);
+        let names = match option.short {
+            Some(short) => {
+                let short_suffix = if value_suffix.is_empty() { "" } else { "+" };
+                format!(
+                    "'(-{short} --{})'{{-{short}{short_suffix},{long}}}",
+                    option.long
+                )
+            }
+            None => long,
+        };
+        writeln!(out, "    {}'[{}]{}' \\", names, option.description, action)?;
+    }
+    writeln!(out, "    '*:file:_files'")?;
+    Ok(())
+}
+
+/// Write the completion script for the shell.
+pub fn write_completion<W: Write>(out: &mut W, shell: Shell, executable: &str) -> Result<()> {
+    match shell {
+        Shell::Bash => write_bash_completion(out, executable),
+        Shell::Zsh => write_zsh_completion(out, executable),
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Sans-I/O parser for cpio archives
+//!
+//! The `Parser` does not read anything itself. The caller feeds it bytes and
+//! gets events back. This allows sharing the parsing between the synchronous
+//! readers, the `AsyncCpioReader`, and tools that cannot do I/O (e.g. WASM or
+//! fuzzing targets).
+
+use std::io::{Error, ErrorKind, Result};
+
+use crate::align_to_4_bytes;
+use crate::compression::Compression;
+use crate::header::{decode_filename, padded_name_size, Header, CPIO_HEADER_LENGTH};
+
+/// Event returned by `Parser::next`
+#[derive(Debug, PartialEq)]
+pub enum Event<'a> {
+    /// At least the given number of bytes are needed to continue.
+    NeedMore(usize),
+    /// Start of a cpio archive with the given compression.
+    ///
+    /// The magic number is not consumed. Compressed archives need to be
+    /// decompressed by the caller and fed into a new parser.
+    Archive(Compression),
+    /// Header of the next entry (followed by `Data` events for its content)
+    Entry(Header),
+    /// File data of the current entry
+    Data(&'a [u8]),
+    /// Trailer (end) of the current cpio archive
+    Trailer(Header),
+}
+
+#[derive(Debug)]
+enum State {
+    /// Expecting the magic number of the next archive (after zero padding)
+    Magic,
+    /// Expecting the fixed-size part of the next header
+    Header,
+    /// Expecting the name of the entry
+    Name(Header, u32),
+    /// Reading the file data of the current entry
+    Data { remaining: u64, padding: u64 },
+    /// Skipping bytes (padding or the content of the trailer)
+    Skip { length: u64, in_archive: bool },
+    /// Start of a compressed archive that the caller needs to decompress
+    Compressed(Compression),
+}
+
+/// Sans-I/O state machine for parsing (possibly concatenated) cpio archives
+#[derive(Debug)]
+pub struct Parser {
+    state: State,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            state: State::Magic,
+        }
+    }
+
+    /// Create a parser that expects a header right away (no magic number).
+    pub(crate) fn header() -> Self {
+        Self {
+            state: State::Header,
+        }
+    }
+
+    /// Return whether the input may end here (between cpio archives).
+    pub fn is_at_boundary(&self) -> bool {
+        matches!(self.state, State::Magic | State::Compressed(_))
+    }
+
+    /// Return the number of bytes of the file data of the current entry that
+    /// are not parsed yet.
+    pub fn data_remaining(&self) -> u64 {
+        match self.state {
+            State::Data { remaining, .. } => remaining,
+            _ => 0,
+        }
+    }
+
+    /// Parse the input and return the number of consumed bytes and the event.
+    ///
+    /// The next call needs to be fed with the input following the consumed
+    /// bytes. Malformed input is reported as errors of kind
+    /// `ErrorKind::InvalidData`.
+    pub fn next<'a>(&mut self, input: &'a [u8]) -> Result<(usize, Event<'a>)> {
+        let mut consumed = 0;
+        loop {
+            let rest = &input[consumed..];
+            match self.state {
+                State::Magic => {
+                    if rest.len() < 4 {
+                        return Ok((consumed, Event::NeedMore(4)));
+                    }
+                    if rest[..4] == [0, 0, 0, 0] {
+                        consumed += 4;
+                        continue;
+                    }
+                    let compression =
+                        Compression::from_magic_number(rest[..4].try_into().unwrap())?;
+                    self.state = if compression.is_uncompressed() {
+                        State::Header
+                    } else {
+                        State::Compressed(compression)
+                    };
+                    return Ok((consumed, Event::Archive(compression)));
+                }
+                State::Header => {
+                    let Some(buffer) = rest.get(..CPIO_HEADER_LENGTH as usize) else {
+                        return Ok((consumed, Event::NeedMore(CPIO_HEADER_LENGTH as usize)));
+                    };
+                    let (header, namesize) = Header::parse(buffer.try_into().unwrap())?;
+                    consumed += buffer.len();
+                    self.state = State::Name(header, namesize);
+                }
+                State::Name(_, namesize) => {
+                    let size = usize::try_from(padded_name_size(namesize)?).map_err(|_| {
+                        Error::new(ErrorKind::InvalidData, "Entry name size is too big.")
+                    })?;
+                    let Some(name) = rest.get(..size) else {
+                        return Ok((consumed, Event::NeedMore(size)));
+                    };
+                    consumed += size;
+                    let State::Name(mut header, _) =
+                        std::mem::replace(&mut self.state, State::Magic)
+                    else {
+                        unreachable!();
+                    };
+                    header.filename = decode_filename(name.to_vec(), namesize)?;
+                    let padding = align_to_4_bytes(header.filesize).into();
+                    if header.filename == "TRAILER!!!" {
+                        self.state = State::Skip {
+                            length: u64::from(header.filesize) + padding,
+                            in_archive: false,
+                        };
+                        return Ok((consumed, Event::Trailer(header)));
+                    }
+                    self.state = State::Data {
+                        remaining: header.filesize.into(),
+                        padding,
+                    };
+                    return Ok((consumed, Event::Entry(header)));
+                }
+                State::Data {
+                    remaining: 0,
+                    padding,
+                } => {
+                    self.state = State::Skip {
+                        length: padding,
+                        in_archive: true,
+                    };
+                }
+                State::Data { remaining, padding } => {
+                    if rest.is_empty() {
+                        return Ok((consumed, Event::NeedMore(1)));
+                    }
+                    let length = rest
+                        .len()
+                        .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+                    consumed += length;
+                    self.state = State::Data {
+                        remaining: remaining - u64::try_from(length).unwrap(),
+                        padding,
+                    };
+                    return Ok((consumed, Event::Data(&rest[..length])));
+                }
+                State::Skip {
+                    length: 0,
+                    in_archive,
+                } => {
+                    self.state = if in_archive {
+                        State::Header
+                    } else {
+                        State::Magic
+                    };
+                }
+                State::Skip { length, in_archive } => {
+                    if rest.is_empty() {
+                        return Ok((consumed, Event::NeedMore(1)));
+                    }
+                    let skip = rest
+                        .len()
+                        .min(usize::try_from(length).unwrap_or(usize::MAX));
+                    consumed += skip;
+                    self.state = State::Skip {
+                        length: length - u64::try_from(skip).unwrap(),
+                        in_archive,
+                    };
+                }
+                State::Compressed(compression) => {
+                    return Ok((consumed, Event::Archive(compression)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed the input in chunks of the given size and collect the events.
+    fn parse_in_chunks(input: &[u8], chunk_size: usize) -> Vec<String> {
+        let mut parser = Parser::new();
+        let mut events = Vec::new();
+        let mut buffer = Vec::new();
+        let mut chunks = input.chunks(chunk_size);
+        loop {
+            let (consumed, event) = parser.next(&buffer).unwrap();
+            let description = match event {
+                Event::NeedMore(_) => None,
+                Event::Archive(compression) => Some(format!("archive {}", compression)),
+                Event::Entry(header) => Some(format!("entry {}", header.filename)),
+                Event::Data(data) => Some(format!("data {}", data.escape_ascii())),
+                Event::Trailer(_) => Some("trailer".into()),
+            };
+            buffer.drain(..consumed);
+            match description {
+                Some(description) => events.push(description),
+                None => match chunks.next() {
+                    Some(chunk) => buffer.extend_from_slice(chunk),
+                    None => break,
+                },
+            }
+            if matches!(parser.state, State::Compressed(_)) {
+                break;
+            }
+        }
+        assert!(parser.is_at_boundary());
+        events
+    }
+
+    #[test]
+    fn test_parser_single() {
+        let archive = std::fs::read("tests/single.cpio").unwrap();
+        let expected = vec![
+            "archive cpio",
+            "entry .",
+            "entry path",
+            "entry path/file",
+            "data content\\n",
+            "trailer",
+        ];
+        assert_eq!(parse_in_chunks(&archive, archive.len()), expected);
+    }
+
+    #[test]
+    fn test_parser_byte_by_byte() {
+        let archive = std::fs::read("tests/single.cpio").unwrap();
+        let events = parse_in_chunks(&archive, 1);
+        let data: Vec<_> = events.iter().filter(|e| e.starts_with("data ")).collect();
+        assert_eq!(data.len(), 8);
+        assert_eq!(events.last().unwrap(), "trailer");
+    }
+
+    #[test]
+    fn test_parser_compressed() {
+        let archive = std::fs::read("tests/gzip.cpio").unwrap();
+        let events = parse_in_chunks(&archive, 7);
+        assert_eq!(events.first().unwrap(), "archive cpio");
+        assert_eq!(events.last().unwrap(), "archive gzip");
+    }
+
+    #[test]
+    fn test_parser_invalid_magic_number() {
+        let mut parser = Parser::new();
+        let got = parser.next(b"abcd").unwrap_err();
+        assert_eq!(got.kind(), ErrorKind::InvalidData);
+    }
+}
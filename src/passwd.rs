@@ -0,0 +1,73 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Look up user and group names in /etc/passwd and /etc/group.
+//!
+//! The C library functions getpwuid() and getgrgid() use NSS, which can
+//! fail in static binaries. Parsing the files directly works everywhere.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Result};
+
+const GROUP_PATH: &str = "/etc/group";
+const PASSWD_PATH: &str = "/etc/passwd";
+
+/// Return the name of the first line whose third field matches the ID.
+///
+/// Both /etc/passwd and /etc/group store the name in the first and the
+/// ID in the third colon-separated field.
+fn find_name<R: BufRead>(reader: R, id: u32) -> Result<Option<String>> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split(':');
+        if let (Some(name), Some(_), Some(field_id)) = (fields.next(), fields.next(), fields.next())
+        {
+            if field_id.parse::<u32>() == Ok(id) {
+                return Ok(Some(name.to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn lookup_name(path: &str, id: u32) -> Result<Option<String>> {
+    match File::open(path) {
+        Ok(file) => find_name(BufReader::new(file), id),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Look up the user name for the user ID (UID) in /etc/passwd.
+pub fn passwd_name(uid: u32) -> Result<Option<String>> {
+    lookup_name(PASSWD_PATH, uid)
+}
+
+/// Look up the group name for the group ID (GID) in /etc/group.
+pub fn group_name(gid: u32) -> Result<Option<String>> {
+    lookup_name(GROUP_PATH, gid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_name() {
+        let passwd = b"# comment\nroot:x:0:0:root:/root:/bin/bash\n\
+            user:x:1000:1000:User,,,:/home/user:/bin/bash\n";
+        assert_eq!(
+            find_name(passwd.as_ref(), 1000).unwrap(),
+            Some("user".to_string())
+        );
+        assert_eq!(find_name(passwd.as_ref(), 100).unwrap(), None);
+    }
+
+    #[test]
+    fn test_group_name_root() {
+        assert_eq!(group_name(0).unwrap(), Some("root".to_string()));
+    }
+}
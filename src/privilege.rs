@@ -0,0 +1,173 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Drop the capabilities that are not needed for extracting as root
+//!
+//! Extracting with preserved permissions as root needs to change the owner
+//! of the extracted files, but nothing else of the power of root. Dropping
+//! all other capabilities after opening the archive and the target
+//! directory limits the damage a bug in the parser of a hostile archive
+//! could do.
+
+use std::io::Result;
+
+/// Capabilities kept for extracting with preserved permissions
+#[cfg(target_os = "linux")]
+const KEPT_CAPABILITIES: [u32; 5] = [
+    // Set the owner of the extracted entries.
+    0, // CAP_CHOWN
+    // Write into extracted directories without write permission.
+    1, // CAP_DAC_OVERRIDE
+    // Set the permissions and modification times of entries owned by others.
+    3, // CAP_FOWNER
+    // Keep the setgid bit of files whose group the process is not member of.
+    4, // CAP_FSETID
+    // Create device nodes.
+    27, // CAP_MKNOD
+];
+
+#[cfg(target_os = "linux")]
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Return the effective, permitted, and inheritable capability sets of the
+/// calling thread (as 64-bit masks).
+#[cfg(target_os = "linux")]
+fn capget() -> Result<(u64, u64, u64)> {
+    let mut header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapUserData::default(); 2];
+    let rc = unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let join = |low: u32, high: u32| u64::from(high) << 32 | u64::from(low);
+    Ok((
+        join(data[0].effective, data[1].effective),
+        join(data[0].permitted, data[1].permitted),
+        join(data[0].inheritable, data[1].inheritable),
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn capset(effective: u64, permitted: u64, inheritable: u64) -> Result<()> {
+    let mut header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [0, 32].map(|shift| CapUserData {
+        effective: (effective >> shift) as u32,
+        permitted: (permitted >> shift) as u32,
+        inheritable: (inheritable >> shift) as u32,
+    });
+    let rc = unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Drop all capabilities except the ones needed for extracting.
+///
+/// Keep CAP_CHOWN, CAP_DAC_OVERRIDE, CAP_FOWNER, CAP_FSETID, and CAP_MKNOD.
+/// The other capabilities are also dropped from the bounding set (if the
+/// process has CAP_SETPCAP), so that the spawned decompression programs do
+/// not regain them. Capabilities are
+/// a property of the thread on Linux. So call this function before
+/// spawning any thread. It does nothing on other platforms.
+#[cfg(target_os = "linux")]
+pub fn drop_capabilities() -> Result<()> {
+    let kept = KEPT_CAPABILITIES
+        .iter()
+        .fold(0u64, |mask, capability| mask | 1 << capability);
+    // Dropping from the bounding set requires CAP_SETPCAP. So do it first.
+    for capability in 0..64 {
+        if kept & 1 << capability != 0 {
+            continue;
+        }
+        let rc = unsafe { libc::prctl(libc::PR_CAPBSET_DROP, capability, 0, 0, 0) };
+        if rc != 0 {
+            let error = std::io::Error::last_os_error();
+            match error.raw_os_error() {
+                // The capability is unknown to the kernel.
+                Some(libc::EINVAL) => break,
+                // CAP_SETPCAP is missing. Keep the bounding set, but still
+                // drop the capabilities of the process below.
+                Some(libc::EPERM) => break,
+                _ => return Err(error),
+            }
+        }
+    }
+    let (effective, permitted, inheritable) = capget()?;
+    capset(effective & kept, permitted & kept, inheritable & kept)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn drop_capabilities() -> Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_capabilities() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        // Capabilities are per thread. So do not drop them for the test runner.
+        std::thread::spawn(|| {
+            drop_capabilities().unwrap();
+            let (effective, permitted, _) = capget().unwrap();
+            // Bits of CAP_CHOWN, CAP_DAC_OVERRIDE, CAP_FOWNER, CAP_FSETID, and CAP_MKNOD
+            assert_eq!(effective & !0x800_001B, 0);
+            assert_eq!(permitted & !0x800_001B, 0);
+            let rc = unsafe { libc::prctl(libc::PR_CAPBSET_READ, 21, 0, 0, 0) };
+            assert_eq!(rc, 0, "CAP_SYS_ADMIN should not be in the bounding set");
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_drop_capabilities_without_setpcap() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        std::thread::spawn(|| {
+            let (effective, permitted, inheritable) = capget().unwrap();
+            // Bit of CAP_SETPCAP
+            let setpcap = 1 << 8;
+            capset(
+                effective & !setpcap,
+                permitted & !setpcap,
+                inheritable & !setpcap,
+            )
+            .unwrap();
+            drop_capabilities().unwrap();
+            let (effective, permitted, _) = capget().unwrap();
+            assert_eq!(effective & !0x800_001B, 0);
+            assert_eq!(permitted & !0x800_001B, 0);
+        })
+        .join()
+        .unwrap();
+    }
+}
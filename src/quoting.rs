@@ -0,0 +1,113 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+use std::borrow::Cow;
+
+/// Quoting style for printing entry names (like ls --quoting-style)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum QuotingStyle {
+    /// Print the names as they are.
+    #[default]
+    Literal,
+    /// Escape special characters with backslash sequences like `ls -b`.
+    Escape,
+    /// Enclose the names in double quotes and escape like C strings.
+    C,
+}
+
+impl std::str::FromStr for QuotingStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "literal" => Ok(Self::Literal),
+            "escape" => Ok(Self::Escape),
+            "c" => Ok(Self::C),
+            _ => Err(format!(
+                "Unknown quoting style '{}'. Expected 'literal', 'escape', or 'c'.",
+                s
+            )),
+        }
+    }
+}
+
+fn push_escaped(quoted: &mut String, c: char) {
+    match c {
+        '\x07' => quoted.push_str("\\a"),
+        '\x08' => quoted.push_str("\\b"),
+        '\x0C' => quoted.push_str("\\f"),
+        '\n' => quoted.push_str("\\n"),
+        '\r' => quoted.push_str("\\r"),
+        '\t' => quoted.push_str("\\t"),
+        '\x0B' => quoted.push_str("\\v"),
+        '\\' => quoted.push_str("\\\\"),
+        c if c.is_control() => {
+            let mut buffer = [0; 4];
+            for byte in c.encode_utf8(&mut buffer).bytes() {
+                quoted.push_str(&format!("\\{:03o}", byte));
+            }
+        }
+        c => quoted.push(c),
+    }
+}
+
+impl QuotingStyle {
+    /// Quote the name according to the quoting style.
+    pub fn quote<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::Literal => Cow::Borrowed(name),
+            Self::Escape => {
+                if !name
+                    .chars()
+                    .any(|c| c.is_control() || c == '\\' || c == ' ')
+                {
+                    return Cow::Borrowed(name);
+                }
+                let mut quoted = String::with_capacity(name.len() + 8);
+                for c in name.chars() {
+                    match c {
+                        ' ' => quoted.push_str("\\ "),
+                        c => push_escaped(&mut quoted, c),
+                    }
+                }
+                Cow::Owned(quoted)
+            }
+            Self::C => {
+                let mut quoted = String::with_capacity(name.len() + 2);
+                quoted.push('"');
+                for c in name.chars() {
+                    match c {
+                        '"' => quoted.push_str("\\\""),
+                        c => push_escaped(&mut quoted, c),
+                    }
+                }
+                quoted.push('"');
+                Cow::Owned(quoted)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_c() {
+        assert_eq!(QuotingStyle::C.quote("a \"b\"\n"), "\"a \\\"b\\\"\\n\"");
+    }
+
+    #[test]
+    fn test_quote_escape() {
+        assert_eq!(QuotingStyle::Escape.quote("usr/bin/sh"), "usr/bin/sh");
+        assert_eq!(
+            QuotingStyle::Escape.quote("new\nline tab\t\x1b"),
+            "new\\nline\\ tab\\t\\033"
+        );
+    }
+
+    #[test]
+    fn test_quote_literal() {
+        assert_eq!(QuotingStyle::Literal.quote("new\nline"), "new\nline");
+    }
+}
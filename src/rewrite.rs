@@ -0,0 +1,680 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Rewrite the cpio archives with edited entries.
+//!
+//! The entries are streamed from the input to the output. Compressed cpio
+//! archives are decompressed and compressed again with the same compression
+//! (using the default compression level of the program).
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, Write};
+use std::os::unix::fs::FileExt;
+
+use crate::header::*;
+use crate::metadata::FileType;
+use crate::seek_forward::SeekForward;
+use crate::{
+    align_to_4_bytes, check_decompressor_available, compile_patterns, decompress_buffered,
+    entry_name_matches, patterns_match, read_magic_header, strip_entry_name, CpioFilenameReader,
+    MatchMode, Patterns, LOG_LEVEL_INFO, LOG_LEVEL_WARNING,
+};
+
+/// What to do with an entry when rewriting the cpio archives
+pub(crate) enum Edit<'a> {
+    Keep,
+    Delete,
+    /// Replace the file data with the given content.
+    Replace(&'a [u8]),
+}
+
+/// Function that decides what to do with each entry (and may change its header)
+pub(crate) type Editor<'a> = dyn FnMut(&mut Header) -> Result<Edit<'a>> + 'a;
+
+/// Alignment of the file data when rewriting the cpio archives
+///
+/// The names of the selected regular files are padded with NUL bytes so that
+/// their data starts at a multiple of the alignment.
+#[derive(Debug)]
+pub struct DataAlign {
+    bytes: u32,
+    min_size: u32,
+    patterns: Patterns,
+}
+
+impl DataAlign {
+    /// Align the data of the regular files with at least `min_size` bytes
+    /// whose names match one of the patterns to a multiple of `bytes`.
+    /// No patterns select all files.
+    pub fn new(
+        bytes: u32,
+        min_size: u32,
+        patterns: &[String],
+        match_mode: MatchMode,
+    ) -> Result<Self> {
+        if bytes == 0 || !bytes.is_multiple_of(4) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The data alignment must be a positive multiple of 4, but got {}.",
+                    bytes
+                ),
+            ));
+        }
+        Ok(Self {
+            bytes,
+            min_size,
+            patterns: compile_patterns(patterns, match_mode)?,
+        })
+    }
+
+    pub fn bytes(&self) -> u32 {
+        self.bytes
+    }
+
+    /// Return the alignment for the data of the entry (if it is selected).
+    fn alignment(&self, header: &Header) -> Option<u32> {
+        (header.filesize >= self.min_size && patterns_match(&self.patterns, &header.filename))
+            .then_some(self.bytes)
+    }
+}
+
+/// Copy the file data (and padding) of the entry and return its length.
+fn copy_data<R: Read, W: Write>(file: &mut R, out: &mut W, filesize: u32) -> Result<u64> {
+    let length = u64::from(filesize) + u64::from(align_to_4_bytes(filesize));
+    let copied = std::io::copy(&mut file.take(length), out)?;
+    if copied != length {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "failed to copy whole file data",
+        ));
+    }
+    Ok(length)
+}
+
+/// Rewrite one cpio archive (starting at `offset` in the output).
+///
+/// Return the offset in the output after the trailer.
+fn rewrite_cpio<R: Read + SeekForward, W: Write>(
+    file: &mut R,
+    out: &mut W,
+    edit: &mut Editor,
+    mut offset: u64,
+    data_align: Option<&DataAlign>,
+) -> Result<u64> {
+    loop {
+        let mut header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            offset += header.write(out, offset, None)?;
+            return Ok(offset + copy_data(file, out, header.filesize)?);
+        }
+        match edit(&mut header)? {
+            Edit::Keep => {
+                offset +=
+                    header.write(out, offset, data_align.and_then(|a| a.alignment(&header)))?;
+                offset += copy_data(file, out, header.filesize)?;
+            }
+            Edit::Delete => header.skip_file_content(file)?,
+            Edit::Replace(content) => {
+                header.skip_file_content(file)?;
+                header.filesize = u32::try_from(content.len()).map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Content for '{}' is too big.", header.filename),
+                    )
+                })?;
+                if header.check.is_some() {
+                    header.check = Some(checksum(content));
+                }
+                offset +=
+                    header.write(out, offset, data_align.and_then(|a| a.alignment(&header)))?;
+                let padding = align_to_4_bytes(header.filesize);
+                out.write_all(content)?;
+                out.write_all(&[0; 3][..padding as usize])?;
+                offset += u64::from(header.filesize) + u64::from(padding);
+            }
+        }
+    }
+}
+
+/// Rewrite the cpio archives to `output` applying `edit` to each entry.
+///
+/// If `data_align` is set, the data of the selected files is aligned
+/// (relative to the start of the uncompressed output or the start of the
+/// decompressed cpio archive).
+pub(crate) fn rewrite_cpio_archive(
+    archive: File,
+    output: File,
+    edit: &mut Editor,
+    data_align: Option<&DataAlign>,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let file_size = archive.metadata()?.len();
+    let mut file = BufReader::with_capacity(io_buffer_size, archive);
+    check_decompressor_available(&mut file)?;
+    let mut out = BufWriter::with_capacity(io_buffer_size, output);
+    let mut offset = 0;
+    loop {
+        // Keep the zero padding between (and after) the cpio archives.
+        let end = file.stream_position()?;
+        let compression = match read_magic_header(&mut file) {
+            None => {
+                write_zeros(&mut out, file_size - end)?;
+                break;
+            }
+            Some(x) => x?,
+        };
+        let padding = file.stream_position()? - end;
+        write_zeros(&mut out, padding)?;
+        offset += padding;
+        if compression.is_uncompressed() {
+            offset = rewrite_cpio(&mut file, &mut out, edit, offset, data_align)?;
+        } else {
+            let output = out.into_inner().map_err(|e| e.into_error())?;
+            let mut compressor = compression.compress(output)?;
+            let mut decompressed =
+                decompress_buffered(compression, file, io_buffer_size, log_level)?;
+            let mut writer = BufWriter::with_capacity(io_buffer_size, &mut compressor);
+            rewrite_cpio(&mut decompressed, &mut writer, edit, 0, data_align)?;
+            std::io::copy(&mut decompressed, &mut writer)?;
+            decompressed.finish()?;
+            writer.flush()?;
+            drop(writer);
+            return compressor.finish();
+        }
+    }
+    out.flush()
+}
+
+fn write_zeros<W: Write>(out: &mut W, length: u64) -> Result<()> {
+    std::io::copy(&mut std::io::repeat(0).take(length), out)?;
+    Ok(())
+}
+
+/// Rewrite the cpio archives to `output` without the entries matching the patterns.
+///
+/// The patterns are matched against the entry names (without leading `./`
+/// or `/`) according to `match_mode`. Hard-linked files store their content with the last
+/// link, so deleting that link drops the content of the remaining links.
+pub fn delete_entries(
+    archive: File,
+    output: File,
+    patterns: &[String],
+    match_mode: MatchMode,
+    data_align: Option<&DataAlign>,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let patterns = compile_patterns(patterns, match_mode)?;
+    let mut deleted = 0;
+    rewrite_cpio_archive(
+        archive,
+        output,
+        &mut |header| {
+            if !patterns_match(&patterns, &header.filename) {
+                return Ok(Edit::Keep);
+            }
+            if log_level >= LOG_LEVEL_INFO {
+                eprintln!("Deleting {}", header.filename);
+            }
+            deleted += 1;
+            Ok(Edit::Delete)
+        },
+        data_align,
+        log_level,
+        io_buffer_size,
+    )?;
+    if deleted == 0 && log_level >= LOG_LEVEL_WARNING {
+        eprintln!("Warning: No entry matches the patterns.");
+    }
+    Ok(())
+}
+
+fn count_matching_names<R: Read + SeekForward>(file: &mut R, patterns: &Patterns) -> Result<u64> {
+    let mut count = 0;
+    for name in (CpioFilenameReader { file }) {
+        if patterns_match(patterns, &name?) {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Return the number of entries in the cpio archives matching the patterns.
+///
+/// This can be used to check that `delete_entries` would change anything
+/// before rewriting the cpio archives. The cpio archives are read from the
+/// current position of `archive` (which is moved).
+pub fn count_matching_entries(
+    archive: &File,
+    patterns: &[String],
+    match_mode: MatchMode,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<u64> {
+    let patterns = compile_patterns(patterns, match_mode)?;
+    let mut file = BufReader::with_capacity(io_buffer_size, archive.try_clone()?);
+    let mut count = 0;
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if compression.is_uncompressed() {
+            count += count_matching_names(&mut file, &patterns)?;
+        } else {
+            let mut decompressed =
+                decompress_buffered(compression, file, io_buffer_size, log_level)?;
+            count += count_matching_names(&mut decompressed, &patterns)?;
+            decompressed.finish()?;
+            break;
+        }
+    }
+    Ok(count)
+}
+
+/// Sum of all bytes (as used by the cpio crc format)
+fn checksum(content: &[u8]) -> u32 {
+    content
+        .iter()
+        .fold(0u32, |sum, byte| sum.wrapping_add((*byte).into()))
+}
+
+/// Return the selected entries and the offsets of their headers and data.
+///
+/// Return `None` if one of the cpio archives is compressed (and therefore
+/// cannot be changed in place).
+fn select_uncompressed_entries(
+    archive: &File,
+    io_buffer_size: usize,
+    select: &mut dyn FnMut(&Header) -> bool,
+) -> Result<Option<Vec<(u64, u64, Header)>>> {
+    let mut file = BufReader::with_capacity(io_buffer_size, archive.try_clone()?);
+    let mut selected = Vec::new();
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if !compression.is_uncompressed() {
+            return Ok(None);
+        }
+        loop {
+            let offset = file.stream_position()?;
+            let header = Header::read(&mut file)?;
+            let data_offset = file.stream_position()?;
+            header.skip_file_content(&mut file)?;
+            if header.filename == "TRAILER!!!" {
+                break;
+            }
+            if select(&header) {
+                selected.push((offset, data_offset, header));
+            }
+        }
+    }
+    Ok(Some(selected))
+}
+
+/// Overwrite the hexadecimal header field at the offset (relative to the header).
+fn write_header_field(archive: &File, header_offset: u64, field: u64, value: u32) -> Result<()> {
+    archive.write_all_at(format!("{:08X}", value).as_bytes(), header_offset + field)
+}
+
+/// Replace the content of the entries with the given name in place.
+///
+/// This is only possible if all cpio archives are uncompressed and the
+/// content has the same size. Return `false` without changing anything
+/// otherwise (or if no entry matches).
+pub fn replace_entry_in_place(
+    archive: &File,
+    name: &str,
+    content: &[u8],
+    io_buffer_size: usize,
+) -> Result<bool> {
+    let Some(matches) = select_uncompressed_entries(archive, io_buffer_size, &mut |header| {
+        entry_name_matches(&header.filename, name)
+    })?
+    else {
+        return Ok(false);
+    };
+    if matches.is_empty()
+        || matches.iter().any(|(_, _, header)| {
+            header.file_type() != Some(FileType::RegularFile)
+                || usize::try_from(header.filesize) != Ok(content.len())
+        })
+    {
+        return Ok(false);
+    }
+    for (offset, data_offset, header) in &matches {
+        archive.write_all_at(content, *data_offset)?;
+        if header.check.is_some() {
+            write_header_field(archive, *offset, 102, checksum(content))?;
+        }
+    }
+    Ok(true)
+}
+
+/// Rewrite the cpio archives to `output` with the content of the entries
+/// with the given name replaced.
+///
+/// The metadata of the entries (except for the size) is kept.
+pub fn replace_entry(
+    archive: File,
+    output: File,
+    name: &str,
+    content: &[u8],
+    data_align: Option<&DataAlign>,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let mut replaced = 0;
+    rewrite_cpio_archive(
+        archive,
+        output,
+        &mut |header| {
+            if !entry_name_matches(&header.filename, name) {
+                return Ok(Edit::Keep);
+            }
+            if header.file_type() != Some(FileType::RegularFile) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Entry '{}' is not a regular file.", header.filename),
+                ));
+            }
+            replaced += 1;
+            Ok(Edit::Replace(content))
+        },
+        data_align,
+        log_level,
+        io_buffer_size,
+    )?;
+    if replaced == 0 {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Entry '{}' not found.", name),
+        ));
+    }
+    Ok(())
+}
+
+/// Return the new name if the entry or one of its parent directories is renamed.
+///
+/// A leading `./` or `/` of the entry name is kept.
+fn renamed(filename: &str, old: &str, new: &str) -> Option<String> {
+    let name = strip_entry_name(filename);
+    let prefix = &filename[..filename.len() - name.len()];
+    let rest = name.strip_prefix(strip_entry_name(old).trim_end_matches('/'))?;
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+    Some(format!(
+        "{}{}{}",
+        prefix,
+        strip_entry_name(new).trim_end_matches('/'),
+        rest
+    ))
+}
+
+/// Rewrite the cpio archives to `output` with renamed entries.
+///
+/// Each rename (from the old to the new name) applies to the entry with the
+/// old name and all entries below it. The first matching rename is applied.
+/// Symlink targets are not changed.
+pub fn rename_entries(
+    archive: File,
+    output: File,
+    renames: &[(String, String)],
+    data_align: Option<&DataAlign>,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let mut used = vec![false; renames.len()];
+    rewrite_cpio_archive(
+        archive,
+        output,
+        &mut |header| {
+            for (index, (old, new)) in renames.iter().enumerate() {
+                if let Some(filename) = renamed(&header.filename, old, new) {
+                    if log_level >= LOG_LEVEL_INFO {
+                        eprintln!("Renaming {} to {}", header.filename, filename);
+                    }
+                    header.filename = filename;
+                    used[index] = true;
+                    break;
+                }
+            }
+            Ok(Edit::Keep)
+        },
+        data_align,
+        log_level,
+        io_buffer_size,
+    )?;
+    if log_level >= LOG_LEVEL_WARNING {
+        for ((old, _), used) in renames.iter().zip(used) {
+            if !used {
+                eprintln!("Warning: No entry named '{}' found.", old);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Set the modification time of the entries matching the patterns in place.
+///
+/// This is only possible if all cpio archives are uncompressed. Return
+/// `false` without changing anything otherwise. No patterns select all
+/// entries.
+pub fn set_mtime_in_place(
+    archive: &File,
+    mtime: u32,
+    patterns: &[String],
+    match_mode: MatchMode,
+    io_buffer_size: usize,
+) -> Result<bool> {
+    let patterns = compile_patterns(patterns, match_mode)?;
+    let Some(matches) = select_uncompressed_entries(archive, io_buffer_size, &mut |header| {
+        patterns_match(&patterns, &header.filename)
+    })?
+    else {
+        return Ok(false);
+    };
+    for (offset, _, _) in matches {
+        write_header_field(archive, offset, 46, mtime)?;
+    }
+    Ok(true)
+}
+
+/// Rewrite the cpio archives to `output` with the modification time of the
+/// entries matching the patterns set to `mtime`. No patterns select all
+/// entries.
+#[allow(clippy::too_many_arguments)]
+pub fn set_mtime(
+    archive: File,
+    output: File,
+    mtime: u32,
+    patterns: &[String],
+    match_mode: MatchMode,
+    data_align: Option<&DataAlign>,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let patterns = compile_patterns(patterns, match_mode)?;
+    rewrite_cpio_archive(
+        archive,
+        output,
+        &mut |header| {
+            if patterns_match(&patterns, &header.filename) {
+                header.mtime = mtime;
+            }
+            Ok(Edit::Keep)
+        },
+        data_align,
+        log_level,
+        io_buffer_size,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_cpio_keep_all() {
+        let archive = std::fs::read("tests/single.cpio").unwrap();
+        let mut rewritten = Vec::new();
+        rewrite_cpio(
+            &mut archive.as_slice(),
+            &mut rewritten,
+            &mut |_| Ok(Edit::Keep),
+            0,
+            None,
+        )
+        .unwrap();
+        // The input is padded to 512 bytes after the trailer.
+        assert_eq!(rewritten, archive[..rewritten.len()]);
+        assert!(archive[rewritten.len()..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_rewrite_cpio_archive_keeps_padding() {
+        let directory = crate::libc::make_temp_dir().unwrap();
+        let path = directory.join("rewritten.cpio");
+        rewrite_cpio_archive(
+            File::open("tests/zstd.cpio").unwrap(),
+            File::create(&path).unwrap(),
+            &mut |_| Ok(Edit::Keep),
+            None,
+            LOG_LEVEL_WARNING,
+            crate::DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        let archive = std::fs::read("tests/zstd.cpio").unwrap();
+        let rewritten = std::fs::read(&path).unwrap();
+        // The zero padding after the uncompressed cpio archive is kept.
+        assert_eq!(rewritten[..512], archive[..512]);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_count_matching_entries() {
+        let file = File::open("tests/zstd.cpio").unwrap();
+        let count = count_matching_entries(
+            &file,
+            &["path".into(), "usr/bin/*".into()],
+            MatchMode::Glob,
+            LOG_LEVEL_WARNING,
+            crate::DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_rewrite_cpio_delete() {
+        let archive = std::fs::read("tests/single.cpio").unwrap();
+        let mut rewritten = Vec::new();
+        rewrite_cpio(
+            &mut archive.as_slice(),
+            &mut rewritten,
+            &mut |header| {
+                Ok(match header.filename.as_str() {
+                    "path/file" => Edit::Delete,
+                    _ => Edit::Keep,
+                })
+            },
+            0,
+            None,
+        )
+        .unwrap();
+        let mut names = Vec::new();
+        let mut reader = rewritten.as_slice();
+        loop {
+            let header = Header::read(&mut reader).unwrap();
+            header.skip_file_content(&mut reader).unwrap();
+            if header.filename == "TRAILER!!!" {
+                break;
+            }
+            names.push(header.filename);
+        }
+        assert_eq!(names, vec![".", "path"]);
+    }
+
+    #[test]
+    fn test_rewrite_cpio_replace() {
+        let archive = std::fs::read("tests/single.cpio").unwrap();
+        let mut rewritten = Vec::new();
+        rewrite_cpio(
+            &mut archive.as_slice(),
+            &mut rewritten,
+            &mut |header| {
+                Ok(match header.filename.as_str() {
+                    "path/file" => Edit::Replace(b"replaced"),
+                    _ => Edit::Keep,
+                })
+            },
+            0,
+            None,
+        )
+        .unwrap();
+        let mut reader = rewritten.as_slice();
+        let header = crate::find_entry(&mut reader, "path/file")
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.filesize, 8);
+        assert_eq!(&reader[..8], b"replaced");
+    }
+
+    #[test]
+    fn test_rewrite_cpio_data_align() {
+        let archive = std::fs::read("tests/single.cpio").unwrap();
+        let mut rewritten = Vec::new();
+        let size = rewrite_cpio(
+            &mut archive.as_slice(),
+            &mut rewritten,
+            &mut |_| Ok(Edit::Keep),
+            0,
+            Some(&DataAlign::new(512, 0, &[], MatchMode::Glob).unwrap()),
+        )
+        .unwrap();
+        assert_eq!(size, u64::try_from(rewritten.len()).unwrap());
+        let mut reader = rewritten.as_slice();
+        crate::find_entry(&mut reader, "path/file")
+            .unwrap()
+            .unwrap();
+        assert_eq!((rewritten.len() - reader.len()) % 512, 0);
+        assert_eq!(&reader[..8], b"content\n");
+    }
+
+    #[test]
+    fn test_data_align_selection() {
+        let data_align = DataAlign::new(4096, 1024, &["*.ko".into()], MatchMode::Glob).unwrap();
+        let mut header = Header::new(1, 0o100_644, 0, 0, 1, 0, 2048, "lib/module.ko".into());
+        assert_eq!(data_align.alignment(&header), Some(4096));
+        header.filesize = 512;
+        assert_eq!(data_align.alignment(&header), None);
+        header.filesize = 2048;
+        header.filename = "lib/firmware.bin".into();
+        assert_eq!(data_align.alignment(&header), None);
+    }
+
+    #[test]
+    fn test_data_align_invalid() {
+        let got = DataAlign::new(6, 0, &[], MatchMode::Glob).unwrap_err();
+        assert_eq!(got.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_renamed() {
+        assert_eq!(
+            renamed("./usr/lib", "usr/lib", "usr/lib64"),
+            Some("./usr/lib64".into())
+        );
+        assert_eq!(
+            renamed("usr/lib/libc.so.6", "/usr/lib/", "usr/lib64"),
+            Some("usr/lib64/libc.so.6".into())
+        );
+        assert_eq!(renamed("usr/lib64", "usr/lib", "usr/lib32"), None);
+        assert_eq!(renamed("usr", "usr/lib", "usr/lib32"), None);
+    }
+}
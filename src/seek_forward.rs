@@ -2,7 +2,8 @@
 // SPDX-License-Identifier: ISC
 
 use std::fs::File;
-use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::io::StdinLock;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Seek, SeekFrom};
 use std::process::ChildStdout;
 
 const PIPE_SIZE: usize = 65536;
@@ -19,6 +20,33 @@ pub trait SeekForward {
     fn seek_forward(&mut self, offset: u64) -> Result<()>;
 }
 
+/// Skip `offset` bytes by reading and discarding them.
+///
+/// Only a fixed-size buffer is used, so the memory usage does not depend on
+/// the number of skipped bytes. This is meant for streams that cannot seek
+/// (like pipes).
+pub fn skip_by_reading<R: Read>(reader: &mut R, offset: u64) -> Result<()> {
+    let mut buffer = [0; PIPE_SIZE];
+    let mut remaining = offset;
+    while remaining > 0 {
+        let length = usize::try_from(remaining).map_or(PIPE_SIZE, |r| r.min(PIPE_SIZE));
+        let read = reader.read(&mut buffer[..length])?;
+        if read == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "read only {} bytes, but {} wanted ({} bytes missing)",
+                    offset - remaining,
+                    offset,
+                    remaining
+                ),
+            ));
+        }
+        remaining -= u64::try_from(read).unwrap();
+    }
+    Ok(())
+}
+
 impl SeekForward for File {
     fn seek_forward(&mut self, offset: u64) -> Result<()> {
         self.seek(SeekFrom::Current(offset.try_into().unwrap()))?;
@@ -26,30 +54,115 @@ impl SeekForward for File {
     }
 }
 
+impl<R: Read + SeekForward> SeekForward for BufReader<R> {
+    fn seek_forward(&mut self, offset: u64) -> Result<()> {
+        let buffered = self.buffer().len();
+        if offset <= buffered.try_into().unwrap() {
+            self.consume(offset.try_into().unwrap());
+            return Ok(());
+        }
+        self.consume(buffered);
+        self.get_mut()
+            .seek_forward(offset - u64::try_from(buffered).unwrap())
+    }
+}
+
 impl SeekForward for ChildStdout {
     fn seek_forward(&mut self, offset: u64) -> Result<()> {
-        let mut seek_reader = self.take(offset);
-        let mut remaining: usize = offset.try_into().unwrap();
-        let mut buffer = [0; PIPE_SIZE];
-        while remaining > 0 {
-            let read = seek_reader.read(&mut buffer)?;
-            remaining -= read;
+        skip_by_reading(self, offset)
+    }
+}
+
+impl SeekForward for StdinLock<'_> {
+    fn seek_forward(&mut self, offset: u64) -> Result<()> {
+        let buffered = self.fill_buf()?.len();
+        if offset <= buffered.try_into().unwrap() {
+            self.consume(offset.try_into().unwrap());
+            return Ok(());
         }
-        Ok(())
+        self.consume(buffered);
+        skip_by_reading(self, offset - u64::try_from(buffered).unwrap())
     }
 }
 
 impl SeekForward for &[u8] {
     fn seek_forward(&mut self, offset: u64) -> Result<()> {
-        let mut seek_reader = std::io::Read::take(self, offset);
-        let mut buffer = Vec::new();
-        let read = seek_reader.read_to_end(&mut buffer)?;
-        if read < offset.try_into().unwrap() {
-            return Err(Error::new(
-                ErrorKind::UnexpectedEof,
-                format!("read only {} bytes, but {} wanted", read, offset),
-            ));
+        match usize::try_from(offset) {
+            Ok(offset) if offset <= self.len() => {
+                *self = &self[offset..];
+                Ok(())
+            }
+            _ => {
+                let read = self.len();
+                *self = &self[read..];
+                Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("read only {} bytes, but {} wanted", read, offset),
+                ))
+            }
         }
-        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seek_forward_buf_reader() {
+        let data = b"0123456789abcdef";
+        let mut reader = BufReader::with_capacity(4, data.as_ref());
+        let mut buffer = [0; 2];
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"01");
+        // Within the buffer
+        reader.seek_forward(1).unwrap();
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"34");
+        // Beyond the buffer
+        reader.seek_forward(7).unwrap();
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"cd");
+    }
+
+    #[test]
+    fn test_seek_forward_slice_beyond_end() {
+        let mut data = b"0123".as_ref();
+        let error = data.seek_forward(6).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(error.to_string(), "read only 4 bytes, but 6 wanted");
+    }
+
+    #[test]
+    fn test_seek_forward_child_stdout_early_eof() {
+        let mut child = std::process::Command::new("printf")
+            .arg("abc")
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+        let error = stdout.seek_forward(10).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(
+            error.to_string(),
+            "read only 3 bytes, but 10 wanted (7 bytes missing)"
+        );
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_skip_by_reading() {
+        let data = vec![7; 3 * PIPE_SIZE];
+        let mut reader = data.as_slice().chain(b"end".as_ref());
+        skip_by_reading(&mut reader, u64::try_from(3 * PIPE_SIZE).unwrap()).unwrap();
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "end");
+        let error = skip_by_reading(&mut reader, 1).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(
+            error.to_string(),
+            "read only 0 bytes, but 1 wanted (1 bytes missing)"
+        );
     }
 }
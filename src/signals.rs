@@ -0,0 +1,50 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Handling of SIGINT and SIGTERM during extraction
+//!
+//! The signal handler only records the received signal. The extraction
+//! checks for it between the entries and cleans up before returning an
+//! error of kind [`std::io::ErrorKind::Interrupted`].
+
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+static RECEIVED_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn record_signal(signal: libc::c_int) {
+    RECEIVED_SIGNAL.store(signal, Ordering::SeqCst);
+}
+
+/// Install handlers for SIGINT and SIGTERM that interrupt the extraction.
+///
+/// Without these handlers, the process is terminated immediately and
+/// leaves a partially extracted tree behind.
+pub fn install_interrupt_handlers() -> Result<()> {
+    let handler = record_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Return the number of the received SIGINT or SIGTERM signal (if any).
+pub fn received_signal() -> Option<i32> {
+    match RECEIVED_SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// Fail with an [`ErrorKind::Interrupted`] error if a signal was received.
+pub(crate) fn check_interrupted() -> Result<()> {
+    match received_signal() {
+        None => Ok(()),
+        Some(signal) => Err(Error::new(
+            ErrorKind::Interrupted,
+            format!("Interrupted by signal {}", signal),
+        )),
+    }
+}
@@ -0,0 +1,278 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Check that the symlinks in the cpio archives point to existing entries.
+//!
+//! The symlink targets are resolved against the entries of all cpio
+//! archives (since the kernel extracts them into the same tree). Absolute
+//! targets are resolved relative to the root of the archive. Dangling
+//! symlinks are a common cause of initramfs boot failures after library
+//! path changes.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Write};
+
+use crate::header::*;
+use crate::metadata::FileType;
+use crate::seek_forward::SeekForward;
+use crate::{
+    check_decompressor_available, decompress_buffered, read_magic_header, strip_entry_name,
+    LOG_LEVEL_INFO,
+};
+
+/// Maximum number of symlinks to follow (same as the Linux kernel)
+const MAX_SYMLINKS: u32 = 40;
+
+/// Type of an entry in the archive namespace
+pub(crate) enum Kind {
+    Directory,
+    Symlink(String),
+    /// Any other file type (with the mode of the entry)
+    Other(u32),
+}
+
+/// Problem with the target of a symlink
+#[derive(Debug, PartialEq)]
+pub(crate) enum Problem {
+    Dangling,
+    OutsideArchive,
+    Loop,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Dangling => "dangling",
+            Self::OutsideArchive => "points outside the archive",
+            Self::Loop => "too many levels of symbolic links",
+        })
+    }
+}
+
+/// Entries of all cpio archives (by name without leading `./`) and the
+/// symlinks in archive order
+#[derive(Default)]
+pub(crate) struct Namespace {
+    entries: BTreeMap<String, Kind>,
+    symlinks: Vec<(String, String)>,
+}
+
+impl Namespace {
+    pub(crate) fn insert(&mut self, name: &str, kind: Kind) {
+        let name = strip_entry_name(name).trim_end_matches('/');
+        if name.is_empty() || name == "." {
+            return;
+        }
+        // The parent directories might not be stored in the archive.
+        let mut parent = name;
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            self.entries
+                .entry(dir.to_string())
+                .or_insert(Kind::Directory);
+            parent = dir;
+        }
+        self.entries.insert(name.to_string(), kind);
+    }
+
+    /// Return the entry with the given name (without leading `./`).
+    pub(crate) fn get(&self, name: &str) -> Option<&Kind> {
+        self.entries.get(name)
+    }
+
+    /// Resolve the target of the symlink `name` within the archive.
+    ///
+    /// Return the resolved path (empty for the root directory).
+    pub(crate) fn resolve(&self, name: &str, target: &str) -> std::result::Result<String, Problem> {
+        let name = strip_entry_name(name).trim_end_matches('/');
+        let mut path: Vec<&str> = name.split('/').collect();
+        path.pop();
+        if target.starts_with('/') {
+            path.clear();
+        }
+        let mut pending: VecDeque<&str> = target.split('/').collect();
+        let mut followed = 0;
+        while let Some(component) = pending.pop_front() {
+            match component {
+                "" | "." => continue,
+                ".." => {
+                    if path.pop().is_none() {
+                        return Err(Problem::OutsideArchive);
+                    }
+                    continue;
+                }
+                _ => path.push(component),
+            }
+            match self.entries.get(&path.join("/")) {
+                None => return Err(Problem::Dangling),
+                Some(Kind::Directory) => {}
+                Some(Kind::Other(_)) if pending.is_empty() => {}
+                Some(Kind::Other(_)) => return Err(Problem::Dangling),
+                Some(Kind::Symlink(link_target)) => {
+                    followed += 1;
+                    if followed > MAX_SYMLINKS {
+                        return Err(Problem::Loop);
+                    }
+                    path.pop();
+                    if link_target.starts_with('/') {
+                        path.clear();
+                    }
+                    for component in link_target.split('/').rev() {
+                        pending.push_front(component);
+                    }
+                }
+            }
+        }
+        Ok(path.join("/"))
+    }
+}
+
+pub(crate) fn read_cpio_into_namespace<R: Read + SeekForward>(
+    file: &mut R,
+    namespace: &mut Namespace,
+) -> Result<()> {
+    loop {
+        let header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            break;
+        }
+        let kind = match header.file_type() {
+            Some(FileType::Directory) => Kind::Directory,
+            Some(FileType::Symlink) => {
+                let target = header.read_symlink_target(file)?;
+                namespace
+                    .symlinks
+                    .push((header.filename.clone(), target.clone()));
+                namespace.insert(&header.filename, Kind::Symlink(target));
+                continue;
+            }
+            _ => Kind::Other(header.mode),
+        };
+        header.skip_file_content(file)?;
+        namespace.insert(&header.filename, kind);
+    }
+    Ok(())
+}
+
+/// Check that the targets of all symlinks exist in the cpio archives.
+///
+/// Each dangling symlink or symlink pointing outside of the archive is
+/// reported to `out` (and each resolvable symlink as well if the log level
+/// is info or higher). Fail if at least one symlink is broken.
+pub fn check_symlinks<W: Write>(
+    archive: File,
+    out: &mut W,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let mut file = BufReader::with_capacity(io_buffer_size, archive);
+    check_decompressor_available(&mut file)?;
+    let mut namespace = Namespace::default();
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if compression.is_uncompressed() {
+            read_cpio_into_namespace(&mut file, &mut namespace)?;
+        } else {
+            let mut decompressed =
+                decompress_buffered(compression, file, io_buffer_size, log_level)?;
+            read_cpio_into_namespace(&mut decompressed, &mut namespace)?;
+            decompressed.finish()?;
+            break;
+        }
+    }
+    let mut broken = 0;
+    for (name, target) in &namespace.symlinks {
+        match namespace.resolve(name, target) {
+            Ok(_) => {
+                if log_level >= LOG_LEVEL_INFO {
+                    writeln!(out, "{} -> {}: OK", name, target)?;
+                }
+            }
+            Err(problem) => {
+                broken += 1;
+                writeln!(out, "{} -> {}: {}", name, target, problem)?;
+            }
+        }
+    }
+    if broken > 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} of {} symlinks are broken.",
+                broken,
+                namespace.symlinks.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn namespace() -> Namespace {
+        let mut namespace = Namespace::default();
+        namespace.insert(".", Kind::Directory);
+        namespace.insert("bin", Kind::Symlink("usr/bin".into()));
+        namespace.insert("lib", Kind::Symlink("/usr/lib".into()));
+        namespace.insert("usr/bin/sh", Kind::Other(0o100_755));
+        namespace.insert("usr/lib/libc.so.6", Kind::Other(0o100_644));
+        namespace.insert("loop", Kind::Symlink("loop".into()));
+        namespace
+    }
+
+    #[test]
+    fn test_resolve_relative() {
+        let namespace = namespace();
+        assert_eq!(
+            namespace.resolve("./usr/bin/bash", "sh"),
+            Ok("usr/bin/sh".into())
+        );
+        assert_eq!(
+            namespace.resolve("usr/bin/ldd", "../lib/libc.so.6"),
+            Ok("usr/lib/libc.so.6".into())
+        );
+    }
+
+    #[test]
+    fn test_resolve_through_symlinks() {
+        let namespace = namespace();
+        assert_eq!(namespace.resolve("init", "bin/sh"), Ok("usr/bin/sh".into()));
+        assert_eq!(
+            namespace.resolve("usr/sbin/init", "/lib/libc.so.6"),
+            Ok("usr/lib/libc.so.6".into())
+        );
+        assert_eq!(
+            namespace.resolve("usr/bin/sh2", "../../bin/sh/"),
+            Err(Problem::Dangling)
+        );
+    }
+
+    #[test]
+    fn test_resolve_dangling() {
+        let namespace = namespace();
+        assert_eq!(
+            namespace.resolve("usr/bin/gcc", "/lib/libgcc_s.so.1"),
+            Err(Problem::Dangling)
+        );
+    }
+
+    #[test]
+    fn test_resolve_outside_archive() {
+        let namespace = namespace();
+        assert_eq!(
+            namespace.resolve("escape", "../../etc"),
+            Err(Problem::OutsideArchive)
+        );
+    }
+
+    #[test]
+    fn test_resolve_loop() {
+        let namespace = namespace();
+        assert_eq!(namespace.resolve("loop", "loop"), Err(Problem::Loop));
+    }
+}
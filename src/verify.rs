@@ -0,0 +1,288 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Verify the checksums of the entries in the cpio archives.
+//!
+//! The crc format (magic number 070702) stores the sum of all bytes of the
+//! file data (modulo 2^32) in the check field of each header.
+//!
+//! Archives in any format can carry a `.checksums` entry with the SHA-256
+//! hashes of the regular files in the output format of `sha256sum` (one
+//! `HASH  NAME` line per file). It detects tampering of individual files,
+//! which the simple crc checksum cannot do reliably.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Write};
+
+use crate::header::*;
+use crate::metadata::FileType;
+use crate::objects::HashingWriter;
+use crate::seek_forward::SeekForward;
+use crate::{
+    align_to_4_bytes, check_decompressor_available, decompress_buffered, read_magic_header,
+    strip_entry_name, LOG_LEVEL_INFO, LOG_LEVEL_WARNING,
+};
+
+/// Name of the entry with the SHA-256 hashes of the regular files
+const CHECKSUMS_ENTRY: &str = ".checksums";
+
+/// Reader that sums up all read bytes (like the crc format checksum).
+pub(crate) struct ChecksumReader<R> {
+    inner: R,
+    pub(crate) checksum: u32,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self { inner, checksum: 0 }
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.inner.read(buf)?;
+        for byte in &buf[..read] {
+            self.checksum = self.checksum.wrapping_add((*byte).into());
+        }
+        Ok(read)
+    }
+}
+
+/// Check the checksum of the file data against the header (if it has one).
+pub(crate) fn check_checksum(header: &Header, checksum: u32) -> Result<()> {
+    match header.check {
+        Some(expected) if expected != checksum => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Checksum mismatch for '{}': expected {:08X}, got {:08X}.",
+                header.filename, expected, checksum
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Number of verified and failed entries
+#[derive(Default)]
+struct Verification {
+    verified: u64,
+    failed: u64,
+    /// SHA-256 hashes of the regular files (by name without leading `./`)
+    hashes: BTreeMap<String, String>,
+    /// Content of the `.checksums` entry
+    checksums: Option<String>,
+}
+
+impl Verification {
+    fn report<W: Write>(
+        &mut self,
+        out: &mut W,
+        name: &str,
+        error: Option<String>,
+        log_level: u32,
+    ) -> Result<()> {
+        self.verified += 1;
+        match error {
+            Some(error) => {
+                self.failed += 1;
+                writeln!(out, "{}: FAILED ({})", name, error)
+            }
+            None if log_level >= LOG_LEVEL_INFO => writeln!(out, "{}: OK", name),
+            None => Ok(()),
+        }
+    }
+
+    /// Compare the hashes of the files with the `.checksums` entry.
+    fn verify_checksums_entry<W: Write>(&mut self, out: &mut W, log_level: u32) -> Result<()> {
+        let Some(checksums) = self.checksums.take() else {
+            return Ok(());
+        };
+        for line in checksums.lines() {
+            let Some((expected, name)) = line.split_once("  ") else {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid line in {}: '{}'", CHECKSUMS_ENTRY, line),
+                ));
+            };
+            let error = match self.hashes.get(strip_entry_name(name)) {
+                None => Some("missing in the archive".to_string()),
+                Some(hash) if hash != expected => Some("SHA-256 hash mismatch".to_string()),
+                Some(_) => None,
+            };
+            self.report(out, name, error, log_level)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_cpio_and_verify<R: Read + SeekForward, W: Write>(
+    file: &mut R,
+    out: &mut W,
+    log_level: u32,
+    verification: &mut Verification,
+) -> Result<()> {
+    loop {
+        let header = Header::read(file)?;
+        if header.filename == "TRAILER!!!" {
+            break;
+        }
+        // The data of hard-linked files is stored with only one of the links.
+        if header.file_type() != Some(FileType::RegularFile)
+            || (header.nlink > 1 && header.filesize == 0)
+        {
+            header.skip_file_content(file)?;
+            continue;
+        }
+        let filesize = header.filesize.into();
+        let mut reader = ChecksumReader::new(file.take(filesize));
+        let name = strip_entry_name(&header.filename);
+        let read = if name == CHECKSUMS_ENTRY {
+            let mut checksums = String::new();
+            let read = reader.read_to_string(&mut checksums)?;
+            verification.checksums = Some(checksums);
+            read.try_into().unwrap()
+        } else {
+            let mut writer = HashingWriter::new(std::io::sink());
+            let read = std::io::copy(&mut reader, &mut writer)?;
+            verification
+                .hashes
+                .insert(name.to_string(), writer.finish()?);
+            read
+        };
+        let checksum = reader.checksum;
+        if read != filesize {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Content of '{}' is truncated.", header.filename),
+            ));
+        }
+        file.seek_forward(align_to_4_bytes(header.filesize).into())?;
+        if header.check.is_some() {
+            let error = check_checksum(&header, checksum).err();
+            verification.report(
+                out,
+                &header.filename,
+                error.map(|e| e.to_string()),
+                log_level,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Verify the checksums of the regular files in all cpio archives.
+///
+/// Verify the checksums of the cpio archives in the crc format (070702) and
+/// the SHA-256 hashes listed in a `.checksums` entry (if present). Each
+/// mismatching entry is reported by name to `out` (and each matching entry
+/// as well if the log level is info or higher). Fail if at least one
+/// checksum does not match.
+pub fn verify_cpio_content<W: Write>(
+    archive: File,
+    out: &mut W,
+    log_level: u32,
+    io_buffer_size: usize,
+) -> Result<()> {
+    let mut file = BufReader::with_capacity(io_buffer_size, archive);
+    check_decompressor_available(&mut file)?;
+    let mut verification = Verification::default();
+    loop {
+        let compression = match read_magic_header(&mut file) {
+            None => break,
+            Some(x) => x?,
+        };
+        if compression.is_uncompressed() {
+            read_cpio_and_verify(&mut file, out, log_level, &mut verification)?;
+        } else {
+            let mut decompressed =
+                decompress_buffered(compression, file, io_buffer_size, log_level)?;
+            read_cpio_and_verify(&mut decompressed, out, log_level, &mut verification)?;
+            decompressed.finish()?;
+            break;
+        }
+    }
+    verification.verify_checksums_entry(out, log_level)?;
+    if verification.verified == 0 && log_level >= LOG_LEVEL_WARNING {
+        writeln!(
+            std::io::stderr(),
+            "Warning: Nothing to verify. The cpio archives are not in the crc format \
+             and contain no {} entry.",
+            CHECKSUMS_ENTRY
+        )?;
+    }
+    if verification.failed > 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} of {} checksums did not match.",
+                verification.failed, verification.verified
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_cpio_and_verify() {
+        let cpio_data = b"07070200000002000081B4000003E8000007D000000001\
+            661BE5C600000008000000000000000000000000000000000000000A00000305\
+            path/file\0content\n\
+            07070200000003000081B4000003E8000007D000000001\
+            661BE5C600000008000000000000000000000000000000000000000A00000306\
+            path/fake\0content\n\
+            0707020000000000000000000000000000000000000001000000000000000000\
+            0000000000000000000000000000000000000B00000000TRAILER!!!\0\0\0\0";
+        let mut output = Vec::new();
+        let mut verification = Verification::default();
+        read_cpio_and_verify(
+            &mut cpio_data.as_ref(),
+            &mut output,
+            LOG_LEVEL_INFO,
+            &mut verification,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "path/file: OK\n\
+             path/fake: FAILED (Checksum mismatch for 'path/fake': expected 00000306, got 00000305.)\n"
+        );
+        assert_eq!(verification.verified, 2);
+        assert_eq!(verification.failed, 1);
+    }
+
+    #[test]
+    fn test_verify_checksums_entry() {
+        let mut verification = Verification::default();
+        verification.hashes.insert(
+            "path/file".into(),
+            "434728a410a78f56fc1b5899c3593436e61ab0c731e9072d95e96db290205e53".into(),
+        );
+        verification.hashes.insert(
+            "path/fake".into(),
+            "92e78d0b032962f47792a9fa95fd981ef63e1e3ef074d536d6304c75eddbe29f".into(),
+        );
+        verification.checksums = Some(
+            "434728a410a78f56fc1b5899c3593436e61ab0c731e9072d95e96db290205e53  ./path/file\n\
+             434728a410a78f56fc1b5899c3593436e61ab0c731e9072d95e96db290205e53  path/fake\n\
+             434728a410a78f56fc1b5899c3593436e61ab0c731e9072d95e96db290205e53  path/gone\n"
+                .into(),
+        );
+        let mut output = Vec::new();
+        verification
+            .verify_checksums_entry(&mut output, LOG_LEVEL_INFO)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "./path/file: OK\n\
+             path/fake: FAILED (SHA-256 hash mismatch)\n\
+             path/gone: FAILED (missing in the archive)\n"
+        );
+        assert_eq!(verification.verified, 3);
+        assert_eq!(verification.failed, 2);
+    }
+}
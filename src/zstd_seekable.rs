@@ -0,0 +1,277 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Random access into zstd compressed cpio archives using the seek table of
+//! the zstd seekable format.
+//!
+//! The seekable format splits the content into independently compressed
+//! frames and appends a seek table in a skippable frame at the end:
+//!
+//! ```text
+//! Skippable_Magic_Number (4 bytes, 0x184D2A5E), Frame_Size (4 bytes)
+//! Seek_Table_Entries: Compressed_Size (4 bytes), Decompressed_Size (4 bytes),
+//!                     [Checksum (4 bytes)]
+//! Seek_Table_Footer: Number_Of_Frames (4 bytes), Seek_Table_Descriptor (1 byte),
+//!                    Seekable_Magic_Number (4 bytes, 0x8F92EAB1)
+//! ```
+//!
+//! All numbers are little endian. The kernel decompresses such files like
+//! any other zstd file, because it ignores skippable frames.
+
+use std::fs::File;
+use std::io::{Read, Result, Seek, SeekFrom};
+
+use crate::compression::{Compression, Decompressor};
+use crate::seek_forward::SeekForward;
+
+const SKIPPABLE_MAGIC_NUMBER: u32 = 0x184D2A5E;
+const SEEKABLE_MAGIC_NUMBER: u32 = 0x8F92EAB1;
+const SEEK_TABLE_FOOTER_LENGTH: u64 = 9;
+const SEEK_TABLE_CHECKSUM_FLAG: u8 = 0x80;
+
+/// Restart the decompression at a later frame only when skipping at least
+/// this amount of bytes. Otherwise skipping by decompressing is cheaper than
+/// spawning a new decompressor.
+const RESTART_THRESHOLD: u64 = 1024 * 1024;
+
+/// Start offsets of a frame in the compressed file and the decompressed stream
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    compressed_offset: u64,
+    decompressed_offset: u64,
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[0..4].try_into().unwrap())
+}
+
+/// Read the seek table of the zstd seekable archive starting at `start`.
+///
+/// Return `None` if the file does not end with a valid seek table.
+pub fn read_seek_table(file: &mut File, start: u64) -> Result<Option<Vec<Frame>>> {
+    let end = file.metadata()?.len();
+    if end < start + SEEK_TABLE_FOOTER_LENGTH + 8 {
+        return Ok(None);
+    }
+    let mut footer = [0; SEEK_TABLE_FOOTER_LENGTH as usize];
+    file.seek(SeekFrom::Start(end - SEEK_TABLE_FOOTER_LENGTH))?;
+    file.read_exact(&mut footer)?;
+    if u32_le(&footer[5..9]) != SEEKABLE_MAGIC_NUMBER {
+        return Ok(None);
+    }
+    let number_of_frames = u64::from(u32_le(&footer[0..4]));
+    let entry_length = if footer[4] & SEEK_TABLE_CHECKSUM_FLAG == 0 {
+        8
+    } else {
+        12
+    };
+    let table_length = number_of_frames * entry_length + SEEK_TABLE_FOOTER_LENGTH;
+    if end < start + table_length + 8 {
+        return Ok(None);
+    }
+    let skippable_start = end - table_length - 8;
+    let mut table = vec![0; (table_length + 8).try_into().unwrap()];
+    file.seek(SeekFrom::Start(skippable_start))?;
+    file.read_exact(&mut table)?;
+    if u32_le(&table[0..4]) != SKIPPABLE_MAGIC_NUMBER
+        || u64::from(u32_le(&table[4..8])) != table_length
+    {
+        return Ok(None);
+    }
+
+    let mut frames = Vec::new();
+    let mut compressed_offset = start;
+    let mut decompressed_offset = 0;
+    for entry in table[8..].chunks_exact(entry_length.try_into().unwrap()) {
+        if frames.len() as u64 == number_of_frames {
+            break;
+        }
+        frames.push(Frame {
+            compressed_offset,
+            decompressed_offset,
+        });
+        compressed_offset += u64::from(u32_le(&entry[0..4]));
+        decompressed_offset += u64::from(u32_le(&entry[4..8]));
+    }
+    if frames.is_empty() || compressed_offset != skippable_start {
+        return Ok(None);
+    }
+    Ok(Some(frames))
+}
+
+/// Reader for the decompressed content of a zstd seekable archive.
+///
+/// Seeking forward over complete frames restarts the decompression at the
+/// frame containing the target position instead of decompressing the
+/// skipped frames.
+pub struct SeekableZstdReader {
+    file: File,
+    frames: Vec<Frame>,
+    position: u64,
+    log_level: u32,
+    decompressor: Decompressor,
+}
+
+impl SeekableZstdReader {
+    pub fn new(file: File, frames: Vec<Frame>, log_level: u32) -> Result<Self> {
+        let decompressor = spawn_zstd(&file, frames[0].compressed_offset, log_level)?;
+        Ok(Self {
+            file,
+            frames,
+            position: 0,
+            log_level,
+            decompressor,
+        })
+    }
+
+    fn restart_at_frame(&mut self, index: usize) -> Result<()> {
+        let frame = &self.frames[index];
+        // Replacing the decompressor terminates the previous one.
+        self.decompressor = spawn_zstd(&self.file, frame.compressed_offset, self.log_level)?;
+        self.position = frame.decompressed_offset;
+        Ok(())
+    }
+}
+
+/// Spawn zstd (with the configured decompression settings) reading `file`
+/// from `offset`.
+fn spawn_zstd(file: &File, offset: u64, log_level: u32) -> Result<Decompressor> {
+    let mut stdin = file.try_clone()?;
+    stdin.seek(SeekFrom::Start(offset))?;
+    Compression::Zstd.decompress(stdin, log_level)
+}
+
+impl Read for SeekableZstdReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.decompressor.read(buf)?;
+        self.position += u64::try_from(read).unwrap();
+        Ok(read)
+    }
+}
+
+impl SeekForward for SeekableZstdReader {
+    fn seek_forward(&mut self, offset: u64) -> Result<()> {
+        let target = self.position + offset;
+        let index = self
+            .frames
+            .partition_point(|frame| frame.decompressed_offset <= target)
+            .saturating_sub(1);
+        if self.frames[index].decompressed_offset >= self.position + RESTART_THRESHOLD {
+            self.restart_at_frame(index)?;
+        }
+        let remaining = target - self.position;
+        self.decompressor.seek_forward(remaining)?;
+        self.position = target;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    /// Compress each chunk into its own zstd frame and append a seek table.
+    fn create_seekable_zstd(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut table = Vec::new();
+        for chunk in chunks {
+            let mut child = Command::new("zstd")
+                .arg("-cq")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child.stdin.take().unwrap().write_all(chunk).unwrap();
+            let frame = child.wait_with_output().unwrap().stdout;
+            table.extend_from_slice(&u32::try_from(frame.len()).unwrap().to_le_bytes());
+            table.extend_from_slice(&u32::try_from(chunk.len()).unwrap().to_le_bytes());
+            data.extend_from_slice(&frame);
+        }
+        table.extend_from_slice(&u32::try_from(chunks.len()).unwrap().to_le_bytes());
+        table.push(0);
+        table.extend_from_slice(&SEEKABLE_MAGIC_NUMBER.to_le_bytes());
+        data.extend_from_slice(&SKIPPABLE_MAGIC_NUMBER.to_le_bytes());
+        data.extend_from_slice(&u32::try_from(table.len()).unwrap().to_le_bytes());
+        data.extend_from_slice(&table);
+        data
+    }
+
+    fn write_temp_file(name: &str, data: &[u8]) -> File {
+        let path = std::env::temp_dir().join(format!("3cpio-{}-{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        let file = File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_list_seekable_zstd_cpio() {
+        let cpio = std::fs::read("tests/single.cpio").expect("test cpio should be present");
+        let data = create_seekable_zstd(&[&cpio[0..100], &cpio[100..300], &cpio[300..]]);
+        let file = write_temp_file("list.cpio", &data);
+        let mut output = Vec::new();
+        crate::list_cpio_content(
+            file,
+            &mut output,
+            &crate::ListOptions::default(),
+            crate::LOG_LEVEL_WARNING,
+            crate::DEFAULT_IO_BUFFER_SIZE,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), ".\npath\npath/file\n");
+    }
+
+    #[test]
+    fn test_read_seek_table() {
+        let data = create_seekable_zstd(&[b"first frame", b"second"]);
+        let mut file = write_temp_file("seek-table.zst", &data);
+        let frames = read_seek_table(&mut file, 0).unwrap().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].compressed_offset, 0);
+        assert_eq!(frames[0].decompressed_offset, 0);
+        assert_eq!(frames[1].decompressed_offset, 11);
+    }
+
+    #[test]
+    fn test_read_seek_table_not_seekable() {
+        let mut file = File::open("tests/zstd.cpio").expect("test cpio should be present");
+        assert_eq!(read_seek_table(&mut file, 512).unwrap(), None);
+    }
+
+    #[test]
+    fn test_seekable_zstd_reader_corrupt_frame() {
+        let mut data = create_seekable_zstd(&[b"first frame", b"second frame"]);
+        let mut file = write_temp_file("corrupt.zst", &data);
+        let frames = read_seek_table(&mut file, 0).unwrap().unwrap();
+        // Corrupt the content checksum at the end of the first frame.
+        let checksum = usize::try_from(frames[1].compressed_offset).unwrap() - 1;
+        data[checksum] ^= 0xFF;
+        let file = write_temp_file("corrupt.zst", &data);
+        let mut reader =
+            SeekableZstdReader::new(file, frames, crate::LOG_LEVEL_WARNING - 1).unwrap();
+        let got = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(
+            got.to_string().starts_with("Program 'zstd' failed"),
+            "{}",
+            got
+        );
+    }
+
+    #[test]
+    fn test_seekable_zstd_reader_seek_forward() {
+        let first = vec![b'a'; 2 * RESTART_THRESHOLD as usize];
+        let second = vec![b'b'; 16];
+        let data = create_seekable_zstd(&[&first, &second]);
+        let mut file = write_temp_file("seek-forward.zst", &data);
+        let frames = read_seek_table(&mut file, 0).unwrap().unwrap();
+        let mut reader = SeekableZstdReader::new(file, frames, crate::LOG_LEVEL_WARNING).unwrap();
+        let mut buffer = [0; 4];
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"aaaa");
+        reader.seek_forward(first.len() as u64 - 2).unwrap();
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"bbbb");
+    }
+}
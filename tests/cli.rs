@@ -5,6 +5,12 @@ use std::env;
 use std::error::Error;
 use std::process::{Command, Output};
 
+use proptest::prelude::*;
+
+mod fixtures;
+
+use fixtures::{cpio_with_format, Entry, Format};
+
 // Derive target directory (e.g. `target/debug`) from current executable
 fn get_target_dir() -> std::path::PathBuf {
     let mut path = env::current_exe().expect("env::current_exe not set");
@@ -63,6 +69,7 @@ where
 
 trait OutputContainsAssertion {
     fn assert_stderr_contains(self, expected: &str) -> Self;
+    fn assert_stdout_contains(self, expected: &str) -> Self;
 }
 
 impl OutputContainsAssertion for Output {
@@ -76,6 +83,260 @@ impl OutputContainsAssertion for Output {
         );
         self
     }
+
+    fn assert_stdout_contains(self, expected: &str) -> Self {
+        let stdout = String::from_utf8(self.stdout.clone()).expect("stdout");
+        assert!(
+            stdout.contains(expected),
+            "'{}' not found in '{}'",
+            expected,
+            stdout
+        );
+        self
+    }
+}
+
+#[test]
+fn cat_compressed_not_last() -> Result<(), Box<dyn Error>> {
+    let mut cmd = get_command();
+    cmd.args(["--cat", "tests/gzip.cpio", "tests/single.cpio"]);
+
+    cmd.output()?
+        .assert_failure(1)
+        .assert_stderr_contains("Only the last file can end with a compressed cpio archive")
+        .assert_stdout("");
+    Ok(())
+}
+
+#[test]
+fn cat_output_is_input() -> Result<(), Box<dyn Error>> {
+    let path = fixtures::write_temp("cat-output.cpio", &fixtures::single());
+    let mut cmd = get_command();
+    cmd.arg("--cat")
+        .arg("-o")
+        .arg(&path)
+        .arg(&path)
+        .arg("tests/single.cpio");
+
+    let output = cmd.output()?;
+    let content = std::fs::read(&path)?;
+    std::fs::remove_file(path)?;
+    output
+        .assert_failure(1)
+        .assert_stderr_contains("is the same file as the input")
+        .assert_stdout("");
+    assert_eq!(content, fixtures::single());
+    Ok(())
+}
+
+#[test]
+fn check_boot_single_cpio() -> Result<(), Box<dyn Error>> {
+    let mut cmd = get_command();
+    cmd.args(["--check-boot", "tests/single.cpio"]);
+
+    cmd.output()?
+        .assert_failure(1)
+        .assert_stderr_contains("4 of 4 boot checks failed.")
+        .assert_stdout(
+            "/init: FAILED (missing)\n\
+             /dev: FAILED (missing)\n\
+             /proc: FAILED (missing)\n\
+             /dev/console: FAILED (missing)\n",
+        );
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn check_libraries_missing() -> Result<(), Box<dyn Error>> {
+    // The 3cpio executable is dynamically linked against the C library.
+    let executable = std::fs::read(get_target_dir().join("3cpio"))?;
+    let entries = [Entry::dir("."), Entry::file("usr/bin/3cpio", &executable)];
+    let path = fixtures::write_temp("check-libraries.cpio", &fixtures::cpio(&entries));
+    let mut cmd = get_command();
+    cmd.arg("--check-libraries").arg(&path);
+
+    let output = cmd.output()?;
+    std::fs::remove_file(path)?;
+    output
+        .assert_failure(1)
+        .assert_stderr_contains("1 of 1 dynamically linked ELF files have missing libraries.")
+        .assert_stdout_contains("usr/bin/3cpio: libc.so.6 not found\n");
+    Ok(())
+}
+
+#[test]
+fn check_symlinks_broken() -> Result<(), Box<dyn Error>> {
+    let entries = [
+        Entry::dir("."),
+        Entry::symlink("bin", "usr/bin"),
+        Entry::file("usr/bin/sh", b"#!/bin/false\n"),
+        Entry::symlink("init", "/bin/sh"),
+        Entry::symlink("usr/bin/gcc", "../lib/libgcc_s.so.1"),
+    ];
+    let mut archive = fixtures::cpio(&entries);
+    archive.extend(fixtures::path_traversal());
+    let path = fixtures::write_temp("check-symlinks.cpio", &archive);
+    let mut cmd = get_command();
+    cmd.arg("--check-symlinks").arg("-v").arg(&path);
+
+    let output = cmd.output()?;
+    std::fs::remove_file(path)?;
+    output
+        .assert_failure(1)
+        .assert_stderr_contains("2 of 4 symlinks are broken.")
+        .assert_stdout(
+            "bin -> usr/bin: OK\n\
+             init -> /bin/sh: OK\n\
+             usr/bin/gcc -> ../lib/libgcc_s.so.1: dangling\n\
+             escape -> ../../etc: points outside the archive\n",
+        );
+    Ok(())
+}
+
+#[test]
+fn count_generated_zstd_cpio() -> Result<(), Box<dyn Error>> {
+    let mut archive = fixtures::single();
+    archive.extend(fixtures::compress(
+        &["zstd", "-q", "-9"],
+        &fixtures::bigdata(),
+    ));
+    let path = fixtures::write_temp("count.cpio", &archive);
+    let mut cmd = get_command();
+    cmd.arg("--count").arg("-v").arg(&path);
+
+    let output = cmd.output()?;
+    std::fs::remove_file(path)?;
+    output
+        .assert_stderr("")
+        .assert_success()
+        .assert_stdout("1 cpio 3 entries\n2 zstd 2 entries\n5\n");
+    Ok(())
+}
+
+#[test]
+fn count_segments_lzop_cpio() -> Result<(), Box<dyn Error>> {
+    // Counting the cpio archives does not need the lzop program.
+    let mut cmd = get_command();
+    cmd.arg("--count")
+        .arg("--segments")
+        .arg("-v")
+        .arg("tests/lzop.cpio");
+
+    cmd.output()?
+        .assert_stderr("")
+        .assert_success()
+        .assert_stdout("1 cpio\n2 lzop\n2\n");
+    Ok(())
+}
+
+#[test]
+fn decompress_memlimit_exceeded() -> Result<(), Box<dyn Error>> {
+    let mut cmd = get_command();
+    cmd.args(["--decompress-memlimit", "1000", "--count", "tests/xz.cpio"]);
+
+    cmd.output()?
+        .assert_failure(1)
+        .assert_stderr_contains("Memory usage limit reached")
+        .assert_stderr_contains("Program 'xz' failed (exit status: 1)")
+        .assert_stdout("");
+    Ok(())
+}
+
+#[test]
+fn delete_entries_in_place() -> Result<(), Box<dyn Error>> {
+    let path = fixtures::write_temp("delete.cpio", &fixtures::single());
+    let mut cmd = get_command();
+    cmd.args(["--delete", "path/*"]).arg(&path);
+    cmd.output()?.assert_stderr("").assert_success();
+
+    let mut cmd = get_command();
+    cmd.arg("-t").arg(&path);
+    let output = cmd.output()?;
+    std::fs::remove_file(path)?;
+    output.assert_success().assert_stdout(".\npath\n");
+    Ok(())
+}
+
+#[test]
+fn delete_entries_no_match() -> Result<(), Box<dyn Error>> {
+    let archive = std::fs::read("tests/single.cpio")?;
+    let path = fixtures::write_temp("delete-no-match.cpio", &archive);
+    let mut cmd = get_command();
+    cmd.args(["--delete", "nonexistent"]).arg(&path);
+    let output = cmd.output()?;
+    let rewritten = std::fs::read(&path)?;
+    std::fs::remove_file(path)?;
+    output
+        .assert_stderr("Warning: No entry matches the patterns. Leaving the archive unchanged.\n")
+        .assert_success();
+    assert_eq!(rewritten, archive);
+    Ok(())
+}
+
+#[test]
+fn delete_entries_output_is_input() -> Result<(), Box<dyn Error>> {
+    let path = fixtures::write_temp("delete-output.cpio", &fixtures::single());
+    let output = path
+        .parent()
+        .unwrap()
+        .join(".")
+        .join(path.file_name().unwrap());
+    let mut cmd = get_command();
+    cmd.args(["--delete", "path/*", "-o"])
+        .arg(&output)
+        .arg(&path);
+    cmd.output()?.assert_stderr("").assert_success();
+
+    let mut cmd = get_command();
+    cmd.arg("-t").arg(&path);
+    let output = cmd.output()?;
+    std::fs::remove_file(path)?;
+    output.assert_success().assert_stdout(".\npath\n");
+    Ok(())
+}
+
+#[test]
+fn diff_manifest_single_cpio() -> Result<(), Box<dyn Error>> {
+    let manifest = fixtures::write_temp(
+        "diff-manifest.list",
+        b"dir /path 0775 1000 1000
+\
+          file /path/file /tmp/file 0644 0 0
+\
+          nod /dev/console 0600 0 0 c 5 1
+",
+    );
+    let mut cmd = get_command();
+    cmd.arg("--diff-manifest")
+        .arg(&manifest)
+        .arg("tests/single.cpio");
+
+    let output = cmd.output()?;
+    std::fs::remove_file(manifest)?;
+    output
+        .assert_failure(1)
+        .assert_stderr_contains("4 differences between the archive and the manifest found.")
+        .assert_stdout(
+            "path/file: mode 0664 in the archive, 0644 in the manifest\n\
+             path/file: uid 1000 in the archive, 0 in the manifest\n\
+             path/file: gid 1000 in the archive, 0 in the manifest\n\
+             dev/console: only in the manifest\n",
+        );
+    Ok(())
+}
+
+#[test]
+fn dump_completions_bash() -> Result<(), Box<dyn Error>> {
+    let mut cmd = get_command();
+    cmd.arg("--dump-completions").arg("bash");
+
+    cmd.output()?
+        .assert_stderr("")
+        .assert_success()
+        .assert_stdout_contains("--quoting-style)\n            COMPREPLY=($(compgen -W \"literal escape c\" -- \"$cur\"))")
+        .assert_stdout_contains("complete -F _3cpio 3cpio\n");
+    Ok(())
 }
 
 #[test]
@@ -101,6 +362,82 @@ fn examine_single_cpio() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+fn examine_single_cpio_verbose() -> Result<(), Box<dyn Error>> {
+    let mut cmd = get_command();
+    cmd.arg("-e").arg("-v").arg("tests/single.cpio");
+
+    cmd.output()?
+        .assert_stderr("")
+        .assert_success()
+        .assert_stdout(
+            "0\tcpio\t3 entries\t472 overhead bytes (468 headers, 4 name padding, \
+             0 data padding, 0 alignment)\t32 trailing bytes (zero padding)\n",
+        );
+    Ok(())
+}
+
+#[test]
+fn extract_to_stdout() -> Result<(), Box<dyn Error>> {
+    let mut cmd = get_command();
+    cmd.arg("-x")
+        .arg("--to-stdout")
+        .arg("tests/single.cpio")
+        .arg("path/*");
+
+    cmd.output()?
+        .assert_stderr("")
+        .assert_success()
+        .assert_stdout("content\n");
+    Ok(())
+}
+
+#[test]
+fn extract_to_stdout_match_prefix() -> Result<(), Box<dyn Error>> {
+    let mut cmd = get_command();
+    cmd.arg("-x")
+        .arg("--to-stdout")
+        .arg("--match=prefix")
+        .arg("tests/single.cpio")
+        .arg("/path");
+
+    cmd.output()?
+        .assert_stderr("")
+        .assert_success()
+        .assert_stdout("content\n");
+    Ok(())
+}
+
+#[test]
+fn extract_to_stdout_write_names_to_closed_fd() -> Result<(), Box<dyn Error>> {
+    let mut cmd = get_command();
+    cmd.arg("-x")
+        .arg("--to-stdout")
+        .arg("--write-names-to=987")
+        .arg("tests/single.cpio");
+
+    cmd.output()?
+        .assert_failure(1)
+        .assert_stderr_contains("Failed to open file descriptor 987: Bad file descriptor")
+        .assert_stdout("");
+    Ok(())
+}
+
+#[test]
+fn extract_to_stdout_write_names_to_stdout() -> Result<(), Box<dyn Error>> {
+    let mut cmd = get_command();
+    cmd.arg("-x")
+        .arg("--to-stdout")
+        .arg("--write-names-to=1")
+        .arg("tests/single.cpio");
+
+    cmd.output()?
+        .assert_failure(2)
+        .assert_stderr_contains("--write-names-to requires a file descriptor above 2 (not 1)!")
+        .assert_stdout("");
+    Ok(())
+}
+
 #[test]
 fn file_doesnt_exist() -> Result<(), Box<dyn Error>> {
     let mut cmd = get_command();
@@ -113,6 +450,50 @@ fn file_doesnt_exist() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+fn generated_fixtures_match_test_archives() -> Result<(), Box<dyn Error>> {
+    assert_eq!(fixtures::single(), std::fs::read("tests/single.cpio")?);
+    let gzip = std::fs::read("tests/gzip.cpio")?;
+    let shell = fixtures::compress(&["gzip", "-cd"], &gzip[512..]);
+    assert_eq!(fixtures::shell(), shell);
+    Ok(())
+}
+
+#[test]
+fn list_content_corrupt_gzip() -> Result<(), Box<dyn Error>> {
+    let mut archive = fixtures::compress(&["gzip", "-n"], &fixtures::single());
+    // Corrupt the CRC32 checksum in the gzip trailer.
+    let crc = archive.len() - 8;
+    archive[crc] ^= 0xFF;
+    let path = fixtures::write_temp("corrupt-gzip.cpio", &archive);
+    let mut cmd = get_command();
+    cmd.arg("-t").arg(&path);
+
+    let output = cmd.output()?;
+    std::fs::remove_file(path)?;
+    output
+        .assert_failure(1)
+        .assert_stderr_contains("Program 'gzip' failed (exit status: 1)")
+        .assert_stdout(".\npath\npath/file\n");
+    Ok(())
+}
+
+#[test]
+fn list_content_decompressor_missing() -> Result<(), Box<dyn Error>> {
+    let mut cmd = get_command();
+    cmd.env("PATH", "/nonexistent")
+        .arg("-t")
+        .arg("tests/zstd.cpio");
+
+    cmd.output()?
+        .assert_failure(1)
+        .assert_stderr_contains(
+            "Program 'zstd' not found in PATH. Please install the 'zstd' package.",
+        )
+        .assert_stdout("");
+    Ok(())
+}
+
 #[test]
 fn list_content_compressed_cpio() -> Result<(), Box<dyn Error>> {
     for compression in ["bzip2", "gzip", "lz4", "lzma", "lzop", "xz", "zstd"] {
@@ -127,6 +508,61 @@ fn list_content_compressed_cpio() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+fn list_content_match_exact() -> Result<(), Box<dyn Error>> {
+    let mut cmd = get_command();
+    cmd.arg("-t")
+        .arg("--match=exact")
+        .arg("tests/gzip.cpio")
+        .arg("path")
+        .arg("./usr/bin/sh");
+
+    cmd.output()?
+        .assert_stderr("")
+        .assert_success()
+        .assert_stdout("path\nusr/bin/sh\n");
+    Ok(())
+}
+
+#[test]
+fn list_content_null_terminated() -> Result<(), Box<dyn Error>> {
+    let mut cmd = get_command();
+    cmd.arg("-t").arg("-0").arg("tests/single.cpio");
+
+    cmd.output()?
+        .assert_stderr("")
+        .assert_success()
+        .assert_stdout(".\0path\0path/file\0");
+    Ok(())
+}
+
+#[test]
+fn list_content_parallel() -> Result<(), Box<dyn Error>> {
+    let mut cmd = get_command();
+    cmd.arg("-t").arg("-j").arg("2").arg("tests/gzip.cpio");
+
+    cmd.output()?
+        .assert_stderr("")
+        .assert_success()
+        .assert_stdout(".\npath\npath/file\n.\nusr\nusr/bin\nusr/bin/sh\n");
+    Ok(())
+}
+
+#[test]
+fn list_content_path_traversal() -> Result<(), Box<dyn Error>> {
+    let path = fixtures::write_temp("traversal.cpio", &fixtures::path_traversal());
+    let mut cmd = get_command();
+    cmd.arg("-t").arg(&path);
+
+    let output = cmd.output()?;
+    std::fs::remove_file(path)?;
+    output
+        .assert_stderr("")
+        .assert_success()
+        .assert_stdout(".\n../outside\nescape\nescape/passwd\n");
+    Ok(())
+}
+
 #[test]
 fn list_content_single_cpio() -> Result<(), Box<dyn Error>> {
     let mut cmd = get_command();
@@ -169,3 +605,166 @@ fn print_version() -> Result<(), Box<dyn Error>> {
     assert_eq!(matches, version);
     Ok(())
 }
+
+#[test]
+fn rename_directory() -> Result<(), Box<dyn Error>> {
+    let path = fixtures::write_temp("rename.cpio", &fixtures::single());
+    let output = fixtures::write_temp("renamed.cpio", b"");
+    let mut cmd = get_command();
+    cmd.args(["--rename", "path=dir", "-o"])
+        .arg(&output)
+        .arg(&path);
+    cmd.output()?.assert_stderr("").assert_success();
+
+    let mut cmd = get_command();
+    cmd.arg("-t").arg(&output);
+    let listing = cmd.output()?;
+    std::fs::remove_file(path)?;
+    std::fs::remove_file(output)?;
+    listing.assert_success().assert_stdout(".\ndir\ndir/file\n");
+    Ok(())
+}
+
+#[test]
+fn replace_entry_resized() -> Result<(), Box<dyn Error>> {
+    let path = fixtures::write_temp("replace.cpio", &fixtures::single());
+    let content = fixtures::write_temp("replace.txt", b"replaced content\n");
+    let mut cmd = get_command();
+    cmd.arg("--replace")
+        .arg(format!("path/file={}", content.display()))
+        .arg(&path);
+    cmd.output()?.assert_stderr("").assert_success();
+
+    let mut cmd = get_command();
+    cmd.args(["-x", "--to-stdout"]).arg(&path);
+    let output = cmd.output()?;
+    std::fs::remove_file(path)?;
+    std::fs::remove_file(content)?;
+    output.assert_success().assert_stdout("replaced content\n");
+    Ok(())
+}
+
+/// Write the tree as cpio archive, list it, and extract it (in archive
+/// order, sorted, and with --metadata-only).
+fn roundtrip(entries: &[Entry], format: Format) -> Result<(), Box<dyn Error>> {
+    let path = fixtures::write_temp("roundtrip.cpio", &cpio_with_format(entries, format));
+    get_command()
+        .arg("-t")
+        .arg(&path)
+        .output()?
+        .assert_stderr("")
+        .assert_success()
+        .assert_stdout(fixtures::names(entries));
+
+    for options in [&[][..], &["--sorted"], &["--metadata-only"]] {
+        let directory =
+            std::env::temp_dir().join(format!("3cpio-{}-roundtrip", std::process::id()));
+        // A failed case leaves its directory behind while the tree is shrunk.
+        if directory.exists() {
+            std::fs::remove_dir_all(&directory)?;
+        }
+        get_command()
+            .arg("-x")
+            .args(options)
+            .arg("-C")
+            .arg(&directory)
+            .arg(&path)
+            .output()?
+            .assert_stderr("")
+            .assert_success();
+        fixtures::assert_extracted(entries, &directory, options == ["--metadata-only"]);
+        std::fs::remove_dir_all(directory)?;
+    }
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+proptest! {
+    // Each case runs 3cpio four times.
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn roundtrip_random_trees(entries in fixtures::random_tree(), crc in any::<bool>()) {
+        let format = if crc { Format::Crc } else { Format::Newc };
+        roundtrip(&entries, format).unwrap();
+    }
+}
+
+#[test]
+fn set_mtime_matching_entries() -> Result<(), Box<dyn Error>> {
+    let path = fixtures::write_temp("set-mtime.cpio", &fixtures::single());
+    let mut cmd = get_command();
+    cmd.args(["--set-mtime", "86400"]).arg(&path).arg("path/*");
+    cmd.output()?.assert_stderr("").assert_success();
+
+    let mut cmd = get_command();
+    cmd.args(["-t", "--format", "{mtime} {name}"]).arg(&path);
+    let output = cmd.output()?;
+    std::fs::remove_file(path)?;
+    output
+        .assert_success()
+        .assert_stdout("1713104326 .\n1713104326 path\n86400 path/file\n");
+    Ok(())
+}
+
+#[test]
+fn verify_checksums_entry() -> Result<(), Box<dyn Error>> {
+    let entries = [
+        Entry::dir("."),
+        Entry::file(
+            ".checksums",
+            b"434728a410a78f56fc1b5899c3593436e61ab0c731e9072d95e96db290205e53  ./file\n\
+              434728a410a78f56fc1b5899c3593436e61ab0c731e9072d95e96db290205e53  other\n",
+        ),
+        Entry::file("file", b"content\n"),
+        Entry::file("other", b"tampered\n"),
+    ];
+    let path = fixtures::write_temp("verify-checksums.cpio", &fixtures::cpio(&entries));
+    let mut cmd = get_command();
+    cmd.arg("--verify").arg("-v").arg(&path);
+
+    let output = cmd.output()?;
+    std::fs::remove_file(path)?;
+    output
+        .assert_failure(1)
+        .assert_stderr_contains("1 of 2 checksums did not match.")
+        .assert_stdout("./file: OK\nother: FAILED (SHA-256 hash mismatch)\n");
+    Ok(())
+}
+
+#[test]
+fn verify_crc_cpio() -> Result<(), Box<dyn Error>> {
+    let entries = [Entry::dir("."), Entry::file("file", b"content\n")];
+    let path = fixtures::write_temp("verify.cpio", &cpio_with_format(&entries, Format::Crc));
+    let mut cmd = get_command();
+    cmd.arg("--verify").arg("-v").arg(&path);
+
+    let output = cmd.output()?;
+    std::fs::remove_file(path)?;
+    output
+        .assert_stderr("")
+        .assert_success()
+        .assert_stdout("file: OK\n");
+    Ok(())
+}
+
+#[test]
+fn verify_crc_cpio_mismatch() -> Result<(), Box<dyn Error>> {
+    let entries = [Entry::dir("."), Entry::file("file", b"content\n")];
+    let mut archive = cpio_with_format(&entries, Format::Crc);
+    let position = archive.windows(7).position(|w| w == b"content").unwrap();
+    archive[position] = b'C';
+    let path = fixtures::write_temp("verify-mismatch.cpio", &archive);
+    let mut cmd = get_command();
+    cmd.arg("--verify").arg(&path);
+
+    let output = cmd.output()?;
+    std::fs::remove_file(path)?;
+    output
+        .assert_failure(1)
+        .assert_stderr_contains("1 of 1 checksums did not match.")
+        .assert_stdout(
+            "file: FAILED (Checksum mismatch for 'file': expected 00000305, got 000002E5.)\n",
+        );
+    Ok(())
+}
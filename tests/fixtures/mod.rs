@@ -0,0 +1,325 @@
+// Copyright (C) 2024, Benjamin Drung <bdrung@posteo.de>
+// SPDX-License-Identifier: ISC
+
+//! Generate cpio archives for the tests from declarative specs
+//!
+//! The archives are built in memory (and compressed with the external
+//! compression programs) so that new test cases do not require committing
+//! opaque binary files. The generated archives match the output of
+//! `cpio --reproducible -o -H newc` (see tests/generate).
+
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::sample::Index;
+use threecpio::Header;
+
+/// Modification time of all generated entries (SOURCE_DATE_EPOCH of tests/generate)
+const MTIME: u32 = 1713104326;
+const UID: u32 = 1000;
+const GID: u32 = 1000;
+const BLOCK_SIZE: usize = 512;
+
+/// Entry of a generated cpio archive
+#[derive(Debug)]
+pub struct Entry {
+    name: String,
+    /// Inode number (assigned in order if not set)
+    ino: Option<u32>,
+    mode: u32,
+    nlink: u32,
+    data: Vec<u8>,
+}
+
+impl Entry {
+    pub fn dir(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            ino: None,
+            mode: 0o040_775,
+            nlink: 2,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn file(name: &str, data: &[u8]) -> Self {
+        Self {
+            name: name.into(),
+            ino: None,
+            mode: 0o100_664,
+            nlink: 1,
+            data: data.into(),
+        }
+    }
+
+    pub fn symlink(name: &str, target: &str) -> Self {
+        Self {
+            name: name.into(),
+            ino: None,
+            mode: 0o120_777,
+            nlink: 1,
+            data: target.as_bytes().into(),
+        }
+    }
+
+    /// Hard-linked files sharing the inode `ino`. Like GNU cpio, only the
+    /// last link carries the data.
+    pub fn hard_links(names: &[String], ino: u32, data: &[u8]) -> Vec<Self> {
+        let nlink = names.len().try_into().unwrap();
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Self {
+                name: name.clone(),
+                ino: Some(ino),
+                mode: 0o100_664,
+                nlink,
+                data: if i + 1 == names.len() {
+                    data.into()
+                } else {
+                    Vec::new()
+                },
+            })
+            .collect()
+    }
+}
+
+fn pad_to_4_bytes(data: &mut Vec<u8>) {
+    data.resize(data.len().next_multiple_of(4), 0);
+}
+
+/// Format of the generated cpio archive
+#[derive(Clone, Copy)]
+pub enum Format {
+    /// New ASCII format (070701)
+    Newc,
+    /// New ASCII format with checksums (070702)
+    Crc,
+}
+
+fn write_object(
+    cpio: &mut Vec<u8>,
+    format: Format,
+    ino: u32,
+    mode: u32,
+    nlink: u32,
+    name: &str,
+    data: &[u8],
+) {
+    let mtime = if mode == 0 { 0 } else { MTIME };
+    let (uid, gid) = if mode == 0 { (0, 0) } else { (UID, GID) };
+    let filesize = data.len().try_into().unwrap();
+    let mut header = Header::new(ino, mode, uid, gid, nlink, mtime, filesize, name.into());
+    header.check = match format {
+        Format::Newc => None,
+        Format::Crc if mode & 0o170_000 == 0o100_000 => Some(
+            data.iter()
+                .fold(0u32, |sum, b| sum.wrapping_add((*b).into())),
+        ),
+        Format::Crc => Some(0),
+    };
+    header.write(cpio, 0, None).unwrap();
+    cpio.extend_from_slice(data);
+    pad_to_4_bytes(cpio);
+}
+
+/// Build an uncompressed cpio archive in the newc format.
+pub fn cpio(entries: &[Entry]) -> Vec<u8> {
+    cpio_with_format(entries, Format::Newc)
+}
+
+/// Build an uncompressed cpio archive in the given format.
+///
+/// The inode numbers are assigned in order and the archive is padded to
+/// a multiple of 512 bytes like GNU cpio does.
+pub fn cpio_with_format(entries: &[Entry], format: Format) -> Vec<u8> {
+    let mut cpio = Vec::new();
+    for (ino, entry) in entries.iter().enumerate() {
+        let ino = entry.ino.unwrap_or(u32::try_from(ino).unwrap());
+        write_object(
+            &mut cpio,
+            format,
+            ino,
+            entry.mode,
+            entry.nlink,
+            &entry.name,
+            &entry.data,
+        );
+    }
+    write_object(&mut cpio, format, 0, 0, 1, "TRAILER!!!", b"");
+    cpio.resize(cpio.len().next_multiple_of(BLOCK_SIZE), 0);
+    cpio
+}
+
+/// Compress the data with the given compression command.
+pub fn compress(command: &[&str], data: &[u8]) -> Vec<u8> {
+    let mut child = Command::new(command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|e| panic!("Failed to run {}: {}", command[0], e));
+    let mut stdin = child.stdin.take().unwrap();
+    let data = data.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&data));
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap().unwrap();
+    assert!(output.status.success(), "{} failed", command[0]);
+    output.stdout
+}
+
+/// Write the archive into a temporary file and return its path.
+pub fn write_temp(name: &str, data: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("3cpio-{}-{}", std::process::id(), name));
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+/// Same content as tests/single.cpio
+pub fn single() -> Vec<u8> {
+    cpio(&[
+        Entry::dir("."),
+        Entry::dir("path"),
+        Entry::file("path/file", b"content\n"),
+    ])
+}
+
+/// Second cpio archive of the compressed test archives (e.g. tests/gzip.cpio)
+pub fn shell() -> Vec<u8> {
+    cpio(&[
+        Entry::dir("."),
+        Entry::dir("usr"),
+        Entry::dir("usr/bin"),
+        Entry::file(
+            "usr/bin/sh",
+            b"This is a fake busybox binary to simulate a POSIX shell\n",
+        ),
+    ])
+}
+
+/// Archive with a file that is bigger than the default I/O buffer size
+pub fn bigdata() -> Vec<u8> {
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    cpio(&[Entry::dir("."), Entry::file("bigdata", &data)])
+}
+
+/// Archive with entries that point outside of the extraction directory
+pub fn path_traversal() -> Vec<u8> {
+    cpio(&[
+        Entry::dir("."),
+        Entry::file("../outside", b"escaped\n"),
+        Entry::symlink("escape", "../../etc"),
+        Entry::file("escape/passwd", b"overwritten\n"),
+    ])
+}
+
+/// Kind of a node of a generated tree
+#[derive(Clone, Debug)]
+enum Node {
+    Dir,
+    File(Vec<u8>),
+    /// Symlink with a target of the given depth
+    Symlink(usize),
+    /// Given number of hard-links sharing the data
+    HardLinks(usize, Vec<u8>),
+}
+
+fn node() -> impl Strategy<Value = Node> {
+    prop_oneof![
+        Just(Node::Dir),
+        prop_oneof![vec(any::<u8>(), 0..9), vec(any::<u8>(), 4096..4105)].prop_map(Node::File),
+        (0..3usize).prop_map(Node::Symlink),
+        (2..5usize, vec(any::<u8>(), 0..9)).prop_map(|(links, data)| Node::HardLinks(links, data)),
+    ]
+}
+
+/// Build the entries of a tree. Each node is placed in one of the
+/// directories created before it and gets 0 to 3 extra characters in its
+/// name.
+fn build_tree(nodes: &[(Index, usize, Node)]) -> Vec<Entry> {
+    let mut entries = vec![Entry::dir(".")];
+    let mut dirs = vec![String::new()];
+    for (i, (parent, extra, node)) in nodes.iter().enumerate() {
+        let name = format!("{}e{}{}", parent.get(&dirs), i, "x".repeat(*extra));
+        match node {
+            Node::Dir => {
+                entries.push(Entry::dir(&name));
+                dirs.push(format!("{}/", name));
+            }
+            Node::File(data) => entries.push(Entry::file(&name, data)),
+            Node::Symlink(depth) => {
+                let target = format!("target{}", "/t".repeat(*depth));
+                entries.push(Entry::symlink(&name, &target));
+            }
+            Node::HardLinks(links, data) => {
+                let names: Vec<String> = (0..*links)
+                    .map(|link| format!("{}_{}", name, link))
+                    .collect();
+                // Use inode numbers that are not assigned in order.
+                let ino = 100_000 + u32::try_from(i).unwrap();
+                entries.extend(Entry::hard_links(&names, ino, data));
+            }
+        }
+    }
+    entries
+}
+
+/// Generate random trees of directories, files, symlinks, and hard-links.
+///
+/// The name lengths and file sizes vary around the 4-byte alignment of the
+/// headers and the data to cover all padding lengths. Failing trees shrink
+/// to fewer and smaller entries.
+pub fn random_tree() -> impl Strategy<Value = Vec<Entry>> {
+    vec((any::<Index>(), 0..4usize, node()), 1..=40).prop_map(|nodes| build_tree(&nodes))
+}
+
+/// Return the names of the entries (as listed by `3cpio -t`).
+pub fn names(entries: &[Entry]) -> String {
+    entries.iter().map(|e| format!("{}\n", e.name)).collect()
+}
+
+/// Assert that the entries were extracted correctly into the directory.
+///
+/// With `metadata_only`, the regular files are expected to be empty.
+pub fn assert_extracted(entries: &[Entry], directory: &Path, metadata_only: bool) {
+    for entry in entries {
+        let path = directory.join(&entry.name);
+        let metadata = std::fs::symlink_metadata(&path)
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        assert_eq!(
+            metadata.mode() & 0o170_000,
+            entry.mode & 0o170_000,
+            "{}",
+            entry.name
+        );
+        match entry.mode & 0o170_000 {
+            0o100_000 if entry.ino.is_some() => {
+                // Compare each hard-link with the link carrying the data.
+                let last = entries.iter().rfind(|e| e.ino == entry.ino).unwrap();
+                let last_path = directory.join(&last.name);
+                let data = if metadata_only { &[] } else { &last.data[..] };
+                assert_eq!(std::fs::read(&path).unwrap(), data, "{}", entry.name);
+                assert_eq!(metadata.ino(), std::fs::metadata(last_path).unwrap().ino());
+                assert_eq!(metadata.nlink(), u64::from(entry.nlink), "{}", entry.name);
+            }
+            0o100_000 => {
+                let data = if metadata_only { &[] } else { &entry.data[..] };
+                assert_eq!(std::fs::read(&path).unwrap(), data, "{}", entry.name);
+            }
+            0o120_000 => assert_eq!(
+                std::fs::read_link(&path)
+                    .unwrap()
+                    .as_os_str()
+                    .as_encoded_bytes(),
+                entry.data,
+                "{}",
+                entry.name
+            ),
+            _ => {}
+        }
+    }
+}